@@ -0,0 +1,125 @@
+//! The about window, and the diagnostics we ask for in bug reports.
+use druid::{
+    commands::{SHOW_OPEN_PANEL, SHOW_SAVE_PANEL},
+    widget::{Button, Flex, Label},
+    Application, Data, FileDialogOptions, FileSpec, Selector, Widget, WidgetExt, WindowDesc,
+};
+use std::{env, fmt::Write};
+
+use crate::config::{EXPORT_SETTINGS, IMPORT_SETTINGS};
+
+/// Open the about window.
+pub const SHOW_ABOUT: Selector = Selector::new("image-viewer.show-about");
+
+/// Exported settings, which are TOML.
+const SETTINGS_FILE: FileSpec = FileSpec::new("Settings", &["toml"]);
+
+/// Environment variables that change how we behave, and so are worth including in bug reports.
+const INTERESTING_VARS: &[&str] = &[
+    "RUST_LOG",
+    "GTK_USE_PORTAL",
+    "SNAP",
+    "WAYLAND_DISPLAY",
+    "DISPLAY",
+    "XDG_CURRENT_DESKTOP",
+];
+
+pub fn window<T: Data>() -> WindowDesc<T> {
+    WindowDesc::new(ui_builder())
+        .title("About Image Viewer")
+        .window_size((420., 360.))
+        .resizable(false)
+}
+
+fn ui_builder<T: Data>() -> impl Widget<T> {
+    Flex::column()
+        .with_child(
+            Label::new(format!("Image Viewer {}", env!("CARGO_PKG_VERSION"))).with_text_size(20.),
+        )
+        .with_spacer(8.)
+        .with_child(Label::new("A simple image viewer"))
+        .with_spacer(8.)
+        .with_child(Label::new(diagnostics()).with_text_size(12.))
+        .with_spacer(8.)
+        .with_child(Button::new("Copy diagnostics").on_click(|_, _, _| {
+            Application::global().clipboard().put_string(diagnostics());
+        }))
+        .with_spacer(8.)
+        .with_child(
+            Flex::row()
+                .with_child(Button::new("Export settings…").on_click(|ctx, _, _| {
+                    ctx.submit_command(
+                        SHOW_SAVE_PANEL.with(
+                            FileDialogOptions::new()
+                                .allowed_types(vec![SETTINGS_FILE])
+                                .default_name("image-viewer-settings.toml")
+                                .accept_command(EXPORT_SETTINGS),
+                        ),
+                    );
+                }))
+                .with_spacer(8.)
+                .with_child(Button::new("Import settings…").on_click(|ctx, _, _| {
+                    ctx.submit_command(
+                        SHOW_OPEN_PANEL.with(
+                            FileDialogOptions::new()
+                                .allowed_types(vec![SETTINGS_FILE])
+                                .accept_command(IMPORT_SETTINGS),
+                        ),
+                    );
+                })),
+        )
+        .padding(10.)
+}
+
+/// A plain text summary of the build and the environment we're running in.
+fn diagnostics() -> String {
+    let mut out = String::new();
+    // Writing to a `String` can't fail.
+    let _ = writeln!(out, "version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(out, "os: {} ({})", env::consts::OS, env::consts::ARCH);
+    let _ = writeln!(out, "render backend: {}", backend());
+    let _ = writeln!(out, "features: {}", features().join(", "));
+    let _ = writeln!(out, "sandboxed: {}", crate::shell::is_sandboxed());
+    for var in INTERESTING_VARS {
+        if let Some(val) = env::var_os(var) {
+            let _ = writeln!(out, "{}={}", var, val.to_string_lossy());
+        }
+    }
+    out
+}
+
+/// The piet backend druid was built with. Piet doesn't tell us anything about the GPU, so this is
+/// as specific as we can be.
+fn backend() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "direct2d"
+    } else if cfg!(target_os = "macos") {
+        "coregraphics"
+    } else if cfg!(target_arch = "wasm32") {
+        "web canvas"
+    } else {
+        "cairo"
+    }
+}
+
+/// Each optional feature in Cargo.toml, and whether it was built in.
+const FEATURES: &[(&str, bool)] = &[
+    ("avif", cfg!(feature = "avif")),
+    ("heif", cfg!(feature = "heif")),
+    ("dicom", cfg!(feature = "dicom")),
+    ("fits", cfg!(feature = "fits")),
+    ("raw-demosaic", cfg!(feature = "raw-demosaic")),
+    ("simd", cfg!(feature = "simd")),
+];
+
+fn features() -> Vec<&'static str> {
+    let mut features: Vec<_> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    if features.is_empty() {
+        features.push("none");
+    }
+    features
+}
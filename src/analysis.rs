@@ -0,0 +1,202 @@
+use druid::{piet::ImageFormat, Data, ImageBuf, Rect};
+use std::fmt;
+
+use crate::pixel_ops;
+
+/// The longest side of the sample grid we compute statistics on. Working on a small grid keeps
+/// this cheap enough to run for every image we load.
+const SAMPLE_SIZE: usize = 256;
+/// Luma values at or above this are considered blown out.
+const CLIP_HIGH: u8 = 250;
+/// Luma values at or below this are considered crushed.
+const CLIP_LOW: u8 = 5;
+/// The fraction of clipped pixels above which we flag an image.
+const CLIP_FRACTION: f64 = 0.05;
+/// Variance of the laplacian below which we consider the image soft.
+const BLUR_THRESHOLD: f64 = 60.;
+/// The size of the region `salient_region` picks, as a fraction of each side of the image.
+const SUBJECT_FRACTION: f64 = 0.25;
+
+/// A luma histogram of a (downsampled) image.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bins: [u32; 256],
+    count: u32,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            bins: [0; 256],
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, luma: u8) {
+        self.bins[luma as usize] += 1;
+        self.count += 1;
+    }
+
+    /// The fraction of samples that fall in `range`.
+    fn fraction(&self, range: impl Iterator<Item = usize>) -> f64 {
+        if self.count == 0 {
+            return 0.;
+        }
+        let total: u32 = range.map(|idx| self.bins[idx]).sum();
+        total as f64 / self.count as f64
+    }
+}
+
+/// Things that might be wrong with a shot.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Data)]
+pub struct Exposure {
+    pub over: bool,
+    pub under: bool,
+    pub blurry: bool,
+}
+
+impl fmt::Display for Exposure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sep = "";
+        for (flag, label) in [
+            (self.over, "overexposed"),
+            (self.under, "underexposed"),
+            (self.blurry, "blurry"),
+        ] {
+            if flag {
+                write!(f, "{}{}", sep, label)?;
+                sep = ", ";
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compute the histogram and sharpness of `image`, and use them to guess whether the shot has
+/// problems.
+pub fn analyse(image: &ImageBuf) -> Exposure {
+    let (luma, width, height) = sample_luma(image);
+    let mut hist = Histogram::new();
+    for &px in luma.iter() {
+        hist.add(px);
+    }
+    Exposure {
+        over: hist.fraction(CLIP_HIGH as usize..256) > CLIP_FRACTION,
+        under: hist.fraction(0..=CLIP_LOW as usize) > CLIP_FRACTION,
+        blurry: laplacian_variance(&luma, width, height) < BLUR_THRESHOLD,
+    }
+}
+
+/// Find the most detailed part of `image`, which is usually the subject, and is the part in
+/// focus if anything is. Detail is measured as the size of the laplacian at each point, so edges
+/// and texture count and smooth areas like sky don't.
+///
+/// Returns a region `SUBJECT_FRACTION` of the size of the image, in image pixels. An image with no
+/// detail anywhere gets the middle.
+pub fn salient_region(image: &ImageBuf) -> Rect {
+    let (luma, width, height) = sample_luma(image);
+    let whole = Rect::new(0., 0., image.width() as f64, image.height() as f64);
+    if width < 3 || height < 3 {
+        return whole;
+    }
+    // A summed-area table of the detail, so the detail in any window takes 4 lookups.
+    let at = |x: usize, y: usize| luma[y * width + x] as f64;
+    let stride = width + 1;
+    let mut sums = vec![0.; stride * (height + 1)];
+    for y in 0..height {
+        let mut row = 0.;
+        for x in 0..width {
+            if x > 0 && y > 0 && x < width - 1 && y < height - 1 {
+                row += (at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4. * at(x, y))
+                    .abs();
+            }
+            sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+        }
+    }
+    let win_w = ((width as f64 * SUBJECT_FRACTION).round() as usize).max(1);
+    let win_h = ((height as f64 * SUBJECT_FRACTION).round() as usize).max(1);
+    let detail = |x: usize, y: usize| {
+        sums[(y + win_h) * stride + x + win_w]
+            - sums[y * stride + x + win_w]
+            - sums[(y + win_h) * stride + x]
+            + sums[y * stride + x]
+    };
+    // Start from the middle, so it wins any ties.
+    let mut best = ((width - win_w) / 2, (height - win_h) / 2);
+    let mut most = detail(best.0, best.1);
+    for y in 0..=height - win_h {
+        for x in 0..=width - win_w {
+            let here = detail(x, y);
+            if here > most {
+                best = (x, y);
+                most = here;
+            }
+        }
+    }
+    let (step_x, step_y) = (whole.width() / width as f64, whole.height() / height as f64);
+    Rect::new(
+        best.0 as f64 * step_x,
+        best.1 as f64 * step_y,
+        (best.0 + win_w) as f64 * step_x,
+        (best.1 + win_h) as f64 * step_y,
+    )
+}
+
+/// Sample the image on a grid at most `SAMPLE_SIZE` on a side, converting to luma.
+fn sample_luma(image: &ImageBuf) -> (Vec<u8>, usize, usize) {
+    let (src_w, src_h) = (image.width(), image.height());
+    let step = ((src_w.max(src_h) + SAMPLE_SIZE - 1) / SAMPLE_SIZE).max(1);
+    let (width, height) = (src_w / step, src_h / step);
+    let bpp = image.format().bytes_per_pixel();
+    let pixels = image.raw_pixels();
+
+    let mut samples = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let idx = (y * step * src_w + x * step) * bpp;
+            let px = &pixels[idx..idx + bpp];
+            samples.extend_from_slice(&match image.format() {
+                ImageFormat::Grayscale => [px[0], px[0], px[0], 0xff],
+                ImageFormat::Rgb => [px[0], px[1], px[2], 0xff],
+                _ => [px[0], px[1], px[2], px[3]],
+            });
+        }
+    }
+    // Grey pixels come out as they went in, since the weights add up to 1.
+    let red = pixel_ops::extract_channel(&samples, 0);
+    let green = pixel_ops::extract_channel(&samples, 1);
+    let blue = pixel_ops::extract_channel(&samples, 2);
+    let out = red
+        .iter()
+        .zip(&green)
+        .zip(&blue)
+        .map(|((&r, &g), &b)| luma(r, g, b))
+        .collect();
+    (out, width, height)
+}
+
+/// Rec. 601 luma, in integer arithmetic.
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+/// The variance of the laplacian is a cheap and well-known measure of focus: sharp images have
+/// lots of strong edges, and so a high variance.
+fn laplacian_variance(luma: &[u8], width: usize, height: usize) -> f64 {
+    if width < 3 || height < 3 {
+        // Too small to say anything useful, so assume it's fine.
+        return f64::INFINITY;
+    }
+    let at = |x: usize, y: usize| luma[y * width + x] as f64;
+    let (mut sum, mut sum_sq, mut n) = (0., 0., 0.);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let lap = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4. * at(x, y);
+            sum += lap;
+            sum_sq += lap * lap;
+            n += 1.;
+        }
+    }
+    let mean = sum / n;
+    sum_sq / n - mean * mean
+}
@@ -0,0 +1,144 @@
+//! Blinking: showing two or more images in turn, quickly and lined up, so whatever changed
+//! between them jumps out. Astronomers use this to find things that move between exposures, and
+//! it works as well for spotting a difference between two renders of the same page.
+//!
+//! Every image is shown with the same zoom and position, so the user can zoom in on one part and
+//! watch just that.
+use druid::{
+    widget::{prelude::*, Button, CrossAxisAlignment, Flex, Label, Slider, ViewSwitcher},
+    Color, Data, Lens, Selector, WidgetExt,
+};
+use std::{path::Path, sync::Arc, time::Duration};
+
+use crate::versions::COMPARE_WITH_PREVIOUS;
+
+/// Add the current image to the ones to blink between.
+pub const ADD_TO_BLINK: Selector = Selector::new("image-viewer.add-to-blink");
+/// Start or stop blinking.
+pub const TOGGLE_BLINKING: Selector = Selector::new("image-viewer.toggle-blinking");
+/// Show the next image to blink. Sent on a timer while blinking.
+pub const BLINK_STEP: Selector = Selector::new("image-viewer.blink-step");
+
+/// The slowest and fastest rates, in images per second.
+const RATE_RANGE: (f64, f64) = (0.5, 10.);
+
+#[derive(Debug, Clone, Data, Lens)]
+pub struct Blink {
+    /// The images to show in turn, in the order they were added.
+    pub frames: Arc<Vec<Arc<Path>>>,
+    /// The index in `frames` of the image being shown.
+    pub current: usize,
+    /// How many images to show a second.
+    pub rate: f64,
+    pub playing: bool,
+    /// Whether the panel is open.
+    pub show: bool,
+}
+
+impl Default for Blink {
+    fn default() -> Self {
+        Self {
+            frames: Arc::new(vec![]),
+            current: 0,
+            rate: 2.,
+            playing: false,
+            show: false,
+        }
+    }
+}
+
+impl Blink {
+    /// Add `path` to the end, unless it is already there.
+    pub fn add(&mut self, path: &Path) {
+        if !self.frames.iter().any(|frame| &**frame == path) {
+            Arc::make_mut(&mut self.frames).push(path.into());
+        }
+    }
+
+    /// Whether there is anything to blink between.
+    pub fn can_play(&self) -> bool {
+        self.frames.len() >= 2
+    }
+
+    /// How long to show each image.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1. / self.rate.max(RATE_RANGE.0))
+    }
+
+    /// Move on to the next image, and return it.
+    pub fn step(&mut self) -> Option<Arc<Path>> {
+        if !self.can_play() {
+            return None;
+        }
+        self.current = (self.current + 1) % self.frames.len();
+        Some(self.frames[self.current].clone())
+    }
+}
+
+/// The images to blink, and the controls for blinking them.
+pub fn panel() -> impl Widget<Blink> {
+    let frames = ViewSwitcher::new(
+        |data: &Blink, _| (data.frames.clone(), data.current),
+        |(frames, current), _, _| {
+            let mut column = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+            if frames.len() < 2 {
+                column.add_child(
+                    Label::new("Add two or more images to blink between them")
+                        .with_text_color(Color::grey8(0xa0)),
+                );
+            }
+            for (idx, frame) in frames.iter().enumerate() {
+                let name = frame.file_name().unwrap_or(frame.as_os_str());
+                let label = Label::new(name.to_string_lossy().into_owned());
+                column.add_child(if idx == *current {
+                    label.with_text_color(Color::rgb8(0xff, 0xa0, 0x00))
+                } else {
+                    label
+                });
+            }
+            Box::new(column)
+        },
+    );
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Blink"))
+                .with_flex_spacer(1.)
+                .with_child(
+                    Label::dynamic(|data: &Blink, _| format!("{:.1} per second", data.rate))
+                        .with_text_size(11.)
+                        .with_text_color(Color::grey8(0xa0)),
+                )
+                .with_child(
+                    Slider::new()
+                        .with_range(RATE_RANGE.0, RATE_RANGE.1)
+                        .lens(Blink::rate),
+                )
+                .with_spacer(4.)
+                .with_child(Button::new("Add current").on_click(|ctx, _, _| {
+                    ctx.submit_command(ADD_TO_BLINK);
+                }))
+                .with_child(Button::new("Previous version").on_click(|ctx, _, _| {
+                    ctx.submit_command(COMPARE_WITH_PREVIOUS);
+                }))
+                .with_child(
+                    Button::dynamic(|data: &Blink, _| {
+                        let label = if data.playing { "Stop" } else { "Play" };
+                        label.to_string()
+                    })
+                    .on_click(|ctx, _, _| ctx.submit_command(TOGGLE_BLINKING)),
+                )
+                .with_child(Button::new("Clear").on_click(|ctx, data: &mut Blink, _| {
+                    if data.playing {
+                        ctx.submit_command(TOGGLE_BLINKING);
+                    }
+                    data.frames = Arc::new(vec![]);
+                    data.current = 0;
+                }))
+                .with_child(Button::new("×").on_click(|_, data: &mut Blink, _| data.show = false)),
+        )
+        .with_spacer(4.)
+        .with_child(frames)
+        .padding(4.)
+}
@@ -0,0 +1,82 @@
+//! Keeping recently viewed images around, so going back to them doesn't mean decoding them again.
+use std::{path::Path, time::SystemTime};
+
+use crate::{analysis::Exposure, integrity::Integrity, widgets::ViewerState};
+
+/// A least recently used cache with a memory budget.
+///
+/// Entries report their own size when they are inserted. The newest entry is always kept, even if
+/// it is over budget on its own.
+pub struct ImageCache<K, V> {
+    /// Least recently used first.
+    entries: Vec<Entry<K, V>>,
+    budget: usize,
+    used: usize,
+}
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    bytes: usize,
+}
+
+impl<K: PartialEq, V> ImageCache<K, V> {
+    /// Create a cache holding up to `budget` bytes.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: vec![],
+            budget,
+            used: 0,
+        }
+    }
+
+    /// Get the entry for `key`, marking it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let idx = self.entries.iter().position(|e| e.key == *key)?;
+        let entry = self.entries.remove(idx);
+        self.entries.push(entry);
+        self.entries.last().map(|e| &e.value)
+    }
+
+    /// Take the entry for `key` out of the cache.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let idx = self.entries.iter().position(|e| e.key == *key)?;
+        let entry = self.entries.remove(idx);
+        self.used -= entry.bytes;
+        Some(entry.value)
+    }
+
+    /// Add an entry taking up `bytes`, replacing any existing entry for `key`, and evict the least
+    /// recently used entries until we are back under budget.
+    pub fn insert(&mut self, key: K, value: V, bytes: usize) {
+        self.remove(&key);
+        self.entries.push(Entry { key, value, bytes });
+        self.used += bytes;
+        while self.used > self.budget && self.entries.len() > 1 {
+            let evicted = self.entries.remove(0);
+            self.used -= evicted.bytes;
+        }
+    }
+}
+
+/// An image as it was shown, ready to show again.
+pub struct CachedImage {
+    pub viewer: ViewerState,
+    pub exposure: Exposure,
+    pub integrity: Integrity,
+    /// When the file was last modified when we loaded it, so we can tell if it has changed.
+    pub modified: Option<SystemTime>,
+}
+
+impl CachedImage {
+    /// Whether the file at `path` is still the one we loaded.
+    pub fn is_fresh(&self, path: &Path) -> bool {
+        let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+        modified.is_some() && modified == self.modified
+    }
+
+    /// Roughly how much memory the decoded image takes.
+    pub fn bytes(&self) -> usize {
+        self.viewer.bytes()
+    }
+}
@@ -0,0 +1,166 @@
+//! The command line arguments that need more than clap does for us.
+use druid::Color;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{library, profile::InitialZoom};
+
+/// The images named on the command line: files as they are, the images in directories, and the
+/// images matching `*` and `?` in the last part of a path. Shells on Unix expand wildcards before
+/// we see them, but the Windows command prompt doesn't.
+pub fn expand_paths(args: &[PathBuf]) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    for arg in args {
+        let found = if arg.is_dir() {
+            images_in(arg, |_| true)
+        } else if let Some(pattern) = wildcard(arg) {
+            let dir = arg.parent().unwrap_or_else(|| Path::new(""));
+            images_in(dir, |name| matches(&pattern, name))
+        } else {
+            paths.push(arg.clone());
+            continue;
+        };
+        match found {
+            Ok(found) if found.is_empty() => log::warn!("no images found at {}", arg.display()),
+            Ok(mut found) => paths.append(&mut found),
+            Err(e) => log::error!("could not list {}: {}", arg.display(), e),
+        }
+    }
+    paths
+}
+
+/// The file name of `path`, if it has wildcards in it.
+fn wildcard(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    if name.contains(|c| c == '*' || c == '?') && !path.exists() {
+        Some(name.to_owned())
+    } else {
+        None
+    }
+}
+
+/// The images in `dir` whose names pass `filter`, sorted.
+fn images_in(dir: &Path, filter: impl Fn(&str) -> bool) -> io::Result<Vec<PathBuf>> {
+    let read_from = if dir == Path::new("") {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let mut paths = vec![];
+    for entry in fs::read_dir(read_from)? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let name = entry.file_name();
+        if entry.file_type()?.is_file()
+            && library::is_image(&path)
+            && name.to_str().map_or(false, &filter)
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters and `?` any one.
+/// Case is ignored on Windows, as it is by the file system.
+fn matches(pattern: &str, name: &str) -> bool {
+    let same = |a: char, b: char| {
+        if cfg!(windows) {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to go back to if what follows the last `*` doesn't match: the pattern just after it,
+    // and the next place in the name to try it.
+    let mut retry = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                retry = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || same(c, name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match retry {
+                Some((after_star, tried)) => {
+                    p = after_star;
+                    n = tried + 1;
+                    retry = Some((after_star, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Parse a time like `5s`, `500ms` or `2m`. A bare number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, "s"),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a time like 5s, got {:?}", s))?;
+    let secs = match unit {
+        "ms" => number / 1000.,
+        "s" => number,
+        "m" => number * 60.,
+        _ => return Err(format!("unknown unit {:?}, expected ms, s or m", unit)),
+    };
+    if secs > 0. && secs.is_finite() {
+        Ok(Duration::from_secs_f64(secs))
+    } else {
+        Err(format!("{:?} isn't a positive time", s))
+    }
+}
+
+/// Parse a colour: `black`, `grey` (or `gray`), `white`, or hex like `#1e1e1e`.
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    match s.trim() {
+        "black" => Ok(Color::BLACK),
+        "grey" | "gray" => Ok(Color::grey8(0x80)),
+        "white" => Ok(Color::WHITE),
+        hex => Color::from_hex_str(hex).map_err(|_| {
+            format!(
+                "expected black, grey, white or a colour like #1e1e1e, got {:?}",
+                s
+            )
+        }),
+    }
+}
+
+/// Parse how to zoom images when they are opened: `fit`, `fit-whole`, `fit-width`, or a scale
+/// like `100%` or `2`.
+pub fn parse_scale(s: &str) -> Result<InitialZoom, String> {
+    let scale = match s.trim() {
+        "fit" => return Ok(InitialZoom::Fit),
+        "fit-whole" => return Ok(InitialZoom::FitWhole),
+        "fit-width" => return Ok(InitialZoom::FitWidth),
+        percent if percent.ends_with('%') => percent[..percent.len() - 1]
+            .trim()
+            .parse::<f64>()
+            .map(|percent| percent / 100.),
+        scale => scale.parse::<f64>(),
+    };
+    match scale {
+        Ok(scale) if scale > 0. && scale.is_finite() => Ok(InitialZoom::Scale(scale)),
+        _ => Err(format!(
+            "expected fit, fit-whole, fit-width or a scale like 100%, got {:?}",
+            s
+        )),
+    }
+}
@@ -0,0 +1,154 @@
+//! Copying the current image to the system clipboard, and pasting images from it.
+//!
+//! We offer the pixels as a PNG, which is what other applications look for when pasting an image,
+//! and, if the image is as it was loaded, the file it came from, so file managers paste the file.
+//!
+//! Pasting looks for the same two formats: a PNG is shown as an unsaved image, and a file is
+//! opened. Windows screenshots are only on the clipboard as a bitmap, which we can't read yet.
+use druid::{Application, ClipboardFormat, Selector};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::library;
+
+/// Copy the current image, as it is shown, to the clipboard.
+pub const COPY_TO_CLIPBOARD: Selector = Selector::new("image-viewer.copy-to-clipboard");
+/// Show the image on the clipboard.
+pub const PASTE_FROM_CLIPBOARD: Selector = Selector::new("image-viewer.paste-from-clipboard");
+/// Copy the path of the current image to the clipboard, as text.
+pub const COPY_PATH: Selector = Selector::new("image-viewer.copy-path");
+/// Copy the current image's file to the clipboard, so it can be pasted in a file manager.
+pub const COPY_FILE: Selector = Selector::new("image-viewer.copy-file");
+
+#[cfg(target_os = "macos")]
+const PNG: &str = "public.png";
+#[cfg(target_os = "windows")]
+const PNG: &str = "PNG";
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const PNG: &str = "image/png";
+
+/// Windows has no named format for files (Explorer uses `CF_HDROP`), so there we only offer the
+/// path as text.
+#[cfg(target_os = "macos")]
+const FILE_URL: Option<&str> = Some("public.file-url");
+#[cfg(target_os = "windows")]
+const FILE_URL: Option<&str> = None;
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const FILE_URL: Option<&str> = Some("text/uri-list");
+
+/// An image encoded for the clipboard on the io thread.
+pub struct ClipboardImage {
+    pub png: Vec<u8>,
+    /// The file the image is a copy of, if it hasn't been changed since it was loaded.
+    pub path: Option<PathBuf>,
+}
+
+impl ClipboardImage {
+    /// Put the image on the clipboard, replacing whatever was there.
+    pub fn put(self) {
+        let mut formats = vec![ClipboardFormat::new(PNG, self.png)];
+        if let Some(path) = &self.path {
+            formats.extend(file_formats(path));
+        }
+        Application::global().clipboard().put_formats(&formats);
+    }
+}
+
+/// Put the file at `path` on the clipboard, replacing whatever was there.
+pub fn put_file(path: &Path) {
+    Application::global()
+        .clipboard()
+        .put_formats(&file_formats(path));
+}
+
+/// Put `path` on the clipboard as text, replacing whatever was there.
+pub fn put_path(path: &Path) {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    Application::global()
+        .clipboard()
+        .put_string(path.to_string_lossy());
+}
+
+/// The formats that offer the file at `path`: its URL where the platform has a format for that,
+/// and its path as text.
+fn file_formats(path: &Path) -> Vec<ClipboardFormat> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let mut formats = vec![];
+    if let Some(format) = FILE_URL {
+        formats.push(ClipboardFormat::new(format, file_url(&path)));
+    }
+    formats.push(ClipboardFormat::new(
+        ClipboardFormat::TEXT,
+        path.to_string_lossy().into_owned(),
+    ));
+    formats
+}
+
+/// An image found on the clipboard.
+pub enum Pasted {
+    /// Encoded image data, to be decoded on the io thread.
+    Image(Vec<u8>),
+    /// An image file that was copied, for example in a file manager.
+    File(PathBuf),
+}
+
+/// What is on the clipboard, if it is an image or an image file.
+pub fn get() -> Option<Pasted> {
+    let clipboard = Application::global().clipboard();
+    if let Some(png) = clipboard.get_format(PNG) {
+        return Some(Pasted::Image(png));
+    }
+    let urls = FILE_URL.and_then(|format| clipboard.get_format(format));
+    let path = match urls {
+        // A list of URLs, one per line, of which we show the first.
+        Some(urls) => String::from_utf8_lossy(&urls)
+            .lines()
+            .find_map(path_from_url)?,
+        None => PathBuf::from(clipboard.get_string()?.trim()),
+    };
+    if path.is_file() && library::is_image(&path) {
+        Some(Pasted::File(path))
+    } else {
+        None
+    }
+}
+
+/// The `file://` URL for the absolute `path`.
+pub fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for &byte in path.to_string_lossy().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                url.push(byte as char)
+            }
+            b'\\' => url.push('/'),
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+/// The path of a `file://` URL, the other way from `file_url`.
+fn path_from_url(url: &str) -> Option<PathBuf> {
+    let encoded = url.trim().strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut idx = 0;
+    while idx < encoded.len() {
+        let hex = encoded
+            .get(idx + 1..idx + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (encoded[idx], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                idx += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(decoded).ok()?))
+}
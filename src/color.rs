@@ -0,0 +1,206 @@
+//! Embedded colour profiles, and converting images to sRGB for display.
+//!
+//! We assume the display is sRGB. Images tagged with another colour space (often Adobe RGB from
+//! cameras, or Display P3 from phones) look washed out if shown without conversion.
+use druid::{piet::ImageFormat, ArcStr, Data, ImageBuf};
+use lcms2::{InfoType, Intent, Locale, PixelFormat, Profile, Transform};
+use std::{convert::TryInto, fmt, fs, io, path::Path};
+
+/// The colour space an image says it is in.
+#[derive(Debug, Clone, PartialEq, Data)]
+pub enum ColorSpace {
+    /// No profile, which by convention means sRGB.
+    Untagged,
+    Srgb,
+    AdobeRgb,
+    DisplayP3,
+    /// Some other profile, with its description.
+    Other(ArcStr),
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Untagged
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorSpace::Untagged => f.write_str("untagged"),
+            ColorSpace::Srgb => f.write_str("sRGB"),
+            ColorSpace::AdobeRgb => f.write_str("Adobe RGB"),
+            ColorSpace::DisplayP3 => f.write_str("Display P3"),
+            ColorSpace::Other(desc) => f.write_str(desc),
+        }
+    }
+}
+
+/// Find the profile embedded in the image at `path`, and convert `image` to sRGB with it.
+///
+/// Returns the colour space, and the converted image if it needed converting.
+pub fn to_srgb(path: &Path, image: &ImageBuf) -> (ColorSpace, Option<ImageBuf>) {
+    let icc = match embedded_profile(path) {
+        Ok(Some(icc)) => icc,
+        Ok(None) => return (ColorSpace::Untagged, None),
+        Err(e) => {
+            log::error!(
+                "could not read colour profile from {}: {}",
+                path.display(),
+                e
+            );
+            return (ColorSpace::Untagged, None);
+        }
+    };
+    let profile = match Profile::new_icc(&icc) {
+        Ok(profile) => profile,
+        Err(e) => {
+            log::error!("invalid colour profile in {}: {}", path.display(), e);
+            return (ColorSpace::Untagged, None);
+        }
+    };
+    let space = classify(&profile);
+    if space == ColorSpace::Srgb {
+        return (space, None);
+    }
+    let converted = convert(image, &profile);
+    (space, converted)
+}
+
+fn classify(profile: &Profile) -> ColorSpace {
+    let desc = profile
+        .info(InfoType::Description, Locale::none())
+        .unwrap_or_default();
+    let lower = desc.to_lowercase();
+    if lower.contains("srgb") {
+        ColorSpace::Srgb
+    } else if lower.contains("adobe rgb") {
+        ColorSpace::AdobeRgb
+    } else if lower.contains("p3") {
+        ColorSpace::DisplayP3
+    } else {
+        ColorSpace::Other(desc.trim().into())
+    }
+}
+
+/// Convert 8-bit RGB(A) images from `profile` to sRGB. Alpha is passed through.
+fn convert(image: &ImageBuf, profile: &Profile) -> Option<ImageBuf> {
+    let src = image.raw_pixels();
+    let pixels = match image.format() {
+        ImageFormat::Rgb => convert_pixels::<3>(src, profile, PixelFormat::RGB_8)?,
+        ImageFormat::RgbaSeparate => convert_pixels::<4>(src, profile, PixelFormat::RGBA_8)?,
+        // Greyscale profiles are rare, and premultiplied alpha would need unpremultiplying.
+        _ => return None,
+    };
+    Some(ImageBuf::from_raw(
+        pixels,
+        image.format(),
+        image.width(),
+        image.height(),
+    ))
+}
+
+fn convert_pixels<const N: usize>(
+    src: &[u8],
+    profile: &Profile,
+    format: PixelFormat,
+) -> Option<Vec<u8>> {
+    let srgb = Profile::new_srgb();
+    let transform: Transform<[u8; N], [u8; N]> =
+        match Transform::new(profile, format, &srgb, format, Intent::Perceptual) {
+            Ok(transform) => transform,
+            Err(e) => {
+                log::error!("could not convert colour profile: {}", e);
+                return None;
+            }
+        };
+    let mut pixels: Vec<[u8; N]> = src
+        .chunks_exact(N)
+        .map(|px| px.try_into().unwrap())
+        .collect();
+    transform.transform_in_place(&mut pixels);
+    Some(pixels.iter().flatten().copied().collect())
+}
+
+/// Read the ICC profile embedded in a JPEG, PNG or WebP file.
+fn embedded_profile(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let bytes = fs::read(path)?;
+    Ok(if bytes.starts_with(&[0xff, 0xd8]) {
+        jpeg_profile(&bytes)
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_profile(&bytes)
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        webp_profile(&bytes)
+    } else {
+        None
+    })
+}
+
+/// JPEGs split the profile across APP2 segments, each starting with `ICC_PROFILE\0` then its
+/// sequence number and the total count.
+fn jpeg_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut chunks = vec![];
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xff {
+        let marker = bytes[pos + 1];
+        // Start of scan: there are no more metadata segments.
+        if marker == 0xda {
+            break;
+        }
+        let len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xe2 && segment.starts_with(b"ICC_PROFILE\0") && segment.len() > 14 {
+            chunks.push((segment[12], &segment[14..]));
+        }
+        pos += 2 + len;
+    }
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|(seq, _)| *seq);
+    Some(
+        chunks
+            .into_iter()
+            .flat_map(|(_, data)| data.iter().copied())
+            .collect(),
+    )
+}
+
+/// PNGs store the profile zlib compressed in an `iCCP` chunk, after its name. An `sRGB` chunk
+/// means sRGB without a profile.
+fn png_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 8;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = bytes.get(pos + 8..pos + 8 + len)?;
+        match kind {
+            b"iCCP" => {
+                let name_end = data.iter().position(|&b| b == 0)?;
+                // Skip the name, its terminator and the compression method.
+                let compressed = data.get(name_end + 2..)?;
+                return miniz_oxide::inflate::decompress_to_vec_zlib(compressed).ok();
+            }
+            b"sRGB" => return Some(Profile::new_srgb().icc().ok()?),
+            b"IDAT" | b"IEND" => return None,
+            _ => (),
+        }
+        // Length, type, data and CRC.
+        pos += 12 + len;
+    }
+    None
+}
+
+/// WebP keeps the profile in an `ICCP` chunk of the RIFF container.
+fn webp_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        if &bytes[pos..pos + 4] == b"ICCP" {
+            return bytes.get(pos + 8..pos + 8 + len).map(|data| data.to_vec());
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + len + (len & 1);
+    }
+    None
+}
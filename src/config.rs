@@ -0,0 +1,353 @@
+//! The user's settings, read from `config.toml` in the config folder. Anything the file leaves
+//! out, or gets wrong, keeps its default.
+//!
+//! ```toml
+//! min-zoom = "20%"          # how far out and in the zoom goes
+//! max-zoom = "1500%"
+//! animation = "160ms"       # how long zooming and panning take to settle, 0 for no animation
+//! background = "grey"       # behind images, unless their folder says otherwise: black, grey,
+//!                           # white or a colour like #202020
+//! transparency = "none"     # behind transparency: checkerboard (the default), none or a colour
+//! interpolation = "auto"    # auto (as the profile says), smooth or pixelated
+//! slideshow = "5s"          # how long the slideshow shows each image for
+//! theme = "dark"            # dark (the default), light, or auto to follow the OS
+//!
+//! [keys]                    # keyboard shortcuts, see the keymap module
+//! next = ["Right", "PageDown", "Space"]
+//!
+//! [[macro]]                 # one for each macro, see the macros module
+//! name = "square"
+//! steps = ["crop 1:1", "export png"]
+//! ```
+//!
+//! The settings can be exported to a single file, and imported on another machine. The file
+//! says which version of the export format it is in, so imports can bring older files up to
+//! date.
+use druid::{Color, FileInfo, Selector};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    cli,
+    keymap::{self, Keymap},
+    macros::Macro,
+    profile::InitialZoom,
+    theme::Theme,
+    widgets::{Backdrop, InterpolationPolicy, MAX_SCALE, MIN_SCALE, TARGET_ANIM_LEN},
+};
+
+/// The name of the file, in the config folder.
+pub const FILE_NAME: &str = "config.toml";
+
+/// Export the settings to the chosen path.
+pub const EXPORT_SETTINGS: Selector<FileInfo> = Selector::new("image-viewer.export-settings");
+/// Import the settings at the chosen path, replacing ours.
+pub const IMPORT_SETTINGS: Selector<FileInfo> = Selector::new("image-viewer.import-settings");
+
+/// The version of the export format. When a setting is renamed or moved, bump this and add a
+/// step to `migrate` that moves it in files from before.
+const EXPORT_VERSION: i64 = 1;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The smallest scale the user can zoom out to, though images can always be zoomed out until
+    /// they fit.
+    pub min_zoom: f64,
+    pub max_zoom: f64,
+    /// How long zooming and panning animate for.
+    pub animation: Duration,
+    /// What to draw behind images, if not as the profile says.
+    pub background: Option<Color>,
+    /// What to draw behind the transparent parts of images.
+    pub transparency: Backdrop,
+    /// How to sample images, if not as the profile says.
+    pub interpolation: Option<InterpolationPolicy>,
+    /// How long to show each image for when the slideshow is started without `--slideshow`.
+    pub slideshow: Duration,
+    /// The theme to start with, or `None` to follow the OS.
+    pub theme: Option<Theme>,
+    pub keymap: Keymap,
+    /// The macros, in the order they were saved.
+    pub macros: Vec<Macro>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            min_zoom: MIN_SCALE,
+            max_zoom: MAX_SCALE,
+            animation: Duration::from_secs_f64(TARGET_ANIM_LEN / 1000.),
+            background: None,
+            transparency: Backdrop::default(),
+            interpolation: None,
+            slideshow: Duration::from_secs(5),
+            theme: Some(Theme::Dark),
+            keymap: Keymap::default(),
+            macros: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Read the settings in `dir`. As well as the settings, this returns a description of each
+    /// problem with the file, which the user should be told about.
+    pub fn load(dir: &Path) -> (Self, Vec<String>) {
+        let path = dir.join(FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return (Self::default(), vec![]),
+            Err(e) => {
+                let problem = format!("could not read {}: {}", path.display(), e);
+                return (Self::default(), vec![problem]);
+            }
+        };
+        match text.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => Self::from_table(&table),
+            Ok(_) => (
+                Self::default(),
+                vec![format!("{} isn't a table", path.display())],
+            ),
+            Err(e) => (Self::default(), vec![format!("{}: {}", path.display(), e)]),
+        }
+    }
+
+    /// The defaults, with the settings in `table` instead. A setting that is wrong is left at
+    /// its default, and reported.
+    pub fn from_table(table: &toml::value::Table) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut problems = vec![];
+        for (name, value) in table {
+            let result = match name.as_str() {
+                "min-zoom" => zoom(value).map(|scale| config.min_zoom = scale),
+                "max-zoom" => zoom(value).map(|scale| config.max_zoom = scale),
+                "animation" => text(value).and_then(|len| {
+                    config.animation = match len.trim() {
+                        "0" => Duration::from_secs(0),
+                        len => cli::parse_duration(len)?,
+                    };
+                    Ok(())
+                }),
+                "background" => text(value).and_then(|color| {
+                    config.background = Some(cli::parse_color(&color)?);
+                    Ok(())
+                }),
+                "transparency" => text(value).and_then(|backdrop| {
+                    config.transparency = match backdrop.as_str() {
+                        "checkerboard" => Backdrop::Checkerboard,
+                        "none" => Backdrop::None,
+                        color => Backdrop::Color(cli::parse_color(color)?),
+                    };
+                    Ok(())
+                }),
+                "interpolation" => text(value).and_then(|mode| {
+                    config.interpolation = interpolation(&mode)?;
+                    Ok(())
+                }),
+                "slideshow" => text(value).and_then(|interval| {
+                    config.slideshow = cli::parse_duration(&interval)?;
+                    Ok(())
+                }),
+                "theme" => text(value).and_then(|theme| {
+                    config.theme = match theme.as_str() {
+                        "dark" => Some(Theme::Dark),
+                        "light" => Some(Theme::Light),
+                        "auto" => None,
+                        _ => return Err(format!("expected dark, light or auto, got {:?}", theme)),
+                    };
+                    Ok(())
+                }),
+                "keys" => match value {
+                    toml::Value::Table(keys) => {
+                        let (keymap, key_problems) = Keymap::from_table(keys);
+                        config.keymap = keymap;
+                        problems.extend(key_problems);
+                        Ok(())
+                    }
+                    _ => Err("expected a table".into()),
+                },
+                "macro" => match value {
+                    toml::Value::Array(macros) => {
+                        for table in macros {
+                            let saved = match table {
+                                toml::Value::Table(table) => Macro::from_table(table),
+                                _ => Err("expected [[macro]] tables".into()),
+                            };
+                            match saved {
+                                // A macro saved again under the same name replaces the first.
+                                Ok(saved) => {
+                                    config.macros.retain(|m| m.name != saved.name);
+                                    config.macros.push(saved);
+                                }
+                                Err(e) => problems.push(e),
+                            }
+                        }
+                        Ok(())
+                    }
+                    _ => Err("expected [[macro]] tables".into()),
+                },
+                _ => {
+                    problems.push(format!("unknown setting {:?}", name));
+                    continue;
+                }
+            };
+            if let Err(e) = result {
+                problems.push(format!("{}: {}", name, e));
+            }
+        }
+        if config.min_zoom > config.max_zoom {
+            problems.push("min-zoom is more than max-zoom".into());
+            config.min_zoom = MIN_SCALE;
+            config.max_zoom = MAX_SCALE;
+        }
+        (config, problems)
+    }
+}
+
+/// Add `saved` to the end of the config file in `dir`. The file is appended to, rather than
+/// rewritten, so the user's comments and layout are kept; an earlier macro with the same name is
+/// replaced by this one when the file is next read.
+pub fn save_macro(dir: &Path, saved: &Macro) -> Result<(), String> {
+    let path = dir.join(FILE_NAME);
+    let table =
+        toml::to_string(&toml::Value::Table(saved.to_table())).map_err(|e| e.to_string())?;
+    let write = || {
+        fs::create_dir_all(dir)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        // Starting on a new line, whether or not the file ends in one.
+        write!(file, "\n[[macro]]\n{}", table)
+    };
+    write().map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+/// Write the settings in `dir` to `dest`, as one file that `import` can read.
+pub fn export(dir: &Path, dest: &Path) -> Result<(), String> {
+    let path = dir.join(FILE_NAME);
+    let settings = match fs::read_to_string(&path) {
+        Ok(text) => match text.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err(format!("{} isn't a table", path.display())),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        },
+        // Nothing has been changed from the defaults.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => toml::value::Table::new(),
+        Err(e) => return Err(format!("could not read {}: {}", path.display(), e)),
+    };
+    let mut file = toml::value::Table::new();
+    file.insert("version".into(), EXPORT_VERSION.into());
+    file.insert("exported-by".into(), env!("CARGO_PKG_VERSION").into());
+    file.insert("settings".into(), settings.into());
+    // Serializing a `Value` puts plain values before tables, as TOML needs.
+    let text = toml::to_string(&toml::Value::Table(file)).map_err(|e| e.to_string())?;
+    let text = format!(
+        "# Image Viewer settings, for --import-settings or the About window\n{}",
+        text
+    );
+    fs::write(dest, text).map_err(|e| format!("could not write {}: {}", dest.display(), e))
+}
+
+/// Make the settings exported to `src` the settings in `dir`. Settings that are wrong are
+/// refused, rather than imported and then ignored, and the file being replaced is kept beside
+/// it, as `config.toml.bak`.
+pub fn import(src: &Path, dir: &Path) -> Result<(), String> {
+    let text =
+        fs::read_to_string(src).map_err(|e| format!("could not read {}: {}", src.display(), e))?;
+    let file = match text.parse::<toml::Value>() {
+        Ok(toml::Value::Table(table)) => table,
+        Ok(_) => return Err(format!("{} isn't a table", src.display())),
+        Err(e) => return Err(format!("{}: {}", src.display(), e)),
+    };
+    let settings = migrate(file)?;
+    let (_, problems) = Config::from_table(&settings);
+    if !problems.is_empty() {
+        return Err(problems.join("\n"));
+    }
+    let text = toml::to_string(&toml::Value::Table(settings)).map_err(|e| e.to_string())?;
+    let path = dir.join(FILE_NAME);
+    let write = || {
+        fs::create_dir_all(dir)?;
+        if path.exists() {
+            let mut backup = path.clone().into_os_string();
+            backup.push(".bak");
+            fs::copy(&path, backup)?;
+        }
+        fs::write(&path, text)
+    };
+    write().map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+/// Bring an exported file up to the current version, returning the settings in it.
+///
+/// Files without a version are taken to be settings files copied from another machine: either
+/// `config.toml`, or `keys.toml` from when the keymap was kept in a file of its own.
+fn migrate(mut file: toml::value::Table) -> Result<toml::value::Table, String> {
+    let version = match file.remove("version") {
+        None => 0,
+        Some(toml::Value::Integer(version)) => version,
+        Some(_) => return Err("the version isn't a number".into()),
+    };
+    if version > EXPORT_VERSION {
+        return Err(format!(
+            "these settings are from a newer version of Image Viewer, which exports version {}; \
+             this one can only import up to version {}",
+            version, EXPORT_VERSION
+        ));
+    }
+    if version < 1 {
+        // `keys.toml` had the actions at the top level.
+        if !file.is_empty() && file.keys().all(|name| keymap::is_action(name)) {
+            let mut settings = toml::value::Table::new();
+            settings.insert("keys".into(), file.into());
+            file = settings;
+        }
+        let mut wrapped = toml::value::Table::new();
+        wrapped.insert("settings".into(), file.into());
+        file = wrapped;
+    }
+    match file.remove("settings") {
+        Some(toml::Value::Table(settings)) => Ok(settings),
+        None => Ok(toml::value::Table::new()),
+        Some(_) => Err("settings isn't a table".into()),
+    }
+}
+
+/// A setting given as text, or as a number, which is read as the text of the number.
+fn text(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(n) => Ok(n.to_string()),
+        _ => Err(format!("expected text or a number, got {}", value)),
+    }
+}
+
+/// A scale, as `--scale` takes it, except that the fitting modes make no sense as a limit.
+fn zoom(value: &toml::Value) -> Result<f64, String> {
+    match cli::parse_scale(&text(value)?)? {
+        InitialZoom::Scale(scale) => Ok(scale),
+        _ => Err("expected a scale like 1500%".into()),
+    }
+}
+
+/// The policy for an interpolation mode, or `None` to leave it to the profile.
+fn interpolation(mode: &str) -> Result<Option<InterpolationPolicy>, String> {
+    let nearest_above = match mode {
+        "auto" => return Ok(None),
+        "smooth" => f64::INFINITY,
+        // Nearest neighbour as soon as the pixels are bigger than the screen's.
+        "pixelated" => 1.,
+        _ => {
+            return Err(format!(
+                "expected auto, smooth or pixelated, got {:?}",
+                mode
+            ))
+        }
+    };
+    Ok(Some(InterpolationPolicy {
+        nearest_above,
+        ..InterpolationPolicy::default()
+    }))
+}
@@ -0,0 +1,318 @@
+//! Image decoding, and a shared pool to do it on off the UI and io threads.
+//!
+//! Pool jobs are queued by priority, and the pool only runs as many at once as we think we have the
+//! cores and memory for.
+use druid::{piet::ImageFormat, ImageBuf};
+use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, GenericImageView};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    error::Error,
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::exif;
+
+/// How much memory we are happy to have tied up in in-flight decodes.
+const DEFAULT_MEMORY_BUDGET: usize = 1 << 30; // 1GiB
+/// A rough guess at the memory needed for one decode (a 50MP RGBA image).
+const BYTES_PER_JOB: usize = 50_000_000 * 4;
+/// Browsers show frames with very short delays for 100ms, as many GIFs rely on it.
+const MIN_FRAME_DELAY: Duration = Duration::from_millis(20);
+const SHORT_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// How urgent a decode is. When a worker becomes free, it always takes the highest priority job
+/// waiting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Batch,
+    /// For the thumbnails in the grid view, once there is one.
+    #[allow(dead_code)]
+    Thumbnail,
+    Prefetch,
+    /// The user is waiting for this image.
+    Interactive,
+}
+
+pub struct DecodePool {
+    pool: rayon::ThreadPool,
+    queue: Arc<Mutex<BinaryHeap<Job>>>,
+    /// Used to keep jobs with the same priority in FIFO order.
+    next_seq: u64,
+}
+
+impl DecodePool {
+    pub fn new() -> Self {
+        Self::with_memory_budget(DEFAULT_MEMORY_BUDGET)
+    }
+
+    /// Create a pool that won't run more decodes at once than fit in `memory_budget` bytes.
+    pub fn with_memory_budget(memory_budget: usize) -> Self {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        let threads = cores.min(memory_budget / BYTES_PER_JOB).max(1);
+        log::debug!("starting decode pool with {} threads", threads);
+        Self {
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|idx| format!("decode-{}", idx))
+                .build()
+                .expect("could not start decode threads"),
+            queue: Arc::new(Mutex::new(BinaryHeap::new())),
+            next_seq: 0,
+        }
+    }
+
+    /// Queue `f` to run on the pool.
+    pub fn spawn(&mut self, priority: Priority, f: impl FnOnce() + Send + 'static) {
+        let job = Job {
+            priority,
+            seq: self.next_seq,
+            f: Box::new(f),
+        };
+        self.next_seq += 1;
+        self.queue.lock().unwrap().push(job);
+
+        // We don't run the job we just queued: each task pops whatever is most urgent at the time
+        // it gets a thread.
+        let queue = self.queue.clone();
+        self.pool.spawn(move || {
+            let job = queue.lock().unwrap().pop();
+            if let Some(job) = job {
+                (job.f)();
+            }
+        });
+    }
+}
+
+struct Job {
+    priority: Priority,
+    seq: u64,
+    f: Box<dyn FnOnce() + Send>,
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Job {}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap, so earlier jobs must compare greater.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Decode the image at `path`.
+///
+/// This does the same job as `ImageBuf::from_file`, but that converts with `to_rgba8` and then
+/// copies the pixels again on the way into the `ImageBuf`. Here we take ownership of the
+/// decoder's buffer where the layout already matches, so the only copy is the one into the
+/// `Arc`.
+///
+/// Photos are turned the right way up if their EXIF tags say they were taken on their side.
+pub fn open(path: &Path) -> Result<ImageBuf, Box<dyn Error + Send + Sync>> {
+    if has_extension(path, "webp") {
+        return open_webp(path).map(|image| upright(path, image));
+    }
+    if crate::raw::is_raw(path) {
+        return crate::raw::open(path);
+    }
+    #[cfg(feature = "avif")]
+    if has_extension(path, "avif") {
+        return open_avif(path);
+    }
+    #[cfg(feature = "heif")]
+    if has_extension(path, "heic") || has_extension(path, "heif") {
+        return open_heif(path);
+    }
+    Ok(upright(path, from_dynamic_image(image::open(path)?)))
+}
+
+/// Turn `image` the right way up, if the file has an EXIF orientation.
+fn upright(path: &Path, image: ImageBuf) -> ImageBuf {
+    match exif::read(path).and_then(|exif| exif.orientation) {
+        Some(orientation) => exif::orient(image, orientation),
+        None => image,
+    }
+}
+
+/// Decode an AVIF image.
+///
+/// libavif converts to 8 bits per channel for us, scaling (rather than truncating) samples from
+/// 10 and 12 bit images.
+#[cfg(feature = "avif")]
+fn open_avif(path: &Path) -> Result<ImageBuf, Box<dyn Error + Send + Sync>> {
+    let bytes = std::fs::read(path)?;
+    let image =
+        libavif::decode_rgb(&bytes).map_err(|e| format!("could not decode AVIF image: {:?}", e))?;
+    let (width, height) = (image.width(), image.height());
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b, a) = image.pixel(x, y);
+            pixels.extend([r, g, b, a]);
+        }
+    }
+    Ok(ImageBuf::from_raw(
+        pixels,
+        ImageFormat::RgbaSeparate,
+        width as usize,
+        height as usize,
+    ))
+}
+
+/// Decode an image held in memory, telling the format from its first few bytes.
+///
+/// There is no file to read EXIF tags from, so the image is left as it was stored.
+pub fn from_memory(bytes: &[u8]) -> Result<ImageBuf, Box<dyn Error + Send + Sync>> {
+    match image::guess_format(bytes)? {
+        image::ImageFormat::WebP => decode_webp(bytes),
+        format => Ok(from_dynamic_image(image::load_from_memory_with_format(
+            bytes, format,
+        )?)),
+    }
+}
+
+fn open_webp(path: &Path) -> Result<ImageBuf, Box<dyn Error + Send + Sync>> {
+    decode_webp(&std::fs::read(path)?)
+}
+
+/// Decode a WebP image, lossy or lossless, with or without alpha.
+fn decode_webp(bytes: &[u8]) -> Result<ImageBuf, Box<dyn Error + Send + Sync>> {
+    let image = webp::Decoder::new(bytes)
+        .decode()
+        .ok_or("could not decode WebP image")?;
+    let format = if image.is_alpha() {
+        ImageFormat::RgbaSeparate
+    } else {
+        ImageFormat::Rgb
+    };
+    Ok(ImageBuf::from_raw(
+        &*image,
+        format,
+        image.width() as usize,
+        image.height() as usize,
+    ))
+}
+
+/// Decode the primary image of a HEIF file.
+///
+/// libheif applies the rotation and mirroring stored in the container for us.
+#[cfg(feature = "heif")]
+fn open_heif(path: &Path) -> Result<ImageBuf, Box<dyn Error + Send + Sync>> {
+    use libheif_rs::{ColorSpace, HeifContext, RgbChroma};
+
+    let path = path.to_str().ok_or("path is not valid unicode")?;
+    let ctx = HeifContext::read_from_file(path)?;
+    let handle = ctx.primary_image_handle()?;
+    let image = handle.decode(ColorSpace::Rgb(RgbChroma::Rgba), false)?;
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or("decoded HEIF image has no pixels")?;
+    let (width, height) = (plane.width as usize, plane.height as usize);
+    // Rows may be padded, so copy them out one at a time.
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for row in plane.data.chunks(plane.stride).take(height) {
+        pixels.extend_from_slice(&row[..width * 4]);
+    }
+    Ok(ImageBuf::from_raw(
+        pixels,
+        ImageFormat::RgbaSeparate,
+        width,
+        height,
+    ))
+}
+
+fn has_extension(path: &Path, ext: &str) -> bool {
+    path.extension()
+        .map_or(false, |actual| actual.eq_ignore_ascii_case(ext))
+}
+
+/// Decode the image at `path`, along with all its frames if it is animated.
+///
+/// The image returned is the first frame.
+pub fn open_animated(
+    path: &Path,
+) -> Result<(ImageBuf, Option<AnimatedImage>), Box<dyn Error + Send + Sync>> {
+    if !has_extension(path, "gif") {
+        return Ok((open(path)?, None));
+    }
+    let decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+    let mut frames = vec![];
+    let mut delays = vec![];
+    // The decoder composites each frame onto the canvas for us, so every frame is a full image.
+    for frame in decoder.into_frames() {
+        let frame = frame?;
+        let (num, denom) = frame.delay().numer_denom_ms();
+        let delay = Duration::from_secs_f64(num as f64 / denom.max(1) as f64 * 0.001);
+        delays.push(if delay < MIN_FRAME_DELAY {
+            SHORT_FRAME_DELAY
+        } else {
+            delay
+        });
+        let buffer = frame.into_buffer();
+        let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+        frames.push(ImageBuf::from_raw(
+            buffer.into_raw(),
+            ImageFormat::RgbaSeparate,
+            width,
+            height,
+        ));
+    }
+    match frames.len() {
+        0 => Err("GIF has no frames".into()),
+        1 => Ok((frames.pop().unwrap(), None)),
+        _ => Ok((frames[0].clone(), Some(AnimatedImage { frames, delays }))),
+    }
+}
+
+/// The frames of an animated image, and how long to show each one.
+pub struct AnimatedImage {
+    pub frames: Vec<ImageBuf>,
+    pub delays: Vec<Duration>,
+}
+
+impl AnimatedImage {
+    /// How long one loop of the animation takes.
+    pub fn duration(&self) -> Duration {
+        self.delays.iter().sum()
+    }
+}
+
+impl fmt::Debug for AnimatedImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AnimatedImage")
+            .field("frames", &self.frames.len())
+            .field("duration", &self.duration())
+            .finish()
+    }
+}
+
+pub fn from_dynamic_image(image: DynamicImage) -> ImageBuf {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    if image.color().has_alpha() {
+        let pixels = image.into_rgba8().into_raw();
+        ImageBuf::from_raw(pixels, ImageFormat::RgbaSeparate, width, height)
+    } else {
+        let pixels = image.into_rgb8().into_raw();
+        ImageBuf::from_raw(pixels, ImageFormat::Rgb, width, height)
+    }
+}
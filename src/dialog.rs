@@ -0,0 +1,322 @@
+//! Modal dialogs that ask the user to confirm something or choose what to do about it.
+//!
+//! Submit `SHOW_DIALOG` with a `Dialog`, and its reply selector is submitted with the choice made.
+//! Dialogs shown while another is open wait their turn. When several waiting dialogs ask the same
+//! question (they have the same title), for example about each file that already exists in a
+//! batch, the user is offered "Apply to all", which answers all of them with one choice.
+use druid::{
+    kurbo::Point,
+    widget::{
+        prelude::*, Button, Checkbox, CrossAxisAlignment, Flex, Label, LineBreaking, SizedBox,
+        ViewSwitcher,
+    },
+    ArcStr, Color, Command, Data, Lens, Selector, WidgetExt, WidgetPod,
+};
+use std::{path::Path, sync::Arc};
+
+/// Show a dialog, or queue it behind the one that is open.
+pub const SHOW_DIALOG: Selector<Dialog> = Selector::new("image-viewer.show-dialog");
+
+/// Something the user can choose in a dialog.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Data)]
+pub struct Choice {
+    pub label: &'static str,
+    /// Whether the choice loses data, and so is drawn in red.
+    pub destructive: bool,
+}
+
+impl Choice {
+    pub const fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            destructive: false,
+        }
+    }
+
+    pub const fn destructive(label: &'static str) -> Self {
+        Self {
+            label,
+            destructive: true,
+        }
+    }
+}
+
+pub const CANCEL: Choice = Choice::new("Cancel");
+pub const OVERWRITE: Choice = Choice::destructive("Overwrite");
+
+/// What the user chose, sent with a dialog's reply selector.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub choice: Choice,
+    /// The file the dialog was about, if any.
+    pub subject: Option<Arc<Path>>,
+}
+
+#[derive(Debug, Clone, Data)]
+pub struct Dialog {
+    /// Set when the dialog is queued.
+    id: u64,
+    pub title: ArcStr,
+    pub message: ArcStr,
+    /// The first is chosen by Enter. Escape chooses `CANCEL` if it is here, or else the last.
+    pub choices: Arc<Vec<Choice>>,
+    pub subject: Option<Arc<Path>>,
+    #[data(ignore)]
+    reply: Selector<Reply>,
+}
+
+impl Dialog {
+    pub fn new(
+        title: impl Into<ArcStr>,
+        message: impl Into<ArcStr>,
+        reply: Selector<Reply>,
+    ) -> Self {
+        Self {
+            id: 0,
+            title: title.into(),
+            message: message.into(),
+            choices: Arc::new(vec![]),
+            subject: None,
+            reply,
+        }
+    }
+
+    /// Ask whether to replace the file at `path`.
+    pub fn overwrite(path: &Path, reply: Selector<Reply>) -> Self {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        Self::new(
+            "File already exists",
+            format!(
+                "{} already exists. Do you want to replace it?",
+                name.to_string_lossy()
+            ),
+            reply,
+        )
+        .with_choice(OVERWRITE)
+        .with_choice(CANCEL)
+        .with_subject(path)
+    }
+
+    /// Builder-style method to add a choice, after the ones already added.
+    pub fn with_choice(mut self, choice: Choice) -> Self {
+        Arc::make_mut(&mut self.choices).push(choice);
+        self
+    }
+
+    /// Builder-style method to set the file the dialog is about.
+    pub fn with_subject(mut self, path: &Path) -> Self {
+        self.subject = Some(path.into());
+        self
+    }
+
+    fn default_choice(&self) -> Option<Choice> {
+        self.choices.first().copied()
+    }
+
+    fn cancel_choice(&self) -> Option<Choice> {
+        if self.choices.contains(&CANCEL) {
+            Some(CANCEL)
+        } else {
+            self.choices.last().copied()
+        }
+    }
+
+    fn reply(&self, choice: Choice) -> Command {
+        self.reply.with(Reply {
+            choice,
+            subject: self.subject.clone(),
+        })
+    }
+}
+
+/// The open dialog, and those waiting behind it.
+#[derive(Debug, Clone, Default, Data, Lens)]
+pub struct Dialogs {
+    pub current: Option<Dialog>,
+    queue: Arc<Vec<Dialog>>,
+    /// Whether to give the same answer to the waiting dialogs that ask the same question.
+    apply_to_all: bool,
+    next_id: u64,
+}
+
+impl Dialogs {
+    pub fn is_open(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn show(&mut self, mut dialog: Dialog) {
+        dialog.id = self.next_id;
+        self.next_id += 1;
+        if self.current.is_none() {
+            self.current = Some(dialog);
+        } else {
+            Arc::make_mut(&mut self.queue).push(dialog);
+        }
+    }
+
+    /// Answer the open dialog with `choice`, returning the replies to submit.
+    pub fn answer(&mut self, choice: Choice) -> Vec<Command> {
+        let dialog = match self.current.take() {
+            Some(dialog) => dialog,
+            None => return vec![],
+        };
+        let mut replies = vec![dialog.reply(choice)];
+        if self.apply_to_all {
+            Arc::make_mut(&mut self.queue).retain(|other| {
+                if other.title == dialog.title {
+                    replies.push(other.reply(choice));
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        self.apply_to_all = false;
+        if !self.queue.is_empty() {
+            self.current = Some(Arc::make_mut(&mut self.queue).remove(0));
+        }
+        replies
+    }
+
+    /// Answer the open dialog as if Enter was pressed.
+    pub fn accept(&mut self) -> Vec<Command> {
+        match self.current.as_ref().and_then(Dialog::default_choice) {
+            Some(choice) => self.answer(choice),
+            None => vec![],
+        }
+    }
+
+    /// Answer the open dialog as if Escape was pressed.
+    pub fn cancel(&mut self) -> Vec<Command> {
+        match self.current.as_ref().and_then(Dialog::cancel_choice) {
+            Some(choice) => self.answer(choice),
+            None => vec![],
+        }
+    }
+
+    /// Whether any waiting dialog asks the same question as the open one.
+    fn offers_apply_to_all(&self) -> bool {
+        self.current.as_ref().map_or(false, |current| {
+            self.queue.iter().any(|other| other.title == current.title)
+        })
+    }
+}
+
+/// The open dialog, if any.
+pub fn view() -> impl Widget<Dialogs> {
+    ViewSwitcher::new(
+        |data: &Dialogs, _| {
+            (
+                data.current.as_ref().map(|dialog| dialog.id),
+                data.offers_apply_to_all(),
+            )
+        },
+        |_, data: &Dialogs, _| match &data.current {
+            Some(dialog) => Box::new(dialog_view(dialog, data.offers_apply_to_all())),
+            None => Box::new(SizedBox::empty()),
+        },
+    )
+}
+
+fn dialog_view(dialog: &Dialog, apply_to_all: bool) -> impl Widget<Dialogs> {
+    let mut buttons = Flex::row().with_flex_spacer(1.);
+    for &choice in dialog.choices.iter() {
+        let button = Button::from_label(Label::new(choice.label).with_text_color(
+            if choice.destructive {
+                Color::rgb8(0xff, 0x80, 0x80)
+            } else {
+                Color::WHITE
+            },
+        ))
+        .on_click(move |ctx, data: &mut Dialogs, _| {
+            for reply in data.answer(choice) {
+                ctx.submit_command(reply);
+            }
+        });
+        buttons.add_spacer(4.);
+        buttons.add_child(button);
+    }
+    let mut column = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(dialog.title.clone()).with_text_size(16.))
+        .with_spacer(8.)
+        .with_child(Label::new(dialog.message.clone()).with_line_break_mode(LineBreaking::WordWrap))
+        .with_spacer(8.);
+    if apply_to_all {
+        column.add_child(Checkbox::new("Apply to all").lens(Dialogs::apply_to_all));
+        column.add_spacer(8.);
+    }
+    column
+        .with_child(buttons)
+        .padding(12.)
+        .fix_width(360.)
+        .background(Color::grey8(0x30))
+        .border(Color::grey8(0x60), 1.)
+}
+
+/// Draws a dialog over `content` while `is_open`, and keeps the mouse and keyboard from reaching
+/// `content` until it is answered.
+pub struct Modal<T> {
+    content: WidgetPod<T, Box<dyn Widget<T>>>,
+    dialog: WidgetPod<T, Box<dyn Widget<T>>>,
+    is_open: Box<dyn Fn(&T) -> bool>,
+}
+
+impl<T: Data> Modal<T> {
+    pub fn new(
+        content: impl Widget<T> + 'static,
+        dialog: impl Widget<T> + 'static,
+        is_open: impl Fn(&T) -> bool + 'static,
+    ) -> Self {
+        Modal {
+            content: WidgetPod::new(content).boxed(),
+            dialog: WidgetPod::new(dialog).boxed(),
+            is_open: Box::new(is_open),
+        }
+    }
+}
+
+impl<T: Data> Widget<T> for Modal<T> {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut T, env: &Env) {
+        let from_user = matches!(
+            event,
+            Event::MouseDown(_)
+                | Event::MouseUp(_)
+                | Event::MouseMove(_)
+                | Event::Wheel(_)
+                | Event::KeyDown(_)
+                | Event::KeyUp(_)
+        );
+        if !(from_user && (self.is_open)(data)) {
+            self.content.event(ctx, event, data, env);
+        }
+        self.dialog.event(ctx, event, data, env)
+    }
+    fn lifecycle(&mut self, ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &T, env: &Env) {
+        self.content.lifecycle(ctx, event, data, env);
+        self.dialog.lifecycle(ctx, event, data, env)
+    }
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &T, data: &T, env: &Env) {
+        if (self.is_open)(old_data) != (self.is_open)(data) {
+            ctx.request_paint();
+        }
+        self.content.update(ctx, data, env);
+        self.dialog.update(ctx, data, env)
+    }
+    fn layout(&mut self, ctx: &mut LayoutCtx, bc: &BoxConstraints, data: &T, env: &Env) -> Size {
+        let size = self.content.layout(ctx, bc, data, env);
+        self.content.set_origin(ctx, data, env, Point::ZERO);
+        let dialog = self.dialog.layout(ctx, &bc.loosen(), data, env);
+        let origin = ((size.to_vec2() - dialog.to_vec2()) / 2.).to_point();
+        self.dialog.set_origin(ctx, data, env, origin);
+        size
+    }
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &T, env: &Env) {
+        self.content.paint(ctx, data, env);
+        if (self.is_open)(data) {
+            let r = ctx.size().to_rect();
+            ctx.fill(r, &Color::rgba8(0, 0, 0, 0x80));
+            self.dialog.paint(ctx, data, env);
+        }
+    }
+}
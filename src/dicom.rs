@@ -0,0 +1,343 @@
+//! A minimal DICOM reader, enough to show uncompressed greyscale images.
+//!
+//! Medical images usually have more than 8 bits per sample, so we keep the samples and render
+//! them to 8 bits through a window (a centre and width, like brightness and contrast) that the
+//! user can adjust.
+use druid::{piet::ImageFormat, Data, ImageBuf};
+use std::{convert::TryInto, error::Error, fmt, fs, path::Path};
+
+/// Transfer syntaxes we can read. Everything else is compressed or big-endian.
+const IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+const EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1";
+
+const TRANSFER_SYNTAX: Tag = Tag(0x0002, 0x0010);
+const SAMPLES_PER_PIXEL: Tag = Tag(0x0028, 0x0002);
+const PHOTOMETRIC: Tag = Tag(0x0028, 0x0004);
+const ROWS: Tag = Tag(0x0028, 0x0010);
+const COLUMNS: Tag = Tag(0x0028, 0x0011);
+const BITS_ALLOCATED: Tag = Tag(0x0028, 0x0100);
+const PIXEL_REPRESENTATION: Tag = Tag(0x0028, 0x0103);
+const WINDOW_CENTER: Tag = Tag(0x0028, 0x1050);
+const WINDOW_WIDTH: Tag = Tag(0x0028, 0x1051);
+const RESCALE_INTERCEPT: Tag = Tag(0x0028, 0x1052);
+const RESCALE_SLOPE: Tag = Tag(0x0028, 0x1053);
+const PIXEL_DATA: Tag = Tag(0x7fe0, 0x0010);
+const ITEM: Tag = Tag(0xfffe, 0xe000);
+const ITEM_END: Tag = Tag(0xfffe, 0xe00d);
+const SEQUENCE_END: Tag = Tag(0xfffe, 0xe0dd);
+
+/// Tags worth showing the user, and what to call them.
+const SUMMARY_TAGS: &[(Tag, &str)] = &[
+    (Tag(0x0008, 0x0060), "Modality"),
+    (Tag(0x0010, 0x0010), "Patient"),
+    (Tag(0x0008, 0x0020), "Study date"),
+    (Tag(0x0008, 0x1030), "Study"),
+    (Tag(0x0008, 0x103e), "Series"),
+];
+
+type DicomResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Which range of sample values is spread over black to white.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Data)]
+pub struct Window {
+    pub center: f64,
+    pub width: f64,
+}
+
+/// A decoded DICOM image.
+pub struct DicomImage {
+    width: usize,
+    height: usize,
+    /// Rescaled sample values (e.g. Hounsfield units for CT), row by row.
+    samples: Vec<f32>,
+    /// MONOCHROME1 images show low values as white.
+    inverted: bool,
+    /// The window the file asks for, or one covering every sample if it doesn't.
+    pub default_window: Window,
+    /// The smallest and largest sample values.
+    range: (f32, f32),
+    /// Descriptive tags, as (name, value).
+    pub tags: Vec<(&'static str, String)>,
+}
+
+impl DicomImage {
+    /// The difference between the largest and smallest sample values.
+    pub fn value_range(&self) -> f64 {
+        (self.range.1 - self.range.0).max(1.) as f64
+    }
+
+    /// Render the samples through `window`, as the standard's linear VOI function does.
+    pub fn render(&self, window: Window) -> ImageBuf {
+        let width = (window.width - 1.).max(1.);
+        let bottom = window.center - 0.5 - width / 2.;
+        let pixels = self
+            .samples
+            .iter()
+            .map(|&v| {
+                let level = ((v as f64 - bottom) / width * 255.).round().clamp(0., 255.) as u8;
+                if self.inverted {
+                    255 - level
+                } else {
+                    level
+                }
+            })
+            .collect();
+        ImageBuf::from_raw(pixels, ImageFormat::Grayscale, self.width, self.height)
+    }
+}
+
+impl fmt::Debug for DicomImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DicomImage")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("default_window", &self.default_window)
+            .field("tags", &self.tags)
+            .finish()
+    }
+}
+
+/// Whether `path` looks like a DICOM file we should read with this module.
+pub fn is_dicom(path: &Path) -> bool {
+    cfg!(feature = "dicom")
+        && path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("dcm"))
+}
+
+/// Read the first frame of the DICOM file at `path`.
+pub fn open(path: &Path) -> DicomResult<DicomImage> {
+    let bytes = fs::read(path)?;
+    if bytes.get(128..132) != Some(b"DICM") {
+        return Err("not a DICOM file".into());
+    }
+    let mut elements = vec![];
+    // The file meta group is always explicit VR little endian, and tells us how the rest is
+    // encoded.
+    let mut reader = Reader {
+        bytes: &bytes,
+        pos: 132,
+        explicit: true,
+    };
+    while reader.peek_tag().map_or(false, |tag| tag.0 == 0x0002) {
+        elements.push(reader.element()?);
+    }
+    let syntax = find(&elements, TRANSFER_SYNTAX)
+        .map(text)
+        .unwrap_or_else(|| EXPLICIT_VR_LE.to_owned());
+    reader.explicit = match syntax.as_str() {
+        EXPLICIT_VR_LE => true,
+        IMPLICIT_VR_LE => false,
+        other => return Err(format!("unsupported transfer syntax {}", other).into()),
+    };
+    while reader.pos < bytes.len() {
+        let element = reader.element()?;
+        let done = element.0 == PIXEL_DATA;
+        elements.push(element);
+        if done {
+            break;
+        }
+    }
+    decode(&elements)
+}
+
+fn decode(elements: &[(Tag, &[u8])]) -> DicomResult<DicomImage> {
+    let required = |tag: Tag, name: &str| -> DicomResult<u16> {
+        find(elements, tag)
+            .and_then(u16_value)
+            .ok_or_else(|| format!("missing {}", name).into())
+    };
+    let width = required(COLUMNS, "columns")? as usize;
+    let height = required(ROWS, "rows")? as usize;
+    let bits = required(BITS_ALLOCATED, "bits allocated")?;
+    let samples_per_pixel = find(elements, SAMPLES_PER_PIXEL)
+        .and_then(u16_value)
+        .unwrap_or(1);
+    let signed = find(elements, PIXEL_REPRESENTATION).and_then(u16_value) == Some(1);
+    let photometric = find(elements, PHOTOMETRIC).map(text).unwrap_or_default();
+    if samples_per_pixel != 1 {
+        return Err("only greyscale DICOM images are supported".into());
+    }
+    let data = find(elements, PIXEL_DATA).ok_or("no pixel data")?;
+
+    let count = width * height;
+    if count == 0 {
+        return Err("image is empty".into());
+    }
+    let raw: Vec<f32> = match (bits, signed) {
+        (8, false) => data.iter().take(count).map(|&v| v as f32).collect(),
+        (8, true) => data.iter().take(count).map(|&v| v as i8 as f32).collect(),
+        (16, false) => data
+            .chunks_exact(2)
+            .take(count)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as f32)
+            .collect(),
+        (16, true) => data
+            .chunks_exact(2)
+            .take(count)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32)
+            .collect(),
+        _ => return Err(format!("unsupported bits allocated: {}", bits).into()),
+    };
+    if raw.len() < count {
+        return Err("pixel data is truncated".into());
+    }
+
+    let slope = find(elements, RESCALE_SLOPE)
+        .and_then(decimal)
+        .unwrap_or(1.);
+    let intercept = find(elements, RESCALE_INTERCEPT)
+        .and_then(decimal)
+        .unwrap_or(0.);
+    let samples: Vec<f32> = raw
+        .into_iter()
+        .map(|v| v * slope as f32 + intercept as f32)
+        .collect();
+    let range = samples
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+    let default_window = match (
+        find(elements, WINDOW_CENTER).and_then(decimal),
+        find(elements, WINDOW_WIDTH).and_then(decimal),
+    ) {
+        (Some(center), Some(width)) if width >= 1. => Window { center, width },
+        _ => Window {
+            center: (range.0 as f64 + range.1 as f64) / 2.,
+            width: (range.1 - range.0) as f64 + 1.,
+        },
+    };
+    let tags = SUMMARY_TAGS
+        .iter()
+        .filter_map(|&(tag, name)| {
+            let value = find(elements, tag).map(text)?;
+            // Person names separate their parts with '^'.
+            let value = value.replace('^', " ").trim().to_owned();
+            (!value.is_empty()).then(|| (name, value))
+        })
+        .collect();
+    Ok(DicomImage {
+        width,
+        height,
+        samples,
+        inverted: photometric.trim() == "MONOCHROME1",
+        default_window,
+        range,
+        tags,
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Tag(u16, u16);
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    explicit: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> DicomResult<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or("unexpected end of file")?;
+        let out = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn u16(&mut self) -> DicomResult<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> DicomResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn peek_tag(&self) -> Option<Tag> {
+        let b = self.bytes.get(self.pos..self.pos + 4)?;
+        Some(Tag(
+            u16::from_le_bytes([b[0], b[1]]),
+            u16::from_le_bytes([b[2], b[3]]),
+        ))
+    }
+
+    fn tag(&mut self) -> DicomResult<Tag> {
+        Ok(Tag(self.u16()?, self.u16()?))
+    }
+
+    /// Read the next element. Sequences are skipped over, and come back with an empty value.
+    fn element(&mut self) -> DicomResult<(Tag, &'a [u8])> {
+        let tag = self.tag()?;
+        // Item and delimiter tags never have a VR.
+        let (vr, len) = if self.explicit && tag.0 != 0xfffe {
+            let vr = self.take(2)?;
+            match vr {
+                b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"SV" | b"UC" | b"UN"
+                | b"UR" | b"UT" | b"UV" => {
+                    self.take(2)?;
+                    (Some(vr), self.u32()?)
+                }
+                _ => (Some(vr), self.u16()? as u32),
+            }
+        } else {
+            (None, self.u32()?)
+        };
+        if len == u32::MAX {
+            if tag == PIXEL_DATA {
+                return Err("compressed pixel data is not supported".into());
+            }
+            if matches!(vr, None | Some(b"SQ") | Some(b"UN")) {
+                self.skip_sequence()?;
+                return Ok((tag, &[]));
+            }
+            return Err("element with undefined length".into());
+        }
+        Ok((tag, self.take(len as usize)?))
+    }
+
+    /// Skip the items of a sequence with undefined length, up to and including its delimiter.
+    fn skip_sequence(&mut self) -> DicomResult<()> {
+        loop {
+            let tag = self.tag()?;
+            let len = self.u32()?;
+            match tag {
+                SEQUENCE_END => return Ok(()),
+                ITEM if len == u32::MAX => {
+                    // Nested elements, up to the item delimiter.
+                    while self.peek_tag() != Some(ITEM_END) {
+                        self.element()?;
+                    }
+                    self.take(8)?;
+                }
+                ITEM => {
+                    self.take(len as usize)?;
+                }
+                _ => return Err("malformed sequence".into()),
+            }
+        }
+    }
+}
+
+fn find<'a>(elements: &[(Tag, &'a [u8])], tag: Tag) -> Option<&'a [u8]> {
+    elements.iter().find(|(t, _)| *t == tag).map(|(_, v)| *v)
+}
+
+/// String values are padded to an even length with spaces or nuls.
+fn text(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches(|c| c == ' ' || c == '\0')
+        .to_owned()
+}
+
+fn u16_value(value: &[u8]) -> Option<u16> {
+    Some(u16::from_le_bytes(value.get(..2)?.try_into().ok()?))
+}
+
+/// The first of a (possibly multi-valued) decimal string.
+fn decimal(value: &[u8]) -> Option<f64> {
+    text(value).split('\\').next()?.trim().parse().ok()
+}
@@ -0,0 +1,73 @@
+//! Sending images by email, through whatever mail client the desktop is set up with.
+use druid::Selector;
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, ColorType, RgbImage};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::{decode, pdf, shell};
+
+/// Email the current image.
+pub const SEND_EMAIL: Selector = Selector::new("image-viewer.send-email");
+
+/// Images are scaled down to fit in this many pixels each way before they are attached, which
+/// keeps camera originals under most mail servers' size limits.
+pub const DEFAULT_MAX_SIZE: u32 = 2048;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Open a new message in the mail client with `images` attached.
+///
+/// If `max_size` is set, images bigger than it are attached as scaled down JPEG copies instead
+/// of the originals. The copies are left in the temporary directory, as the mail client reads them
+/// after we return.
+pub fn send(images: &[PathBuf], max_size: Option<u32>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if images.is_empty() {
+        return Err("no images to send".into());
+    }
+    let mut attachments = Vec::with_capacity(images.len());
+    for path in images {
+        let attachment = match max_size {
+            Some(max_size) => shrink(path, max_size)
+                .map_err(|e| format!("{}: {}", path.display(), e))?
+                .unwrap_or_else(|| path.clone()),
+            None => path.clone(),
+        };
+        attachments.push(attachment);
+    }
+    shell::send_by_email(&attachments)?;
+    Ok(())
+}
+
+/// Write a copy of `path` no bigger than `max_size` each way, or return `None` if it already
+/// fits.
+fn shrink(path: &Path, max_size: u32) -> Result<Option<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let image = decode::open(path)?;
+    let (width, height) = (image.width() as u32, image.height() as u32);
+    if width <= max_size && height <= max_size {
+        return Ok(None);
+    }
+    let scale = max_size as f64 / width.max(height) as f64;
+    let new_width = ((width as f64 * scale).round() as u32).max(1);
+    let new_height = ((height as f64 * scale).round() as u32).max(1);
+    // JPEG has no alpha, so flatten the same way the PDF export does.
+    let rgb = RgbImage::from_raw(width, height, pdf::to_rgb(&image))
+        .ok_or("decoded image has the wrong size")?;
+    let resized = image::imageops::resize(&rgb, new_width, new_height, FilterType::Lanczos3);
+
+    let dir = std::env::temp_dir().join("image-viewer-email");
+    fs::create_dir_all(&dir)?;
+    let stem = path.file_stem().ok_or("image has no file name")?;
+    let dest = dir.join(stem).with_extension("jpg");
+    let mut out = BufWriter::new(File::create(&dest)?);
+    JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode(
+        &resized,
+        new_width,
+        new_height,
+        ColorType::Rgb8,
+    )?;
+    Ok(Some(dest))
+}
@@ -0,0 +1,107 @@
+//! Writing images to disk, in the format their file extension says.
+//!
+//! PNG, JPEG and WebP are handled here. Any other format the image crate can write is saved with
+//! its default settings.
+use druid::{piet::ImageFormat, ImageBuf};
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    ColorType,
+};
+use std::{error::Error, fs, fs::File, io::BufWriter, path::Path};
+
+use crate::{pdf, pixel_ops};
+
+/// How to encode saved images.
+#[derive(Debug, Copy, Clone)]
+pub struct EncodeOptions {
+    /// From 1 to 100, for the lossy formats (JPEG and WebP). WebP at 100 is saved lossless.
+    pub quality: u8,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self { quality: 90 }
+    }
+}
+
+pub fn save(
+    image: &ImageBuf,
+    path: &Path,
+    options: &EncodeOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let format = image::ImageFormat::from_path(path)?;
+    let (width, height) = (image.width() as u32, image.height() as u32);
+    let quality = options.quality.max(1).min(100);
+    let has_alpha = matches!(
+        image.format(),
+        ImageFormat::RgbaSeparate | ImageFormat::RgbaPremul
+    );
+    match format {
+        image::ImageFormat::Jpeg => {
+            // JPEG has no alpha, so flatten the same way the PDF export does.
+            let mut out = BufWriter::new(File::create(path)?);
+            JpegEncoder::new_with_quality(&mut out, quality).encode(
+                &pdf::to_rgb(image),
+                width,
+                height,
+                ColorType::Rgb8,
+            )?;
+        }
+        image::ImageFormat::WebP => {
+            // The image crate can't write WebP, so use libwebp, as for decoding.
+            let pixels = unpremultiply(image);
+            let encoder = webp::Encoder::from_rgba(&pixels, width, height);
+            let encoded = if quality == 100 {
+                encoder.encode_lossless()
+            } else {
+                encoder.encode(quality as f32)
+            };
+            fs::write(path, &*encoded)?;
+        }
+        _ if has_alpha => {
+            image::save_buffer_with_format(
+                path,
+                &unpremultiply(image),
+                width,
+                height,
+                ColorType::Rgba8,
+                format,
+            )?;
+        }
+        _ => {
+            image::save_buffer_with_format(
+                path,
+                &pdf::to_rgb(image),
+                width,
+                height,
+                ColorType::Rgb8,
+                format,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Encode `image` as a PNG in memory.
+pub fn to_png(image: &ImageBuf) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let (width, height) = (image.width() as u32, image.height() as u32);
+    let mut out = vec![];
+    PngEncoder::new(&mut out).encode(&unpremultiply(image), width, height, ColorType::Rgba8)?;
+    Ok(out)
+}
+
+/// The pixels of `image` as RGBA with separate alpha, which is what the encoders expect.
+fn unpremultiply(image: &ImageBuf) -> Vec<u8> {
+    let mut pixels = pixel_ops::to_rgba(image).into_owned();
+    if image.format() == ImageFormat::RgbaPremul {
+        for px in pixels.chunks_exact_mut(4) {
+            let alpha = px[3] as u32;
+            if alpha > 0 {
+                for c in &mut px[..3] {
+                    *c = ((*c as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+                }
+            }
+        }
+    }
+    pixels
+}
@@ -0,0 +1,215 @@
+//! Reading the camera settings photos are tagged with.
+//!
+//! EXIF is a TIFF structure. JPEGs keep it in an APP1 segment, PNGs in an `eXIf` chunk and WebPs
+//! in an `EXIF` chunk, and TIFF based RAW files are EXIF all the way through.
+use druid::{ImageBuf, Selector};
+use std::{convert::TryInto, fs, path::Path};
+
+use crate::tiff::{Entry, Tiff};
+
+/// Show or hide the metadata panel.
+pub const TOGGLE_EXIF: Selector = Selector::new("image-viewer.toggle-exif");
+
+const MAKE: u16 = 0x010f;
+const MODEL: u16 = 0x0110;
+const ORIENTATION: u16 = 0x0112;
+const DATE_TIME: u16 = 0x0132;
+const EXIF_IFD: u16 = 0x8769;
+const EXPOSURE_TIME: u16 = 0x829a;
+const F_NUMBER: u16 = 0x829d;
+const ISO: u16 = 0x8827;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const FOCAL_LENGTH: u16 = 0x920a;
+
+/// The tags we show. Everything is optional, as cameras and editors vary in what they write.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Exif {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// In seconds.
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// In mm.
+    pub focal_length: Option<f64>,
+    /// When the photo was taken, as `YYYY:MM:DD HH:MM:SS`.
+    pub taken: Option<String>,
+    /// When the file was last changed by the camera or an editor.
+    pub modified: Option<String>,
+    /// How to rotate and flip the image to display it, from 1 (as stored) to 8.
+    pub orientation: Option<u16>,
+}
+
+impl Exif {
+    /// The make and model of the camera, if either is given.
+    pub fn camera(&self) -> Option<String> {
+        match (&self.make, &self.model) {
+            // Most cameras repeat the make in the model.
+            (Some(make), Some(model)) if model.starts_with(make.as_str()) => Some(model.clone()),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.clone().or_else(|| model.clone()),
+        }
+    }
+
+    /// Labels and values for the tags that are present.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let mut rows = vec![];
+        if let Some(camera) = self.camera() {
+            rows.push(("Camera", camera));
+        }
+        if let Some(time) = self.exposure_time {
+            let time = if time < 1. && time > 0. {
+                format!("1/{} s", (1. / time).round())
+            } else {
+                format!("{} s", time)
+            };
+            rows.push(("Exposure", time));
+        }
+        if let Some(f) = self.f_number {
+            rows.push(("Aperture", format!("f/{:.1}", f)));
+        }
+        if let Some(iso) = self.iso {
+            rows.push(("ISO", iso.to_string()));
+        }
+        if let Some(focal_length) = self.focal_length {
+            rows.push(("Focal length", format!("{:.0} mm", focal_length)));
+        }
+        if let Some(taken) = &self.taken {
+            rows.push(("Taken", taken.clone()));
+        }
+        if let Some(modified) = &self.modified {
+            rows.push(("Modified", modified.clone()));
+        }
+        rows
+    }
+}
+
+/// Read the EXIF tags of the image at `path`, if it has any.
+pub fn read(path: &Path) -> Option<Exif> {
+    let bytes = fs::read(path).ok()?;
+    let block = if bytes.starts_with(&[0xff, 0xd8]) {
+        jpeg_exif(&bytes)?
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_exif(&bytes)?
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        webp_exif(&bytes)?
+    } else {
+        &bytes
+    };
+    parse(block)
+}
+
+/// Read the tags from an EXIF block.
+pub fn parse(block: &[u8]) -> Option<Exif> {
+    // Some writers leave the APP1 identifier on the front.
+    let block = block.strip_prefix(b"Exif\0\0").unwrap_or(block);
+    let tiff = Tiff::new(block)?;
+    let (ifd0, _) = tiff.ifd(tiff.first_ifd()?)?;
+    let find = |entries: &[Entry], tag| entries.iter().find(|entry| entry.tag == tag).copied();
+    let mut exif = Exif {
+        make: find(&ifd0, MAKE).and_then(|e| tiff.ascii(&e)),
+        model: find(&ifd0, MODEL).and_then(|e| tiff.ascii(&e)),
+        modified: find(&ifd0, DATE_TIME).and_then(|e| tiff.ascii(&e)),
+        orientation: find(&ifd0, ORIENTATION)
+            .and_then(|e| tiff.int(&e))
+            .map(|o| o as u16),
+        ..Exif::default()
+    };
+    // The camera settings are in a sub-IFD.
+    let sub_ifd = find(&ifd0, EXIF_IFD)
+        .and_then(|e| tiff.int(&e))
+        .and_then(|pos| tiff.ifd(pos as usize));
+    if let Some((entries, _)) = sub_ifd {
+        exif.exposure_time = find(&entries, EXPOSURE_TIME).and_then(|e| tiff.rational(&e));
+        exif.f_number = find(&entries, F_NUMBER).and_then(|e| tiff.rational(&e));
+        exif.iso = find(&entries, ISO).and_then(|e| tiff.int(&e));
+        exif.focal_length = find(&entries, FOCAL_LENGTH).and_then(|e| tiff.rational(&e));
+        exif.taken = find(&entries, DATE_TIME_ORIGINAL).and_then(|e| tiff.ascii(&e));
+    }
+    Some(exif)
+}
+
+/// The EXIF block is in the APP1 segment that starts with `Exif\0\0`.
+fn jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xff {
+        let marker = bytes[pos + 1];
+        // Start of scan: there are no more metadata segments.
+        if marker == 0xda {
+            break;
+        }
+        let len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// PNG allows the `eXIf` chunk after the image data, so we look through every chunk.
+fn png_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 8;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + len)?;
+        match &bytes[pos + 4..pos + 8] {
+            b"eXIf" => return Some(data),
+            b"IEND" => return None,
+            _ => (),
+        }
+        // Length, type, data and CRC.
+        pos += 12 + len;
+    }
+    None
+}
+
+fn webp_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        if &bytes[pos..pos + 4] == b"EXIF" {
+            return bytes.get(pos + 8..pos + 8 + len);
+        }
+        // Chunks are padded to an even length.
+        pos += 8 + len + (len & 1);
+    }
+    None
+}
+
+/// Rotate and flip `image` as the EXIF orientation says, so it is the right way up.
+///
+/// Orientations are numbered by where the first row and column of the stored image should end
+/// up. 1 is as stored, and unknown values are treated the same.
+pub fn orient(image: ImageBuf, orientation: u16) -> ImageBuf {
+    if !(2..=8).contains(&orientation) {
+        return image;
+    }
+    let (width, height) = (image.width(), image.height());
+    let bpp = image.format().bytes_per_pixel();
+    let src = image.raw_pixels();
+    // 5 to 8 swap the width and height.
+    let (out_w, out_h) = if orientation >= 5 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let mut out = Vec::with_capacity(src.len());
+    for y in 0..out_h {
+        for x in 0..out_w {
+            let (sx, sy) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                _ => (width - 1 - y, x),
+            };
+            let pos = (sy * width + sx) * bpp;
+            out.extend_from_slice(&src[pos..pos + bpp]);
+        }
+    }
+    ImageBuf::from_raw(out, image.format(), out_w, out_h)
+}
@@ -0,0 +1,284 @@
+//! Per-pixel expressions, for quick diagnostics like `a*1.2 + 0.05` or `sqrt(a)`.
+//!
+//! `a` is the value of each colour channel, from 0 to 1, and the result is clamped back to that
+//! range. Alpha is left alone. Since `a` is the only input, an expression is just a curve, and is
+//! applied with a lookup table.
+use druid::{piet::ImageFormat, ImageBuf};
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+use crate::pixel_ops;
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    /// The channel value.
+    A,
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Func {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Min,
+    Max,
+    Clamp,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Func::Abs,
+            "sqrt" => Func::Sqrt,
+            "exp" => Func::Exp,
+            "ln" => Func::Ln,
+            "min" => Func::Min,
+            "max" => Func::Max,
+            "clamp" => Func::Clamp,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Func::Abs | Func::Sqrt | Func::Exp | Func::Ln => 1,
+            Func::Min | Func::Max => 2,
+            Func::Clamp => 3,
+        }
+    }
+}
+
+/// Why an expression couldn't be parsed, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The byte offset in the source.
+    pub pos: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at {})", self.msg, self.pos)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            src,
+            chars: src.char_indices().peekable(),
+        };
+        let expr = parser.expr()?;
+        parser.skip_whitespace();
+        match parser.chars.peek() {
+            None => Ok(expr),
+            Some(&(pos, c)) => Err(parser.error(pos, format!("unexpected `{}`", c))),
+        }
+    }
+
+    /// Evaluate the expression for channel value `a`.
+    pub fn eval(&self, a: f64) -> f64 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::A => a,
+            Expr::Neg(e) => -e.eval(a),
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(a), r.eval(a));
+                match op {
+                    BinOp::Add => l + r,
+                    BinOp::Sub => l - r,
+                    BinOp::Mul => l * r,
+                    BinOp::Div => l / r,
+                    BinOp::Pow => l.powf(r),
+                }
+            }
+            Expr::Call(func, args) => {
+                let arg = |idx: usize| args[idx].eval(a);
+                match func {
+                    Func::Abs => arg(0).abs(),
+                    Func::Sqrt => arg(0).sqrt(),
+                    Func::Exp => arg(0).exp(),
+                    Func::Ln => arg(0).ln(),
+                    Func::Min => arg(0).min(arg(1)),
+                    Func::Max => arg(0).max(arg(1)),
+                    // `f64::clamp` panics if the bounds are the wrong way round.
+                    Func::Clamp => arg(0).max(arg(1)).min(arg(2)),
+                }
+            }
+        }
+    }
+
+    /// The result for every 8-bit input. Results that aren't numbers (like `sqrt(-1)`) become 0.
+    pub fn lut(&self) -> [u8; 256] {
+        let mut lut = [0; 256];
+        for (idx, out) in lut.iter_mut().enumerate() {
+            let v = self.eval(idx as f64 / 255.);
+            // `as` saturates, and turns NaN into 0.
+            *out = (v.max(0.).min(1.) * 255.).round() as u8;
+        }
+        lut
+    }
+
+    /// Apply the expression to every colour channel of `image`.
+    ///
+    /// Premultiplied images are treated as if they were opaque.
+    pub fn apply(&self, image: &ImageBuf) -> ImageBuf {
+        let mut pixels = pixel_ops::to_rgba(image).into_owned();
+        pixel_ops::apply_lut(&mut pixels, &self.lut());
+        let format = match image.format() {
+            ImageFormat::RgbaPremul => ImageFormat::RgbaPremul,
+            _ => ImageFormat::RgbaSeparate,
+        };
+        ImageBuf::from_raw(pixels, format, image.width(), image.height())
+    }
+}
+
+/// A recursive descent parser. In order of increasing precedence we have `+ -`, `* /`, unary
+/// `-`, and `^` (which is right associative).
+struct Parser<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.term()?;
+        while let Some(op) = self.eat_op(&[('+', BinOp::Add), ('-', BinOp::Sub)]) {
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.eat_op(&[('*', BinOp::Mul), ('/', BinOp::Div)]) {
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        let base = self.atom()?;
+        if self.eat('^') {
+            return Ok(Expr::Binary(
+                BinOp::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        self.skip_whitespace();
+        let (start, c) = match self.chars.peek() {
+            Some(&next) => next,
+            None => return Err(self.error(self.src.len(), "unexpected end of expression")),
+        };
+        if c == '(' {
+            self.chars.next();
+            let inner = self.expr()?;
+            self.expect(')')?;
+            Ok(inner)
+        } else if c.is_ascii_digit() || c == '.' {
+            let text = self.take_while(|c| c.is_ascii_digit() || c == '.');
+            text.parse()
+                .map(Expr::Num)
+                .map_err(|_| self.error(start, format!("`{}` is not a number", text)))
+        } else if c.is_ascii_alphabetic() {
+            let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            match name {
+                "a" => Ok(Expr::A),
+                "b" => Err(self.error(start, "`b` is only available when comparing two images")),
+                _ => {
+                    let func = Func::from_name(name)
+                        .ok_or_else(|| self.error(start, format!("unknown name `{}`", name)))?;
+                    let args = self.args()?;
+                    if args.len() != func.arity() {
+                        return Err(self.error(
+                            start,
+                            format!("`{}` takes {} argument(s)", name, func.arity()),
+                        ));
+                    }
+                    Ok(Expr::Call(func, args))
+                }
+            }
+        } else {
+            Err(self.error(start, format!("unexpected `{}`", c)))
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.expect('(')?;
+        let mut args = vec![self.expr()?];
+        while self.eat(',') {
+            args.push(self.expr()?);
+        }
+        self.expect(')')?;
+        Ok(args)
+    }
+
+    fn eat_op(&mut self, ops: &[(char, BinOp)]) -> Option<BinOp> {
+        ops.iter().find(|(c, _)| self.eat(*c)).map(|(_, op)| *op)
+    }
+
+    /// Consume `c` if it is next, ignoring whitespace.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if matches!(self.chars.peek(), Some(&(_, next)) if next == c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat(c) {
+            return Ok(());
+        }
+        let pos = self.chars.peek().map_or(self.src.len(), |&(pos, _)| pos);
+        Err(self.error(pos, format!("expected `{}`", c)))
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.chars.peek().map_or(self.src.len(), |&(pos, _)| pos);
+        while matches!(self.chars.peek(), Some(&(_, c)) if f(c)) {
+            self.chars.next();
+        }
+        let end = self.chars.peek().map_or(self.src.len(), |&(pos, _)| pos);
+        &self.src[start..end]
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(&(_, c)) if c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn error(&self, pos: usize, msg: impl Into<String>) -> ParseError {
+        ParseError {
+            pos,
+            msg: msg.into(),
+        }
+    }
+}
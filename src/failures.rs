@@ -0,0 +1,96 @@
+//! The files that couldn't be loaded this session, and why.
+//!
+//! The list can be reviewed in its own panel, and, if the user asks, stepping through a folder
+//! passes over these files instead of stopping on the error each time.
+use druid::{
+    widget::{prelude::*, Button, Checkbox, CrossAxisAlignment, Flex, Label, ViewSwitcher},
+    ArcStr, Color, Data, Lens, WidgetExt,
+};
+use std::{path::Path, sync::Arc};
+
+#[derive(Debug, Clone, Data)]
+pub struct Failure {
+    pub path: Arc<Path>,
+    pub error: ArcStr,
+}
+
+#[derive(Debug, Clone, Default, Data, Lens)]
+pub struct Failures {
+    /// Most recent first. Each file is in here once, with the last error it gave.
+    pub entries: Arc<Vec<Failure>>,
+    /// Whether next and previous pass over these files.
+    pub skip: bool,
+    /// Whether the panel is open.
+    pub show: bool,
+}
+
+impl Failures {
+    pub fn record(&mut self, path: &Path, error: impl Into<ArcStr>) {
+        let entries = Arc::make_mut(&mut self.entries);
+        entries.retain(|failure| &*failure.path != path);
+        entries.insert(
+            0,
+            Failure {
+                path: path.into(),
+                error: error.into(),
+            },
+        );
+    }
+
+    /// Take `path` off the list, because it has loaded since.
+    pub fn forget(&mut self, path: &Path) {
+        if self.entries.iter().any(|failure| &*failure.path == path) {
+            Arc::make_mut(&mut self.entries).retain(|failure| &*failure.path != path);
+        }
+    }
+
+    /// Whether navigation should pass over `path`.
+    pub fn skips(&self, path: &Path) -> bool {
+        self.skip && self.entries.iter().any(|failure| &*failure.path == path)
+    }
+}
+
+/// The files that failed, with their errors.
+pub fn panel() -> impl Widget<Failures> {
+    let entries = ViewSwitcher::new(
+        |data: &Failures, _| data.entries.clone(),
+        |entries, _, _| {
+            let mut column = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+            if entries.is_empty() {
+                column.add_child(Label::new("Every file has loaded"));
+            }
+            for failure in entries.iter() {
+                let name = failure.path.file_name().unwrap_or(failure.path.as_os_str());
+                column.add_child(
+                    Label::new(name.to_string_lossy().into_owned())
+                        .with_text_color(Color::rgb8(0xff, 0x80, 0x80)),
+                );
+                column.add_child(
+                    Label::new(failure.error.clone())
+                        .with_text_size(11.)
+                        .with_text_color(Color::grey8(0xa0)),
+                );
+                column.add_spacer(4.);
+            }
+            Box::new(column)
+        },
+    );
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Files that failed to load"))
+                .with_flex_spacer(1.)
+                .with_child(Checkbox::new("Skip them").lens(Failures::skip))
+                .with_spacer(4.)
+                .with_child(Button::new("Clear").on_click(|_, data: &mut Failures, _| {
+                    data.entries = Arc::new(vec![]);
+                }))
+                .with_child(
+                    Button::new("×").on_click(|_, data: &mut Failures, _| data.show = false),
+                ),
+        )
+        .with_spacer(4.)
+        .with_child(entries)
+        .padding(4.)
+}
@@ -0,0 +1,257 @@
+//! A minimal FITS reader for astronomical images, and the stretches used to view them.
+//!
+//! Like DICOM, the samples have far more range than a screen, so we keep them and render
+//! through a stretch the user can change.
+use druid::{piet::ImageFormat, Data, ImageBuf, Lens};
+use std::{error::Error, fmt, fs, path::Path};
+
+/// FITS files are made of blocks of this many bytes.
+const BLOCK_SIZE: usize = 2880;
+/// Each header card is this many bytes.
+const CARD_SIZE: usize = 80;
+/// How many samples we keep sorted, for percentiles and zscale.
+const SORTED_SAMPLES: usize = 10_000;
+/// The contrast parameter for zscale, as IRAF uses.
+const ZSCALE_CONTRAST: f64 = 0.25;
+
+type FitsResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// How sample values are mapped to brightness.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Data)]
+pub enum StretchKind {
+    Linear,
+    /// Brings up faint detail.
+    Log,
+    /// Like log for bright values but linear near black, so noise isn't amplified as much.
+    Asinh,
+    /// Linear between limits picked from the image statistics, ignoring the black and white
+    /// points. Good for seeing the background sky.
+    ZScale,
+}
+
+/// The stretch, and the black and white points as percentiles of the sample histogram.
+#[derive(Debug, Copy, Clone, PartialEq, Data, Lens)]
+pub struct Stretch {
+    pub kind: StretchKind,
+    /// In `0..=1`.
+    pub black: f64,
+    /// In `0..=1`.
+    pub white: f64,
+}
+
+impl Default for Stretch {
+    fn default() -> Self {
+        Self {
+            kind: StretchKind::Asinh,
+            black: 0.01,
+            white: 0.999,
+        }
+    }
+}
+
+/// The first image in a FITS file.
+pub struct FitsImage {
+    width: usize,
+    height: usize,
+    /// Physical values, top row first.
+    samples: Vec<f32>,
+    /// A sorted subsample of the finite values, to look up percentiles in.
+    sorted: Vec<f32>,
+    /// The limits zscale picks.
+    zscale: (f64, f64),
+}
+
+impl FitsImage {
+    /// The sample value at percentile `p` (in `0..=1`).
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.sorted.is_empty() {
+            return 0.;
+        }
+        let idx = (p.clamp(0., 1.) * (self.sorted.len() - 1) as f64).round() as usize;
+        self.sorted[idx] as f64
+    }
+
+    /// The sample values that `stretch` maps to black and white.
+    pub fn limits(&self, stretch: Stretch) -> (f64, f64) {
+        match stretch.kind {
+            StretchKind::ZScale => self.zscale,
+            _ => (
+                self.percentile(stretch.black),
+                self.percentile(stretch.white),
+            ),
+        }
+    }
+
+    pub fn render(&self, stretch: Stretch) -> ImageBuf {
+        let (lo, hi) = self.limits(stretch);
+        let range = (hi - lo).max(f64::EPSILON);
+        let curve = |t: f64| match stretch.kind {
+            StretchKind::Linear | StretchKind::ZScale => t,
+            StretchKind::Log => (1. + 1000. * t).ln() / 1001f64.ln(),
+            StretchKind::Asinh => (10. * t).asinh() / 10f64.asinh(),
+        };
+        let pixels = self
+            .samples
+            .iter()
+            .map(|&v| {
+                // NaN marks missing data, which we show as black.
+                let t = ((v as f64 - lo) / range).clamp(0., 1.);
+                if t.is_nan() {
+                    0
+                } else {
+                    (curve(t) * 255.).round() as u8
+                }
+            })
+            .collect();
+        ImageBuf::from_raw(pixels, ImageFormat::Grayscale, self.width, self.height)
+    }
+}
+
+impl fmt::Debug for FitsImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FitsImage")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("zscale", &self.zscale)
+            .finish()
+    }
+}
+
+/// Whether `path` looks like a FITS file we should read with this module.
+pub fn is_fits(path: &Path) -> bool {
+    cfg!(feature = "fits")
+        && path.extension().map_or(false, |ext| {
+            ["fits", "fit", "fts"]
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Read the primary image of the FITS file at `path`. Only the first plane of a cube is read.
+pub fn open(path: &Path) -> FitsResult<FitsImage> {
+    let bytes = fs::read(path)?;
+    let mut header = vec![];
+    let mut data_start = None;
+    for (idx, card) in bytes.chunks_exact(CARD_SIZE).enumerate() {
+        let card = String::from_utf8_lossy(card);
+        let key = card.get(..8).unwrap_or(&card).trim();
+        if key == "END" {
+            // The data starts at the next block.
+            let header_len = (idx + 1) * CARD_SIZE;
+            data_start = Some((header_len + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE);
+            break;
+        }
+        if card.get(8..10) == Some("= ") {
+            // Drop any comment. Strings could contain '/', but we only read numbers.
+            let value = card.get(10..).unwrap_or("").split('/').next().unwrap_or("");
+            let value = value.trim();
+            header.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    let data_start = data_start.ok_or("FITS header has no END")?;
+    let int = |key: &str| -> Option<i64> {
+        header
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.parse().ok())
+    };
+    let float = |key: &str, default: f64| -> f64 {
+        header
+            .iter()
+            .find(|(k, _)| k == key)
+            // Fortran style exponents are allowed.
+            .and_then(|(_, v)| v.replace('D', "E").parse().ok())
+            .unwrap_or(default)
+    };
+    if int("NAXIS").unwrap_or(0) < 2 {
+        return Err("FITS file has no image".into());
+    }
+    let width = int("NAXIS1").ok_or("missing NAXIS1")? as usize;
+    let height = int("NAXIS2").ok_or("missing NAXIS2")? as usize;
+    let bitpix = int("BITPIX").ok_or("missing BITPIX")?;
+    let (bzero, bscale) = (float("BZERO", 0.), float("BSCALE", 1.));
+
+    let count = width * height;
+    if count == 0 {
+        return Err("image is empty".into());
+    }
+    let bytes_per_sample = (bitpix.unsigned_abs() / 8) as usize;
+    let data = bytes
+        .get(data_start..data_start + count * bytes_per_sample)
+        .ok_or("FITS data is truncated")?;
+    // Samples are big-endian.
+    let raw: Vec<f64> = match bitpix {
+        8 => data.iter().map(|&v| v as f64).collect(),
+        16 => data
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        32 => data
+            .chunks_exact(4)
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        -32 => data
+            .chunks_exact(4)
+            .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        -64 => data
+            .chunks_exact(8)
+            .map(|b| f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .collect(),
+        _ => return Err(format!("unsupported BITPIX: {}", bitpix).into()),
+    };
+
+    // FITS images start at the bottom row.
+    let mut samples = Vec::with_capacity(count);
+    for row in raw.chunks_exact(width).rev() {
+        samples.extend(row.iter().map(|&v| (bzero + bscale * v) as f32));
+    }
+
+    let step = (count / SORTED_SAMPLES).max(1);
+    let mut sorted: Vec<f32> = samples
+        .iter()
+        .step_by(step)
+        .copied()
+        .filter(|v| v.is_finite())
+        .collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let zscale = zscale(&sorted);
+    Ok(FitsImage {
+        width,
+        height,
+        samples,
+        sorted,
+        zscale,
+    })
+}
+
+/// IRAF's zscale: fit a line to the sorted samples, and take limits around the median that
+/// follow its slope, steepened by the contrast.
+///
+/// This leaves out IRAF's iterative rejection of outliers, and instead fits only the middle half
+/// of the samples, which are rarely outliers.
+fn zscale(sorted: &[f32]) -> (f64, f64) {
+    let n = sorted.len();
+    if n < 4 {
+        let lo = sorted.first().copied().unwrap_or(0.) as f64;
+        let hi = sorted.last().copied().unwrap_or(1.) as f64;
+        return (lo, hi);
+    }
+    let (start, end) = (n / 4, n - n / 4);
+    let points = &sorted[start..end];
+    let count = points.len() as f64;
+    let mean_x = (start + end - 1) as f64 / 2.;
+    let mean_y = points.iter().map(|&v| v as f64).sum::<f64>() / count;
+    let (mut cov, mut var) = (0., 0.);
+    for (i, &v) in points.iter().enumerate() {
+        let dx = (start + i) as f64 - mean_x;
+        cov += dx * (v as f64 - mean_y);
+        var += dx * dx;
+    }
+    let slope = cov / var / ZSCALE_CONTRAST;
+    let median = sorted[n / 2] as f64;
+    let center = (n / 2) as f64;
+    let z1 = (median - center * slope).max(sorted[0] as f64);
+    let z2 = (median + (n as f64 - 1. - center) * slope).min(sorted[n - 1] as f64);
+    (z1, z2)
+}
@@ -0,0 +1,174 @@
+//! Display settings for the images in one folder, read from a `.image-viewer.toml` in it.
+//!
+//! ```toml
+//! sort = "natural"     # name (the default), natural, modified or size
+//! reverse = false
+//! zoom = "fit-width"   # as --scale takes
+//! background = "#000000"   # or black, grey or white
+//! direction = "rtl"    # ltr (the default) or rtl, which swaps the left and right arrow keys
+//! ```
+use druid::Color;
+use serde::Deserialize;
+use std::{
+    cmp::Ordering,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{cli, profile::InitialZoom};
+
+/// The name of the settings file.
+pub const FILE_NAME: &str = ".image-viewer.toml";
+
+/// The order to step through a folder's images in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    /// By file name.
+    Name,
+    /// By file name, comparing runs of digits as numbers, so `page2` comes before `page10`.
+    Natural,
+    /// Oldest first, by modified time.
+    Modified,
+    /// Smallest file first.
+    Size,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::Name
+    }
+}
+
+/// Which way the pages of the folder are read.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    LeftToRight,
+    /// As manga are read: the right arrow goes back, and the left arrow goes on.
+    RightToLeft,
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Direction::LeftToRight
+    }
+}
+
+/// The file as written.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    sort: SortOrder,
+    reverse: bool,
+    zoom: Option<String>,
+    background: Option<String>,
+    direction: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FolderConfig {
+    pub sort: SortOrder,
+    pub reverse: bool,
+    /// How to zoom the folder's images, if not as the profile says.
+    pub zoom: Option<InitialZoom>,
+    /// What to draw behind the folder's images, if not as the profile says.
+    pub background: Option<Color>,
+    pub direction: Direction,
+}
+
+impl FolderConfig {
+    /// Read the settings for `dir`. A folder without a settings file gets the defaults.
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let text = match fs::read_to_string(dir.join(FILE_NAME)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let file: File = toml::from_str(&text)?;
+        Ok(Self {
+            sort: file.sort,
+            reverse: file.reverse,
+            zoom: file.zoom.as_deref().map(cli::parse_scale).transpose()?,
+            background: file
+                .background
+                .as_deref()
+                .map(cli::parse_color)
+                .transpose()?,
+            direction: match file.direction.as_deref() {
+                None | Some("ltr") => Direction::LeftToRight,
+                Some("rtl") => Direction::RightToLeft,
+                Some(other) => {
+                    return Err(format!("expected direction ltr or rtl, got {:?}", other).into())
+                }
+            },
+        })
+    }
+
+    /// Put `paths` in the order the settings ask for.
+    pub fn sort(&self, paths: &mut [PathBuf]) {
+        match self.sort {
+            SortOrder::Name => paths.sort(),
+            SortOrder::Natural => {
+                paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()))
+            }
+            SortOrder::Modified => {
+                paths.sort_by_cached_key(|path| {
+                    (
+                        fs::metadata(path).and_then(|meta| meta.modified()).ok(),
+                        path.clone(),
+                    )
+                });
+            }
+            SortOrder::Size => {
+                paths.sort_by_cached_key(|path| {
+                    (
+                        fs::metadata(path).map_or(0, |meta| meta.len()),
+                        path.clone(),
+                    )
+                });
+            }
+        }
+        if self.reverse {
+            paths.reverse();
+        }
+    }
+}
+
+/// Compare `a` and `b` as text, except that runs of digits are compared by their value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let b_len = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let (a_num, b_num) = (
+                    a[..a_len].trim_start_matches('0'),
+                    b[..b_len].trim_start_matches('0'),
+                );
+                // Without leading zeros, a longer number is a bigger one.
+                let order = a_num
+                    .len()
+                    .cmp(&b_num.len())
+                    .then_with(|| a_num.cmp(b_num))
+                    .then_with(|| a_len.cmp(&b_len));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+        }
+    }
+}
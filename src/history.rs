@@ -0,0 +1,67 @@
+//! Browser-style history of the images the user has viewed.
+use std::path::{Path, PathBuf};
+
+/// Forget the oldest entries once we have this many.
+const MAX_ENTRIES: usize = 1000;
+
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<PathBuf>,
+    /// The index of the image being viewed, or `None` if we haven't viewed any.
+    current: Option<usize>,
+}
+
+impl History {
+    /// Start with `entries` in the history, positioned at the first.
+    pub fn with_entries(mut entries: Vec<PathBuf>) -> Self {
+        entries.truncate(MAX_ENTRIES);
+        Self {
+            current: if entries.is_empty() { None } else { Some(0) },
+            entries,
+        }
+    }
+
+    /// The image being viewed.
+    pub fn current(&self) -> Option<&Path> {
+        self.current.map(|idx| self.entries[idx].as_path())
+    }
+
+    /// Record that the user is now viewing `path`.
+    ///
+    /// Like a browser, this discards anything ahead of the current position.
+    pub fn push(&mut self, path: PathBuf) {
+        if let Some(current) = self.current {
+            if self.entries[current] == path {
+                return;
+            }
+            self.entries.truncate(current + 1);
+        }
+        self.entries.push(path);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.current = Some(self.entries.len() - 1);
+    }
+
+    /// Step back, returning the image to show, or `None` if we are at the start.
+    pub fn back(&mut self) -> Option<&Path> {
+        match self.current {
+            Some(current) if current > 0 => {
+                self.current = Some(current - 1);
+                Some(&self.entries[current - 1])
+            }
+            _ => None,
+        }
+    }
+
+    /// Step forward, returning the image to show, or `None` if we are at the end.
+    pub fn forward(&mut self) -> Option<&Path> {
+        match self.current {
+            Some(current) if current + 1 < self.entries.len() => {
+                self.current = Some(current + 1);
+                Some(&self.entries[current + 1])
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Checking images against checksums stored alongside them.
+//!
+//! We read the format `sha256sum` and `md5sum` write, either from a sidecar next to the image
+//! (`photo.jpg.sha256`) or from a manifest covering the whole folder (`SHA256SUMS`). Sidecars take
+//! precedence over manifests.
+use druid::{Data, Selector};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::OsStr,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::library;
+
+/// Write a checksum manifest for the folder of the current image.
+pub const WRITE_MANIFEST: Selector = Selector::new("image-viewer.write-manifest");
+
+/// The manifest we write.
+const MANIFEST_NAME: &str = "SHA256SUMS";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Algorithm {
+    Sha256,
+    Md5,
+}
+
+/// Sidecar extensions we look for, in order of preference.
+const SIDECARS: &[(&str, Algorithm)] = &[("sha256", Algorithm::Sha256), ("md5", Algorithm::Md5)];
+/// Manifest file names we look for, in order of preference.
+const MANIFESTS: &[(&str, Algorithm)] = &[
+    ("SHA256SUMS", Algorithm::Sha256),
+    ("MD5SUMS", Algorithm::Md5),
+];
+
+/// Whether an image matches its recorded checksum.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Data)]
+pub enum Integrity {
+    /// We couldn't find a checksum for the image.
+    Unknown,
+    Verified,
+    Corrupt,
+}
+
+impl Default for Integrity {
+    fn default() -> Self {
+        Integrity::Unknown
+    }
+}
+
+/// Check the file at `path` against any checksum we can find for it.
+pub fn verify(path: &Path) -> io::Result<Integrity> {
+    let (algorithm, expected) = match find_checksum(path)? {
+        Some(found) => found,
+        None => return Ok(Integrity::Unknown),
+    };
+    let actual = hash_file(path, algorithm)?;
+    Ok(if actual.eq_ignore_ascii_case(&expected) {
+        Integrity::Verified
+    } else {
+        Integrity::Corrupt
+    })
+}
+
+/// Where `write_manifest` writes the manifest for `dir`.
+pub fn manifest_path(dir: &Path) -> PathBuf {
+    dir.join(MANIFEST_NAME)
+}
+
+/// Write a `SHA256SUMS` manifest for the images in `dir`, returning its path.
+pub fn write_manifest(dir: &Path) -> io::Result<PathBuf> {
+    let mut names = vec![];
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && library::is_image(&entry.path()) {
+            names.push(entry.file_name());
+        }
+    }
+    names.sort();
+
+    let mut manifest = String::new();
+    for name in names {
+        let hash = hash_file(&dir.join(&name), Algorithm::Sha256)?;
+        // Writing to a `String` can't fail.
+        let _ = writeln!(manifest, "{}  {}", hash, name.to_string_lossy());
+    }
+    let path = manifest_path(dir);
+    fs::write(&path, manifest)?;
+    Ok(path)
+}
+
+/// Look for a sidecar or manifest entry for `path`.
+fn find_checksum(path: &Path) -> io::Result<Option<(Algorithm, String)>> {
+    let name = match path.file_name().and_then(OsStr::to_str) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    for &(ext, algorithm) in SIDECARS {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".");
+        sidecar.push(ext);
+        // A sidecar may be `sha256sum` output or just the bare hash; either way it comes first.
+        if let Some(contents) = read_if_exists(Path::new(&sidecar))? {
+            if let Some(hash) = contents.split_whitespace().next() {
+                return Ok(Some((algorithm, hash.to_owned())));
+            }
+        }
+    }
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for &(manifest, algorithm) in MANIFESTS {
+        if let Some(contents) = read_if_exists(&dir.join(manifest))? {
+            if let Some(hash) = find_in_manifest(&contents, name) {
+                return Ok(Some((algorithm, hash.to_owned())));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Find the hash for `name` in the lines of a manifest.
+///
+/// Lines are `<hash>  <name>`, or `<hash> *<name>` for files hashed in binary mode.
+fn find_in_manifest<'a>(contents: &'a str, name: &str) -> Option<&'a str> {
+    contents.lines().find_map(|line| {
+        let (hash, rest) = line.split_once(char::is_whitespace)?;
+        let rest = rest.trim_start_matches(' ');
+        let entry = rest.strip_prefix('*').unwrap_or(rest);
+        let entry = entry.strip_prefix("./").unwrap_or(entry);
+        (entry == name).then(|| hash)
+    })
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn hash_file(path: &Path, algorithm: Algorithm) -> io::Result<String> {
+    match algorithm {
+        Algorithm::Sha256 => hash_with::<Sha256>(path),
+        Algorithm::Md5 => hash_with::<Md5>(path),
+    }
+}
+
+fn hash_with<D: Digest + io::Write>(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = D::new();
+    io::copy(&mut file, &mut hasher)?;
+    let mut hex = String::new();
+    for byte in hasher.finalize() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    Ok(hex)
+}
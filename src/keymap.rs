@@ -0,0 +1,369 @@
+//! Keyboard shortcuts, which the user can change in the `[keys]` table of the config file.
+//!
+//! Each line binds an action to a key, or to a list of keys, replacing the action's default keys:
+//!
+//! ```toml
+//! [keys]
+//! next = ["Right", "PageDown", "Space"]
+//! zoom-in = "Ctrl+="
+//! rotate-cw = []       # no key
+//! ```
+//!
+//! Keys are written as modifiers and a key joined by `+`, like `Ctrl+Shift+C`. The key is a
+//! character, or a name such as `Left`, `PageUp`, `F11` or `Escape`. Shift is ignored for
+//! characters that aren't letters, since it is usually what typed them: `+` is `+` whether or not
+//! the keyboard needs Shift for it.
+use druid::{KbKey, KeyEvent};
+use std::{fmt, str::FromStr};
+
+/// What a shortcut can do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Next,
+    Prev,
+    HistoryBack,
+    HistoryForward,
+    ZoomIn,
+    ZoomOut,
+    ActualSize,
+    Fit,
+    ZoomToSubject,
+    RotateCw,
+    RotateCcw,
+    FlipH,
+    FlipV,
+    Fullscreen,
+    /// Only does anything in fullscreen, so the key can mean something else the rest of the time.
+    LeaveFullscreen,
+    Metadata,
+    Copy,
+    CopyPath,
+    Paste,
+    Upscale,
+    Slideshow,
+    Theme,
+    Note,
+    ComparePrevious,
+    Inspect,
+    CopyColor,
+    Macros,
+}
+
+impl Action {
+    /// Every action, with the name it has in the file and its default keys.
+    const ALL: &'static [(Action, &'static str, &'static [&'static str])] = &[
+        (Action::Next, "next", &["Right", "PageDown"]),
+        (Action::Prev, "prev", &["Left", "PageUp"]),
+        (Action::HistoryBack, "history-back", &["Alt+Left"]),
+        (Action::HistoryForward, "history-forward", &["Alt+Right"]),
+        (Action::ZoomIn, "zoom-in", &["+", "="]),
+        (Action::ZoomOut, "zoom-out", &["-"]),
+        (Action::ActualSize, "actual-size", &["1"]),
+        (Action::Fit, "fit", &["0"]),
+        (Action::ZoomToSubject, "zoom-to-subject", &["S"]),
+        (Action::RotateCw, "rotate-cw", &["R"]),
+        (Action::RotateCcw, "rotate-ccw", &["Shift+R"]),
+        (Action::FlipH, "flip-h", &["H"]),
+        (Action::FlipV, "flip-v", &["V"]),
+        (Action::Fullscreen, "fullscreen", &["F11"]),
+        (Action::LeaveFullscreen, "leave-fullscreen", &["Escape"]),
+        (Action::Metadata, "metadata", &["Ctrl+I"]),
+        (Action::Copy, "copy", &["Ctrl+C"]),
+        (Action::CopyPath, "copy-path", &["Ctrl+Shift+C"]),
+        (Action::Paste, "paste", &["Ctrl+V"]),
+        (Action::Upscale, "upscale", &["U"]),
+        (Action::Slideshow, "slideshow", &["F5"]),
+        (Action::Theme, "toggle-theme", &["T"]),
+        (Action::Note, "note", &["N"]),
+        (Action::ComparePrevious, "compare-with-previous", &["P"]),
+        (Action::Inspect, "inspect", &["I"]),
+        (Action::CopyColor, "copy-color", &["C"]),
+        (Action::Macros, "macros", &["M"]),
+    ];
+
+    fn name(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(action, ..)| *action == self)
+            .map_or("", |(_, name, _)| name)
+    }
+}
+
+/// Whether `name` is the name of an action, as the `[keys]` table has it.
+pub fn is_action(name: &str) -> bool {
+    Action::ALL.iter().any(|(_, n, _)| *n == name)
+}
+
+/// A key, and the modifiers held with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    meta: bool,
+    /// Letters are kept in lower case.
+    key: KbKey,
+}
+
+impl Chord {
+    fn matches(&self, event: &KeyEvent) -> bool {
+        let key = match &event.key {
+            KbKey::Character(c) => KbKey::Character(c.to_lowercase()),
+            key => key.clone(),
+        };
+        let mods = event.mods;
+        key == self.key
+            && mods.ctrl() == self.ctrl
+            && mods.alt() == self.alt
+            && mods.meta() == self.meta
+            && (mods.shift() == self.shift || !self.shift_matters())
+    }
+
+    /// Whether Shift changes what this chord is. It doesn't for characters like `+`, which
+    /// need Shift on some keyboards and not others.
+    fn shift_matters(&self) -> bool {
+        match &self.key {
+            KbKey::Character(c) => c.chars().any(char::is_alphabetic),
+            _ => true,
+        }
+    }
+
+    /// Whether a text box would want this chord for itself: typing, or one of the editing
+    /// shortcuts.
+    pub fn is_text_editing(&self) -> bool {
+        match &self.key {
+            KbKey::Character(_) if !self.ctrl && !self.alt && !self.meta => true,
+            KbKey::Character(c) => self.ctrl && ["a", "c", "v", "x", "z"].contains(&c.as_str()),
+            KbKey::Backspace | KbKey::Delete => true,
+            _ => false,
+        }
+    }
+
+    /// Whether a multi-line text box would want this chord for moving around its text, or
+    /// starting a new line.
+    pub fn is_text_navigation(&self) -> bool {
+        let moves = matches!(
+            self.key,
+            KbKey::ArrowLeft
+                | KbKey::ArrowRight
+                | KbKey::ArrowUp
+                | KbKey::ArrowDown
+                | KbKey::Home
+                | KbKey::End
+                | KbKey::PageUp
+                | KbKey::PageDown
+                | KbKey::Enter
+        );
+        // Shift selects as it moves.
+        moves && !self.ctrl && !self.alt && !self.meta
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // `+` joins the parts, but can also be the key.
+        let (mods, key) = if s == "+" {
+            ("", "+")
+        } else if let Some(mods) = s.strip_suffix("++") {
+            (mods, "+")
+        } else {
+            s.rsplit_once('+').unwrap_or(("", s))
+        };
+        let mut chord = Chord {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            meta: false,
+            key: parse_key(key.trim())
+                .ok_or_else(|| format!("unknown key {:?} in {:?}", key, s))?,
+        };
+        for modifier in mods.split('+').map(str::trim).filter(|m| !m.is_empty()) {
+            let held = match &*modifier.to_lowercase() {
+                "ctrl" | "control" => &mut chord.ctrl,
+                "alt" | "option" => &mut chord.alt,
+                "shift" => &mut chord.shift,
+                "meta" | "cmd" | "super" | "win" => &mut chord.meta,
+                _ => return Err(format!("unknown modifier {:?} in {:?}", modifier, s)),
+            };
+            *held = true;
+        }
+        Ok(chord)
+    }
+}
+
+/// The names of the keys that aren't characters. Function keys are handled separately.
+const NAMED_KEYS: &[(&str, KbKey)] = &[
+    ("Left", KbKey::ArrowLeft),
+    ("Right", KbKey::ArrowRight),
+    ("Up", KbKey::ArrowUp),
+    ("Down", KbKey::ArrowDown),
+    ("PageUp", KbKey::PageUp),
+    ("PageDown", KbKey::PageDown),
+    ("Home", KbKey::Home),
+    ("End", KbKey::End),
+    ("Escape", KbKey::Escape),
+    ("Enter", KbKey::Enter),
+    ("Tab", KbKey::Tab),
+    ("Backspace", KbKey::Backspace),
+    ("Delete", KbKey::Delete),
+    ("Insert", KbKey::Insert),
+];
+
+/// Parse a key name, ignoring case.
+fn parse_key(name: &str) -> Option<KbKey> {
+    let lower = name.to_lowercase();
+    if lower == "space" {
+        return Some(KbKey::Character(" ".into()));
+    }
+    if lower.chars().count() == 1 {
+        return Some(KbKey::Character(lower));
+    }
+    if let Some(number) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        return function_key(number);
+    }
+    let alias = match &*lower {
+        "arrowleft" => "left",
+        "arrowright" => "right",
+        "arrowup" => "up",
+        "arrowdown" => "down",
+        "pgup" => "pageup",
+        "pgdn" => "pagedown",
+        "esc" => "escape",
+        "return" => "enter",
+        "del" => "delete",
+        other => other,
+    };
+    NAMED_KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(alias))
+        .map(|(_, key)| key.clone())
+}
+
+fn function_key(number: u8) -> Option<KbKey> {
+    Some(match number {
+        1 => KbKey::F1,
+        2 => KbKey::F2,
+        3 => KbKey::F3,
+        4 => KbKey::F4,
+        5 => KbKey::F5,
+        6 => KbKey::F6,
+        7 => KbKey::F7,
+        8 => KbKey::F8,
+        9 => KbKey::F9,
+        10 => KbKey::F10,
+        11 => KbKey::F11,
+        12 => KbKey::F12,
+        _ => return None,
+    })
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(held, name) in &[
+            (self.ctrl, "Ctrl+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+            (self.meta, "Meta+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        match &self.key {
+            KbKey::Character(c) if c == " " => f.write_str("Space"),
+            KbKey::Character(c) => f.write_str(&c.to_uppercase()),
+            key => match NAMED_KEYS.iter().find(|(_, named)| named == key) {
+                Some((name, _)) => f.write_str(name),
+                None => match (1..=12).find(|&n| function_key(n).as_ref() == Some(key)) {
+                    Some(n) => write!(f, "F{}", n),
+                    None => write!(f, "{:?}", key),
+                },
+            },
+        }
+    }
+}
+
+/// Which action each chord does.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Chord, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = Action::ALL
+            .iter()
+            .flat_map(|&(action, _, keys)| {
+                keys.iter()
+                    .map(move |key| (key.parse().expect("default keys are valid"), action))
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /// The defaults, with the actions in `table` bound to the keys it gives instead. As well as
+    /// the keymap, this returns a description of each problem with the table, such as a key
+    /// bound to two actions, which the user should be told about.
+    pub fn from_table(table: &toml::value::Table) -> (Self, Vec<String>) {
+        let mut problems = vec![];
+        let mut user = vec![];
+        let mut rebound = vec![];
+        for (name, keys) in table {
+            let action = match Action::ALL.iter().find(|(_, n, _)| n == name) {
+                Some(&(action, ..)) => action,
+                None => {
+                    problems.push(format!("unknown action {:?}", name));
+                    continue;
+                }
+            };
+            let keys = match keys {
+                toml::Value::String(key) => vec![key.as_str()],
+                toml::Value::Array(keys) => keys.iter().filter_map(toml::Value::as_str).collect(),
+                _ => {
+                    problems.push(format!("expected a key or a list of keys for {}", name));
+                    continue;
+                }
+            };
+            rebound.push(action);
+            for key in keys {
+                match key.parse() {
+                    Ok(chord) => user.push((chord, action)),
+                    Err(e) => problems.push(e),
+                }
+            }
+        }
+        let defaults = Self::default()
+            .bindings
+            .into_iter()
+            .filter(|(_, action)| !rebound.contains(action));
+        // The user's keys come first, so they win over any default they clash with.
+        let mut bindings: Vec<(Chord, Action)> = vec![];
+        for (chord, action) in user.into_iter().chain(defaults) {
+            match bindings.iter().find(|(bound, _)| *bound == chord) {
+                Some((_, first)) if *first == action => (),
+                Some((_, first)) => problems.push(format!(
+                    "{} is bound to both {} and {}, so only does {}",
+                    chord,
+                    first.name(),
+                    action.name(),
+                    first.name()
+                )),
+                None => bindings.push((chord, action)),
+            }
+        }
+        (Self { bindings }, problems)
+    }
+
+    /// The action for a key press, and the chord it matched.
+    pub fn action(&self, event: &KeyEvent) -> Option<(&Chord, Action)> {
+        self.bindings
+            .iter()
+            .find(|(chord, _)| chord.matches(event))
+            .map(|(chord, action)| (chord, *action))
+    }
+}
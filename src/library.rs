@@ -0,0 +1,137 @@
+//! The list of images we can step through.
+use druid::{Data, Selector};
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{folder_config::FolderConfig, IMAGE_EXTENSIONS};
+
+/// Show the next image in the list.
+pub const NEXT_IMAGE: Selector = Selector::new("image-viewer.next-image");
+/// Show the previous image in the list.
+pub const PREV_IMAGE: Selector = Selector::new("image-viewer.prev-image");
+
+/// The images in a directory, in the order we step through them.
+#[derive(Debug, Clone, Data)]
+pub struct ImageList {
+    paths: Arc<Vec<PathBuf>>,
+    /// The index of the image being shown.
+    current: usize,
+    /// The settings from the folder's settings file.
+    config: Arc<FolderConfig>,
+}
+
+impl ImageList {
+    /// List the images in the same directory as `path`, positioned at `path`.
+    ///
+    /// `path` is included even if it doesn't look like an image, so the list always contains the
+    /// image the user opened.
+    pub fn for_file(path: &Path) -> io::Result<Self> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        // `read_dir("")` fails, but we join onto `dir` rather than using the entries' own paths so
+        // our paths compare equal to the ones the user gave us.
+        let read_from = if dir == Path::new("") {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let mut paths = vec![];
+        for entry in read_from.read_dir()? {
+            let entry = entry?;
+            let entry_path = dir.join(entry.file_name());
+            if entry.file_type()?.is_file() && is_image(&entry_path) {
+                paths.push(entry_path);
+            }
+        }
+        if !paths.iter().any(|p| p == path) {
+            paths.push(path.to_owned());
+        }
+        // A mistake in the settings shouldn't stop us stepping through the folder.
+        let config = FolderConfig::load(read_from).unwrap_or_else(|e| {
+            log::warn!("ignoring settings in {}: {}", read_from.display(), e);
+            FolderConfig::default()
+        });
+        config.sort(&mut paths);
+        let current = paths.iter().position(|p| p == path).unwrap_or(0);
+        Ok(Self {
+            paths: Arc::new(paths),
+            current,
+            config: Arc::new(config),
+        })
+    }
+
+    /// The settings for the folder.
+    pub fn config(&self) -> &FolderConfig {
+        &self.config
+    }
+
+    /// All the images, in order.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// The index of the image being shown.
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    /// Move to `path` if it's in the list. Returns whether it was.
+    pub fn select(&mut self, path: &Path) -> bool {
+        match self.paths.iter().position(|p| p == path) {
+            Some(idx) => {
+                self.current = idx;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move to the next image, returning it, or `None` if we are at the end.
+    pub fn next(&mut self) -> Option<&Path> {
+        if self.current + 1 < self.paths.len() {
+            self.current += 1;
+            Some(&self.paths[self.current])
+        } else {
+            None
+        }
+    }
+
+    /// The images up to `distance` either side of the current one, nearest first, alternating
+    /// forward and back.
+    pub fn neighbours(&self, distance: usize) -> Vec<PathBuf> {
+        let mut out = Vec::with_capacity(distance * 2);
+        for step in 1..=distance {
+            if let Some(next) = self.paths.get(self.current + step) {
+                out.push(next.clone());
+            }
+            if let Some(prev) = self.current.checked_sub(step) {
+                out.push(self.paths[prev].clone());
+            }
+        }
+        out
+    }
+
+    /// Move to the previous image, returning it, or `None` if we are at the start.
+    pub fn prev(&mut self) -> Option<&Path> {
+        if self.current > 0 {
+            self.current -= 1;
+            Some(&self.paths[self.current])
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` has one of the extensions we can open.
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map_or(false, |ext| {
+            IMAGE_EXTENSIONS
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(ext))
+        })
+}
@@ -0,0 +1,865 @@
+//! Loading images without blocking the UI.
+//!
+//! The UI sends requests to the io thread, which watches the open file for changes and hands the
+//! decoding to the decode pool. Results come back to the UI as `FILE_LOADED` commands, so the
+//! previous image stays on screen until the new one is ready.
+//!
+//! While the user looks at an image, the ones either side of it are decoded in the background, so
+//! stepping to them is instant.
+//!
+//! Longer jobs the UI asks for, like writing checksum manifests, also go through here.
+use crossbeam_channel::{self as channel, Receiver, RecvError, Sender};
+use druid::{ExtEventSink, ImageBuf, Selector, SingleUse, Target};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use qu::ick_use::*;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    analysis::{self, Exposure},
+    clipboard::ClipboardImage,
+    color::{self, ColorSpace},
+    decode::{self, AnimatedImage, DecodePool, Priority},
+    dicom::{self, DicomImage},
+    email,
+    encode::{self, EncodeOptions},
+    exif::{self, Exif},
+    fits::{self, FitsImage, Stretch},
+    integrity::{self, Integrity},
+    library::ImageList,
+    notes,
+    pairs::{self, Pair},
+    palette::{self, Swatch},
+    pdf::{self, PdfOptions},
+    stats::FolderStats,
+    svg::{self, SvgImage},
+    upscale::{self, Upscaler},
+    versions,
+    wallpaper::{self, View},
+    widgets,
+};
+
+/// A decoded image, and what we found out about it on the way.
+pub struct Loaded {
+    pub path: PathBuf,
+    /// When the file was last modified, if the platform tells us.
+    pub modified: Option<SystemTime>,
+    pub image: ImageBuf,
+    /// Mip levels for `image`, if it is drawn as it is.
+    pub mips: Vec<ImageBuf>,
+    /// All the frames, if the image is animated.
+    pub animation: Option<AnimatedImage>,
+    /// The full depth samples, if the image is DICOM.
+    pub dicom: Option<DicomImage>,
+    /// The full depth samples, if the image is FITS.
+    pub fits: Option<FitsImage>,
+    /// The source, if the image is an SVG.
+    pub svg: Option<SvgImage>,
+    /// The colour space of the file. If it isn't sRGB, `image` has been converted, and this is
+    /// the image as it was.
+    pub color_space: ColorSpace,
+    pub unconverted: Option<ImageBuf>,
+    pub exposure: Exposure,
+    pub integrity: Integrity,
+    /// The camera settings, if the file has EXIF tags.
+    pub exif: Option<Exif>,
+}
+
+impl Loaded {
+    fn new(image: ImageBuf) -> Self {
+        Self {
+            path: PathBuf::new(),
+            modified: None,
+            image,
+            mips: vec![],
+            animation: None,
+            dicom: None,
+            fits: None,
+            svg: None,
+            color_space: ColorSpace::default(),
+            unconverted: None,
+            exposure: Exposure::default(),
+            integrity: Integrity::default(),
+            exif: None,
+        }
+    }
+}
+
+/// A file that couldn't be loaded, and why.
+#[derive(Debug)]
+pub struct LoadError {
+    pub path: PathBuf,
+    pub error: Box<dyn Error + Send + Sync>,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl Error for LoadError {}
+
+pub type LoadResult = Result<Loaded, LoadError>;
+
+/// Sent to the UI when an image has finished loading (or failed to).
+pub const FILE_LOADED: Selector<SingleUse<LoadResult>> = Selector::new("image-viewer.file-loaded");
+
+/// Sent to the UI with the list of images in the directory of a file it asked about.
+pub const DIR_SCANNED: Selector<SingleUse<ImageList>> = Selector::new("image-viewer.dir-scanned");
+
+/// Sent to the UI when a checksum manifest has been written (or failed to be).
+pub const MANIFEST_WRITTEN: Selector<SingleUse<io::Result<PathBuf>>> =
+    Selector::new("image-viewer.manifest-written");
+
+/// Sent to the UI when a PDF export has finished (or failed).
+pub const PDF_EXPORTED: Selector<SingleUse<Result<PathBuf, Box<dyn Error + Send + Sync>>>> =
+    Selector::new("image-viewer.pdf-exported");
+
+/// Sent to the UI when the mail client has been opened (or couldn't be).
+pub const EMAIL_SENT: Selector<SingleUse<Result<(), Box<dyn Error + Send + Sync>>>> =
+    Selector::new("image-viewer.email-sent");
+
+/// Sent to the UI when an edited image has been saved (or failed to be).
+pub const IMAGE_SAVED: Selector<SingleUse<Result<PathBuf, Box<dyn Error + Send + Sync>>>> =
+    Selector::new("image-viewer.image-saved");
+
+/// Sent to the UI with the upscaled copy of an image (or why there isn't one).
+pub const UPSCALED: Selector<SingleUse<Result<PathBuf, Box<dyn Error + Send + Sync>>>> =
+    Selector::new("image-viewer.upscaled");
+
+/// Sent to the UI when the wallpapers have been written (or failed to be).
+pub const WALLPAPERS_EXPORTED: Selector<
+    SingleUse<Result<wallpaper::Exported, Box<dyn Error + Send + Sync>>>,
+> = Selector::new("image-viewer.wallpapers-exported");
+
+/// Sent to the UI when an image has been encoded for the clipboard (or failed to be).
+pub const CLIPBOARD_IMAGE: Selector<
+    SingleUse<Result<ClipboardImage, Box<dyn Error + Send + Sync>>>,
+> = Selector::new("image-viewer.clipboard-image");
+
+/// Sent to the UI when an image pasted from the clipboard has been decoded (or failed to be).
+pub const PASTED_IMAGE: Selector<SingleUse<Result<Loaded, Box<dyn Error + Send + Sync>>>> =
+    Selector::new("image-viewer.pasted-image");
+
+/// Sent to the UI when an image piped to standard input has been decoded (or failed to be).
+pub const STDIN_IMAGE: Selector<SingleUse<Result<Loaded, Box<dyn Error + Send + Sync>>>> =
+    Selector::new("image-viewer.stdin-image");
+
+/// Sent to the UI with the statistics for a list of images, and the first image in the list.
+pub const FOLDER_STATS: Selector<SingleUse<(Option<PathBuf>, FolderStats)>> =
+    Selector::new("image-viewer.folder-stats");
+
+/// Sent to the UI with the note on an image (or why it couldn't be read).
+pub const NOTE_READ: Selector<SingleUse<(PathBuf, io::Result<String>)>> =
+    Selector::new("image-viewer.note-read");
+
+/// Sent to the UI when the note on an image couldn't be saved.
+pub const NOTE_NOT_SAVED: Selector<SingleUse<(PathBuf, io::Error)>> =
+    Selector::new("image-viewer.note-not-saved");
+
+/// Sent to the UI with an earlier version of an image to compare it with (or why there isn't
+/// one).
+pub const PREVIOUS_VERSION: Selector<
+    SingleUse<(PathBuf, Result<PathBuf, Box<dyn Error + Send + Sync>>)>,
+> = Selector::new("image-viewer.previous-version");
+
+/// Sent to the UI with the pairs of images in two folders, compared.
+pub const PAIRS_COMPARED: Selector<SingleUse<io::Result<Vec<Pair>>>> =
+    Selector::new("image-viewer.pairs-compared");
+
+/// Sent to the UI with the palette of an image, and the image, so a palette for an image that is
+/// no longer shown can be told apart.
+pub const PALETTE_EXTRACTED: Selector<SingleUse<(Arc<ImageBuf>, Vec<Swatch>)>> =
+    Selector::new("image-viewer.palette-extracted");
+
+/// How many images either side of the current one to decode ahead of time.
+pub const PREFETCH_DISTANCE: usize = 2;
+
+/// Messages from the UI to the io thread.
+pub enum UiMsg {
+    LoadImage(PathBuf),
+    /// Watch this file for changes, without loading it, because the UI already has it.
+    Watch(PathBuf),
+    /// Decode these images ahead of time, nearest first, forgetting any others decoded earlier.
+    Prefetch(Vec<PathBuf>),
+    /// List the images next to this one.
+    ScanDir(PathBuf),
+    /// Write a checksum manifest for the images in this directory.
+    WriteManifest(PathBuf),
+    /// Summarize these images for the folder statistics panel.
+    FolderStats(Vec<PathBuf>),
+    /// Find up to `count` dominant colours of an image, for the palette panel.
+    ExtractPalette {
+        image: Arc<ImageBuf>,
+        count: usize,
+    },
+    /// Combine images into a PDF.
+    ExportPdf {
+        images: Vec<PathBuf>,
+        dest: PathBuf,
+        options: PdfOptions,
+    },
+    /// Attach images to a new email, scaling them down to `max_size` if set.
+    SendEmail {
+        images: Vec<PathBuf>,
+        max_size: Option<u32>,
+    },
+    /// Save an edited image, turned as the EXIF `orientation` says, in the format the extension
+    /// of `dest` says.
+    SaveImage {
+        image: Arc<ImageBuf>,
+        orientation: u16,
+        dest: PathBuf,
+        options: EncodeOptions,
+    },
+    /// Make a copy of the image at `path`, `scale` times bigger, unless there is one already.
+    Upscale {
+        path: PathBuf,
+        upscaler: Arc<dyn Upscaler>,
+        scale: u32,
+    },
+    /// Write the part of `image` in `view` at common screen sizes, beside `original`.
+    ExportWallpapers {
+        image: Arc<ImageBuf>,
+        view: View,
+        original: PathBuf,
+        options: EncodeOptions,
+    },
+    /// Encode an image for the clipboard, turned as the EXIF `orientation` says. `path` is the
+    /// file to offer as well, if any.
+    CopyImage {
+        image: Arc<ImageBuf>,
+        orientation: u16,
+        path: Option<PathBuf>,
+    },
+    /// Decode an image pasted from the clipboard.
+    DecodePasted(Vec<u8>),
+    /// Read an image from standard input, until it is closed, and decode it.
+    ReadStdin,
+    /// Read the note on an image.
+    ReadNote(PathBuf),
+    /// Write the note on an image. This is done before any later message is handled, so a note
+    /// saved as the window closes is written before we shut down.
+    SaveNote {
+        image: PathBuf,
+        text: String,
+    },
+    /// Find an earlier version of an image, in git or as a backup beside it.
+    FindPreviousVersion(PathBuf),
+    /// Pair the images in two folders by name, and compare each pair.
+    ComparePairs {
+        old: PathBuf,
+        new: PathBuf,
+    },
+    Shutdown,
+}
+
+/// Start the io thread, returning a channel to send it requests on.
+///
+/// Send `UiMsg::Shutdown` and join the thread before exiting.
+pub fn spawn(evt_sink: ExtEventSink) -> (Sender<UiMsg>, JoinHandle<()>) {
+    let (ui_tx, ui_rx) = channel::unbounded::<UiMsg>();
+    // Set up the file watcher on the io thread, so it doesn't hold up the window appearing.
+    let io_thread = thread::spawn(move || match IoState::new(ui_rx, evt_sink) {
+        Ok(mut io_state) => io_state.run(),
+        Err(e) => log::error!("could not start io thread: {}", e),
+    });
+    (ui_tx, io_thread)
+}
+
+/// State for the i/o thread
+struct IoState {
+    ui_rx: Receiver<UiMsg>,
+    evt_sink: ExtEventSink,
+    open_file: Option<PathBuf>,
+    watcher: RecommendedWatcher,
+    watcher_rx: Receiver<Result<notify::Event, notify::Error>>,
+    decode_pool: DecodePool,
+    /// Bumped every time we start loading an image, so that a slow decode can't overwrite a newer
+    /// one.
+    generation: Arc<AtomicU64>,
+    /// Shared with the prefetch jobs on the decode pool.
+    prefetch: Arc<Mutex<Prefetch>>,
+}
+
+/// Images decoded before they were asked for.
+#[derive(Default)]
+struct Prefetch {
+    /// The images we want ready, nearest first.
+    wanted: Vec<PathBuf>,
+    /// Images queued or being decoded.
+    pending: HashSet<PathBuf>,
+    ready: HashMap<PathBuf, LoadResult>,
+    /// A pending image the UI has asked for in the meantime, and the generation of that load.
+    /// Whoever decodes it sends it straight on.
+    deliver: Option<(PathBuf, u64)>,
+}
+
+impl IoState {
+    fn new(ui_rx: Receiver<UiMsg>, evt_sink: ExtEventSink) -> Result<Self> {
+        let (watcher_tx, watcher_rx) = channel::unbounded();
+        Ok(Self {
+            ui_rx,
+            evt_sink,
+            open_file: None,
+            watcher: notify::recommended_watcher(watcher_tx)?,
+            watcher_rx,
+            decode_pool: DecodePool::new(),
+            generation: Arc::new(AtomicU64::new(0)),
+            prefetch: Arc::new(Mutex::new(Prefetch::default())),
+        })
+    }
+    fn run(&mut self) {
+        loop {
+            channel::select! {
+                recv(self.ui_rx) -> msg => if !self.handle_ui(msg) {
+                    break;
+                },
+                recv(self.watcher_rx) -> msg => if !self.handle_notify(msg) {
+                    break;
+                }
+            }
+        }
+    }
+
+    // returns false on error
+    fn handle_ui(&mut self, msg: Result<UiMsg, RecvError>) -> bool {
+        match msg {
+            Ok(UiMsg::LoadImage(path)) => self.load_img(path),
+            Ok(UiMsg::Watch(path)) => {
+                self.watch(path);
+                true
+            }
+            Ok(UiMsg::Prefetch(paths)) => self.prefetch(paths),
+            Ok(UiMsg::ScanDir(path)) => self.scan_dir(&path),
+            Ok(UiMsg::WriteManifest(dir)) => self.write_manifest(dir),
+            Ok(UiMsg::FolderStats(paths)) => self.folder_stats(paths),
+            Ok(UiMsg::ExtractPalette { image, count }) => self.extract_palette(image, count),
+            Ok(UiMsg::ExportPdf {
+                images,
+                dest,
+                options,
+            }) => self.export_pdf(images, dest, options),
+            Ok(UiMsg::SendEmail { images, max_size }) => self.send_email(images, max_size),
+            Ok(UiMsg::SaveImage {
+                image,
+                orientation,
+                dest,
+                options,
+            }) => self.save_image(image, orientation, dest, options),
+            Ok(UiMsg::Upscale {
+                path,
+                upscaler,
+                scale,
+            }) => self.upscale(path, upscaler, scale),
+            Ok(UiMsg::ExportWallpapers {
+                image,
+                view,
+                original,
+                options,
+            }) => self.export_wallpapers(image, view, original, options),
+            Ok(UiMsg::CopyImage {
+                image,
+                orientation,
+                path,
+            }) => self.copy_image(image, orientation, path),
+            Ok(UiMsg::DecodePasted(data)) => self.decode_pasted(data),
+            Ok(UiMsg::ReadStdin) => self.read_stdin(),
+            Ok(UiMsg::ReadNote(image)) => self.read_note(image),
+            Ok(UiMsg::SaveNote { image, text }) => self.save_note(image, text),
+            Ok(UiMsg::FindPreviousVersion(path)) => self.find_previous_version(path),
+            Ok(UiMsg::ComparePairs { old, new }) => self.compare_pairs(old, new),
+            Ok(UiMsg::Shutdown) | Err(_) => false,
+        }
+    }
+
+    fn handle_notify(
+        &mut self,
+        msg: Result<Result<notify::Event, notify::Error>, RecvError>,
+    ) -> bool {
+        let msg = match msg {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("{}", e);
+                return false;
+            }
+        };
+        let evt = match msg {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("{}", e);
+                return false;
+            }
+        };
+        match &evt.kind {
+            notify::EventKind::Modify(_) => {
+                if let Some(path) = self.open_file.take() {
+                    // sleep for a bit to let the write finish
+                    // TODO We need to pump events for the duration, otherwise they will back up.
+                    thread::sleep(Duration::from_millis(1000));
+                    self.load_img(path)
+                } else {
+                    true
+                }
+            }
+            _ => true,
+        }
+    }
+
+    fn scan_dir(&mut self, path: &Path) -> bool {
+        match ImageList::for_file(path) {
+            Ok(list) => {
+                if self
+                    .evt_sink
+                    .submit_command(DIR_SCANNED, SingleUse::new(list), Target::Global)
+                    .is_err()
+                {
+                    log::error!("should be unreachable");
+                    return false;
+                }
+            }
+            // Not being able to navigate isn't fatal, we can still show the image.
+            Err(e) => log::error!("could not list directory of {}: {}", path.display(), e),
+        }
+        true
+    }
+
+    fn write_manifest(&mut self, dir: PathBuf) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // This reads every image in the folder, so keep it out of the way of interactive loads.
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let result = integrity::write_manifest(&dir);
+            if evt_sink
+                .submit_command(MANIFEST_WRITTEN, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn folder_stats(&mut self, paths: Vec<PathBuf>) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // This reads every image in the folder, so keep it out of the way of interactive loads.
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let stats = FolderStats::compute(&paths);
+            let first = paths.into_iter().next();
+            if evt_sink
+                .submit_command(FOLDER_STATS, SingleUse::new((first, stats)), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn extract_palette(&mut self, image: Arc<ImageBuf>, count: usize) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // The panel is open, but the image it is for comes first.
+        self.decode_pool.spawn(Priority::Prefetch, move || {
+            let swatches = palette::extract(&image, count);
+            if evt_sink
+                .submit_command(
+                    PALETTE_EXTRACTED,
+                    SingleUse::new((image, swatches)),
+                    Target::Global,
+                )
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn compare_pairs(&mut self, old: PathBuf, new: PathBuf) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // This reads every image in both folders, so keep it out of the way of interactive loads.
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let result = pairs::compare(&old, &new);
+            if evt_sink
+                .submit_command(PAIRS_COMPARED, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn export_pdf(&mut self, images: Vec<PathBuf>, dest: PathBuf, options: PdfOptions) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let result = pdf::export(&images, &dest, &options).map(|()| dest);
+            if evt_sink
+                .submit_command(PDF_EXPORTED, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn send_email(&mut self, images: Vec<PathBuf>, max_size: Option<u32>) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let result = email::send(&images, max_size);
+            if evt_sink
+                .submit_command(EMAIL_SENT, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn save_image(
+        &mut self,
+        image: Arc<ImageBuf>,
+        orientation: u16,
+        dest: PathBuf,
+        options: EncodeOptions,
+    ) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let image = exif::orient((*image).clone(), orientation);
+            let result = encode::save(&image, &dest, &options).map(|()| dest);
+            if evt_sink
+                .submit_command(IMAGE_SAVED, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn upscale(&mut self, path: PathBuf, upscaler: Arc<dyn Upscaler>, scale: u32) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // Upscalers can take minutes, so this gets a thread of its own rather than holding up a
+        // decoder.
+        thread::spawn(move || {
+            let result = upscale::upscale_cached(&*upscaler, &path, scale);
+            if evt_sink
+                .submit_command(UPSCALED, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    /// Notes are small, so are read and written here rather than on a decoder.
+    fn read_note(&mut self, image: PathBuf) -> bool {
+        let text = notes::read(&image);
+        if self
+            .evt_sink
+            .submit_command(NOTE_READ, SingleUse::new((image, text)), Target::Global)
+            .is_err()
+        {
+            log::error!("should be unreachable");
+        }
+        true
+    }
+
+    fn save_note(&mut self, image: PathBuf, text: String) -> bool {
+        if let Err(e) = notes::write(&image, &text) {
+            log::error!("could not save note on {}: {}", image.display(), e);
+            // The window may already be gone, if this was saved as it closed.
+            let _ = self.evt_sink.submit_command(
+                NOTE_NOT_SAVED,
+                SingleUse::new((image, e)),
+                Target::Global,
+            );
+        }
+        true
+    }
+
+    fn find_previous_version(&mut self, path: PathBuf) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // This runs git, which can take a while in a big repository.
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let result = versions::previous_version(&path);
+            if evt_sink
+                .submit_command(
+                    PREVIOUS_VERSION,
+                    SingleUse::new((path, result)),
+                    Target::Global,
+                )
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn export_wallpapers(
+        &mut self,
+        image: Arc<ImageBuf>,
+        view: View,
+        original: PathBuf,
+        options: EncodeOptions,
+    ) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        self.decode_pool.spawn(Priority::Batch, move || {
+            let result = wallpaper::export(&image, view, &original, &options);
+            if evt_sink
+                .submit_command(WALLPAPERS_EXPORTED, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn decode_pasted(&mut self, data: Vec<u8>) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        self.decode_pool.spawn(Priority::Interactive, move || {
+            let result = load_from_memory(&data);
+            if evt_sink
+                .submit_command(PASTED_IMAGE, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn read_stdin(&mut self) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // The reading waits on whatever is writing to the pipe, so it gets a thread of its own
+        // rather than holding up a decoder.
+        thread::spawn(move || {
+            let mut data = vec![];
+            let result = match io::stdin().lock().read_to_end(&mut data) {
+                Ok(_) => load_from_memory(&data),
+                Err(e) => Err(e.into()),
+            };
+            if evt_sink
+                .submit_command(STDIN_IMAGE, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    fn copy_image(
+        &mut self,
+        image: Arc<ImageBuf>,
+        orientation: u16,
+        path: Option<PathBuf>,
+    ) -> bool {
+        let evt_sink = self.evt_sink.clone();
+        // The user is waiting to paste, so don't queue behind batch jobs.
+        self.decode_pool.spawn(Priority::Interactive, move || {
+            let image = exif::orient((*image).clone(), orientation);
+            let result = encode::to_png(&image).map(|png| ClipboardImage { png, path });
+            if evt_sink
+                .submit_command(CLIPBOARD_IMAGE, SingleUse::new(result), Target::Global)
+                .is_err()
+            {
+                log::error!("should be unreachable");
+            }
+        });
+        true
+    }
+
+    /// Watch `path` instead of the previous open file, returning the new load generation. Any
+    /// decodes still going for the previous file are discarded when they finish.
+    fn watch(&mut self, path: PathBuf) -> u64 {
+        if let Some(prev) = self.open_file.take() {
+            // The file may have been deleted, which stops the watch anyway.
+            if let Err(e) = self.watcher.unwatch(&prev) {
+                log::warn!("could not stop watching {}: {}", prev.display(), e);
+            }
+        }
+        // A file we can't watch may still load, or fail to in a way the user should hear about,
+        // so carry on either way.
+        match self.watcher.watch(&path, RecursiveMode::NonRecursive) {
+            Ok(()) => {
+                log::debug!("watching {}", path.display());
+                self.open_file = Some(path);
+            }
+            Err(e) => log::warn!("could not watch {}: {}", path.display(), e),
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    fn load_img(&mut self, path: PathBuf) -> bool {
+        let generation = self.watch(path.clone());
+        {
+            let mut prefetch = self.prefetch.lock().unwrap();
+            if let Some(image) = prefetch.ready.remove(&path) {
+                log::debug!("using prefetched {}", path.display());
+                submit_loaded(&self.evt_sink, image);
+                return true;
+            }
+            if prefetch.pending.contains(&path) {
+                prefetch.deliver = Some((path, generation));
+                return true;
+            }
+        }
+        let latest = self.generation.clone();
+        let evt_sink = self.evt_sink.clone();
+        self.decode_pool.spawn(Priority::Interactive, move || {
+            let image = load(&path);
+            if latest.load(Ordering::SeqCst) != generation {
+                log::debug!("discarding stale decode of {}", path.display());
+                return;
+            }
+            submit_loaded(&evt_sink, image);
+        });
+        true
+    }
+
+    fn prefetch(&mut self, paths: Vec<PathBuf>) -> bool {
+        let mut prefetch = self.prefetch.lock().unwrap();
+        prefetch.ready.retain(|path, _| paths.contains(path));
+        prefetch.wanted = paths.clone();
+        for path in paths {
+            if prefetch.ready.contains_key(&path) || !prefetch.pending.insert(path.clone()) {
+                continue;
+            }
+            let shared = self.prefetch.clone();
+            let latest = self.generation.clone();
+            let evt_sink = self.evt_sink.clone();
+            self.decode_pool.spawn(Priority::Prefetch, move || {
+                {
+                    // The user may have moved on before we got a thread.
+                    let mut prefetch = shared.lock().unwrap();
+                    let delivering = matches!(&prefetch.deliver, Some((p, _)) if *p == path);
+                    if !delivering && !prefetch.wanted.contains(&path) {
+                        prefetch.pending.remove(&path);
+                        return;
+                    }
+                }
+                let image = load(&path);
+                let mut prefetch = shared.lock().unwrap();
+                prefetch.pending.remove(&path);
+                match prefetch.deliver.take() {
+                    Some((wanted, generation)) if wanted == path => {
+                        if latest.load(Ordering::SeqCst) == generation {
+                            submit_loaded(&evt_sink, image);
+                        }
+                    }
+                    deliver => {
+                        prefetch.deliver = deliver;
+                        if prefetch.wanted.contains(&path) {
+                            prefetch.ready.insert(path, image);
+                        }
+                    }
+                }
+            });
+        }
+        true
+    }
+}
+
+fn submit_loaded(evt_sink: &ExtEventSink, image: LoadResult) {
+    if evt_sink
+        .submit_command(FILE_LOADED, SingleUse::new(image), Target::Global)
+        .is_err()
+    {
+        log::error!("should be unreachable");
+    }
+}
+
+/// Decode `path`, and work out everything else we show about it.
+///
+/// This runs on the decode pool, which keeps the analysis off the UI thread.
+fn load(path: &Path) -> LoadResult {
+    let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+    decode_any(path)
+        .map(|mut loaded| {
+            loaded.path = path.to_owned();
+            loaded.modified = modified;
+            loaded.exposure = analysis::analyse(&loaded.image);
+            // Animations are drawn at full size, and DICOM and FITS are rendered again on screen,
+            // so only the others need mips.
+            if loaded.animation.is_none() && loaded.dicom.is_none() && loaded.fits.is_none() {
+                loaded.mips = widgets::mip_levels(&loaded.image);
+            }
+            // A checksum we can't read shouldn't stop us showing the image.
+            loaded.integrity = integrity::verify(path).unwrap_or_else(|e| {
+                log::error!("could not verify {}: {}", path.display(), e);
+                Integrity::Unknown
+            });
+            loaded.exif = exif::read(path);
+            loaded
+        })
+        .map_err(|error| LoadError {
+            path: path.to_owned(),
+            error,
+        })
+}
+
+/// Decode an image that isn't in a file, and work out what we show about it.
+fn load_from_memory(data: &[u8]) -> Result<Loaded, Box<dyn Error + Send + Sync>> {
+    let mut loaded = Loaded::new(decode::from_memory(data)?);
+    loaded.exposure = analysis::analyse(&loaded.image);
+    loaded.mips = widgets::mip_levels(&loaded.image);
+    Ok(loaded)
+}
+
+/// Decode `path` with whichever decoder handles it.
+fn decode_any(path: &Path) -> Result<Loaded, Box<dyn Error + Send + Sync>> {
+    Ok(if dicom::is_dicom(path) {
+        let dicom = dicom::open(path)?;
+        let image = dicom.render(dicom.default_window);
+        Loaded {
+            dicom: Some(dicom),
+            ..Loaded::new(image)
+        }
+    } else if fits::is_fits(path) {
+        let fits = fits::open(path)?;
+        let image = fits.render(Stretch::default());
+        Loaded {
+            fits: Some(fits),
+            ..Loaded::new(image)
+        }
+    } else if svg::is_svg(path) {
+        let (svg, image) = svg::open(path)?;
+        Loaded {
+            svg: Some(svg),
+            ..Loaded::new(image)
+        }
+    } else {
+        let (image, animation) = decode::open_animated(path)?;
+        let (color_space, converted) = color::to_srgb(path, &image);
+        match converted {
+            Some(converted) => Loaded {
+                animation,
+                color_space,
+                unconverted: Some(image),
+                ..Loaded::new(converted)
+            },
+            None => Loaded {
+                animation,
+                color_space,
+                ..Loaded::new(image)
+            },
+        }
+    })
+}
@@ -0,0 +1,298 @@
+//! Macros: a few edits, like turning, cropping to a shape and exporting, recorded as the user
+//! makes them and replayed on another image with one click. They are kept in the config file:
+//!
+//! ```toml
+//! [[macro]]
+//! name = "square"
+//! steps = ["rotate-cw", "crop 1:1", "export png"]
+//! ```
+//!
+//! A crop is replayed from the middle of the image, at the shape it was made at, and an export
+//! saves a copy beside the image, named after the macro, like `photo-square.png`. Running a macro
+//! while recording records its steps, so macros can be built out of others.
+use druid::{
+    widget::{prelude::*, Button, CrossAxisAlignment, Flex, Label, TextBox, ViewSwitcher},
+    ArcStr, Color, Command, Data, Lens, Selector, WidgetExt,
+};
+use std::{fmt, str::FromStr, sync::Arc};
+
+use crate::widgets::{CropAspect, ReportFocus, CROP_TO, FLIP_H, FLIP_V, ROTATE_CCW, ROTATE_CW};
+
+/// Open or close the macros panel.
+pub const TOGGLE_MACROS: Selector = Selector::new("image-viewer.toggle-macros");
+/// Save the recorded steps as a macro, under the name typed in the panel.
+pub const SAVE_MACRO: Selector = Selector::new("image-viewer.save-macro");
+/// Run a macro on the current image.
+pub const RUN_MACRO: Selector<Macro> = Selector::new("image-viewer.run-macro");
+/// Save a copy of the current image beside it, as the macro named first, in the format with the
+/// extension second.
+pub const EXPORT_COPY: Selector<(ArcStr, ArcStr)> = Selector::new("image-viewer.export-copy");
+
+/// How many steps to remember for "Use recent".
+const RECENT_LEN: usize = 10;
+/// The formats an export step can save in.
+const EXPORT_FORMATS: &[&str] = &["png", "jpg", "jpeg", "webp"];
+
+/// One edit in a macro.
+#[derive(Debug, Clone, PartialEq, Data)]
+pub enum Step {
+    RotateCw,
+    RotateCcw,
+    FlipH,
+    FlipV,
+    /// Crop as much as fits the shape, from the middle.
+    Crop(CropAspect),
+    /// Save a copy, in the format with this extension.
+    Export(ArcStr),
+}
+
+impl Step {
+    /// The command that makes this step, as part of the macro called `name`.
+    pub fn command(&self, name: &ArcStr) -> Command {
+        match self {
+            Step::RotateCw => ROTATE_CW.into(),
+            Step::RotateCcw => ROTATE_CCW.into(),
+            Step::FlipH => FLIP_H.into(),
+            Step::FlipV => FLIP_V.into(),
+            Step::Crop(aspect) => CROP_TO.with(*aspect),
+            Step::Export(ext) => EXPORT_COPY.with((name.clone(), ext.clone())),
+        }
+    }
+
+    /// An export to `ext`, if we can save in that format.
+    pub fn export(ext: &str) -> Option<Self> {
+        let ext = ext.to_lowercase();
+        EXPORT_FORMATS
+            .contains(&ext.as_str())
+            .then(|| Step::Export(ext.into()))
+    }
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, arg) = s.split_once(' ').unwrap_or((s, ""));
+        let arg = arg.trim();
+        let step = match (name, arg) {
+            ("rotate-cw", "") => Step::RotateCw,
+            ("rotate-ccw", "") => Step::RotateCcw,
+            ("flip-h", "") => Step::FlipH,
+            ("flip-v", "") => Step::FlipV,
+            ("crop", "original") => Step::Crop(CropAspect::Original),
+            ("crop", ratio) => {
+                let parsed = ratio
+                    .split_once(':')
+                    .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+                match parsed {
+                    Some((w, h)) if w > 0 && h > 0 => Step::Crop(CropAspect::Ratio(w, h)),
+                    _ => {
+                        return Err(format!(
+                            "expected crop original or a shape like crop 16:9, got {:?}",
+                            s
+                        ))
+                    }
+                }
+            }
+            ("export", ext) => Step::export(ext).ok_or_else(|| {
+                format!(
+                    "expected export and one of {}, got {:?}",
+                    EXPORT_FORMATS.join(", "),
+                    s
+                )
+            })?,
+            _ => return Err(format!("unknown step {:?}", s)),
+        };
+        Ok(step)
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::RotateCw => f.write_str("rotate-cw"),
+            Step::RotateCcw => f.write_str("rotate-ccw"),
+            Step::FlipH => f.write_str("flip-h"),
+            Step::FlipV => f.write_str("flip-v"),
+            // Free crops aren't recorded, since there is no shape to replay them at, so this is
+            // never written.
+            Step::Crop(CropAspect::Free) => f.write_str("crop"),
+            Step::Crop(CropAspect::Original) => f.write_str("crop original"),
+            Step::Crop(CropAspect::Ratio(w, h)) => write!(f, "crop {}:{}", w, h),
+            Step::Export(ext) => write!(f, "export {}", ext),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Data)]
+pub struct Macro {
+    pub name: ArcStr,
+    pub steps: Arc<Vec<Step>>,
+}
+
+impl Macro {
+    /// Read a `[[macro]]` table from the config file.
+    pub fn from_table(table: &toml::value::Table) -> Result<Self, String> {
+        let name = match table.get("name") {
+            Some(toml::Value::String(name)) if !name.trim().is_empty() => name.trim(),
+            _ => return Err("expected a name for each macro".into()),
+        };
+        let steps = match table.get("steps") {
+            Some(toml::Value::Array(steps)) => steps
+                .iter()
+                .map(|step| match step {
+                    toml::Value::String(step) => step.parse(),
+                    _ => Err(format!("expected a step, got {}", step)),
+                })
+                .collect::<Result<Vec<Step>, _>>()
+                .map_err(|e| format!("macro {:?}: {}", name, e))?,
+            _ => return Err(format!("expected a list of steps for macro {:?}", name)),
+        };
+        Ok(Self {
+            name: name.into(),
+            steps: Arc::new(steps),
+        })
+    }
+
+    /// The macro as the `[[macro]]` table `from_table` reads.
+    pub fn to_table(&self) -> toml::value::Table {
+        let mut table = toml::value::Table::new();
+        table.insert("name".into(), self.name.to_string().into());
+        let steps: Vec<toml::Value> = self.steps.iter().map(|s| s.to_string().into()).collect();
+        table.insert("steps".into(), steps.into());
+        table
+    }
+}
+
+#[derive(Debug, Clone, Default, Data, Lens)]
+pub struct Macros {
+    /// The macros in the config file, and any saved since.
+    pub saved: Arc<Vec<Macro>>,
+    /// The last few steps the user took, oldest first.
+    pub recent: Arc<Vec<Step>>,
+    /// The steps recorded, or being recorded.
+    pub steps: Arc<Vec<Step>>,
+    pub recording: bool,
+    /// The name to save the steps under, as typed.
+    pub name: String,
+    /// Whether the panel is open.
+    pub show: bool,
+}
+
+impl Macros {
+    pub fn new(saved: Vec<Macro>) -> Self {
+        Self {
+            saved: Arc::new(saved),
+            ..Self::default()
+        }
+    }
+
+    /// Note that the user took `step`, recording it if we are recording.
+    pub fn performed(&mut self, step: Step) {
+        let recent = Arc::make_mut(&mut self.recent);
+        if recent.len() == RECENT_LEN {
+            recent.remove(0);
+        }
+        recent.push(step.clone());
+        if self.recording {
+            Arc::make_mut(&mut self.steps).push(step);
+        }
+    }
+
+    /// Keep `saved`, replacing any macro with the same name.
+    pub fn add(&mut self, saved: Macro) {
+        let macros = Arc::make_mut(&mut self.saved);
+        match macros.iter_mut().find(|m| m.name == saved.name) {
+            Some(existing) => *existing = saved,
+            None => macros.push(saved),
+        }
+    }
+}
+
+/// Steps as the config file has them, like "rotate-cw, crop 1:1".
+fn describe(steps: &[Step]) -> String {
+    let steps: Vec<_> = steps.iter().map(Step::to_string).collect();
+    steps.join(", ")
+}
+
+/// The controls for recording macros, and a button to run each saved one.
+pub fn panel() -> impl Widget<Macros> {
+    let saved = ViewSwitcher::new(
+        |data: &Macros, _| data.saved.clone(),
+        |saved, _, _| {
+            let mut row = Flex::row();
+            if saved.is_empty() {
+                row.add_child(
+                    Label::new("Record some steps and save them to run them on other images")
+                        .with_text_color(Color::grey8(0xa0)),
+                );
+            }
+            for run in saved.iter() {
+                let label = run.name.to_string();
+                let run = run.clone();
+                row.add_child(
+                    Button::new(label)
+                        .on_click(move |ctx, _, _| ctx.submit_command(RUN_MACRO.with(run.clone()))),
+                );
+            }
+            Box::new(row)
+        },
+    );
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Macros"))
+                .with_flex_spacer(1.)
+                .with_child(
+                    Button::dynamic(|data: &Macros, _| {
+                        let label = if data.recording { "Stop" } else { "Record" };
+                        label.to_string()
+                    })
+                    .on_click(|_, data: &mut Macros, _| {
+                        if !data.recording {
+                            data.steps = Arc::new(vec![]);
+                        }
+                        data.recording = !data.recording;
+                    }),
+                )
+                .with_child(
+                    Button::new("Use recent").on_click(|_, data: &mut Macros, _| {
+                        data.steps = data.recent.clone();
+                        data.recording = false;
+                    }),
+                )
+                .with_spacer(4.)
+                .with_child(
+                    TextBox::new()
+                        .with_placeholder("Name")
+                        .controller(ReportFocus)
+                        .fix_width(120.)
+                        .lens(Macros::name),
+                )
+                .with_child(Button::new("Save").on_click(|ctx, _, _| {
+                    ctx.submit_command(SAVE_MACRO);
+                }))
+                .with_child(Button::new("×").on_click(|ctx, _, _| {
+                    ctx.submit_command(TOGGLE_MACROS);
+                })),
+        )
+        .with_spacer(4.)
+        .with_child(
+            Label::dynamic(
+                |data: &Macros, _| match (data.recording, data.steps.is_empty()) {
+                    (true, true) => "Recording: turn, crop to a shape or save as".to_string(),
+                    (true, false) => format!("Recording: {}", describe(&data.steps)),
+                    (false, false) => format!("Recorded: {}", describe(&data.steps)),
+                    (false, true) if data.recent.is_empty() => String::new(),
+                    (false, true) => format!("Recent: {}", describe(&data.recent)),
+                },
+            )
+            .with_text_size(11.)
+            .with_text_color(Color::grey8(0xa0)),
+        )
+        .with_spacer(4.)
+        .with_child(saved)
+        .padding(4.)
+}
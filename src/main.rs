@@ -1,211 +1,889 @@
+mod about;
+mod analysis;
+mod blink;
+mod cache;
+mod cli;
+mod clipboard;
+mod color;
+mod config;
+mod decode;
+mod dialog;
+mod dicom;
+mod email;
+mod encode;
+mod exif;
+mod expr;
+mod failures;
+mod fits;
+mod folder_config;
+mod history;
+mod integrity;
+mod keymap;
+mod library;
+mod loader;
+mod macros;
+mod notes;
+mod pairs;
+mod palette;
+mod pdf;
+mod pixel_ops;
+mod profile;
+mod raw;
+mod shell;
+mod stats;
+mod status;
+mod svg;
+mod theme;
+mod tiff;
+mod toast;
+mod upscale;
+mod versions;
+mod wallpaper;
 mod widgets;
 
-use crossbeam_channel::{self as channel, Receiver, RecvError};
+use clap::Parser;
+use crossbeam_channel as channel;
 use druid::{
-    commands::{OPEN_FILE, QUIT_APP, SHOW_OPEN_PANEL},
+    commands::{OPEN_FILE, QUIT_APP, SHOW_OPEN_PANEL, SHOW_SAVE_PANEL},
     kurbo::Point,
-    theme,
-    widget::{prelude::*, Flex, Label, Maybe},
-    AppDelegate, AppLauncher, ArcStr, Color, Command, Data, DelegateCtx, Env, ExtEventSink,
-    FileDialogOptions, FileSpec, Handled, ImageBuf, Lens, Selector, SingleUse, Target, Widget,
-    WidgetExt, WidgetPod, WindowDesc,
+    lens,
+    widget::{
+        prelude::*, Button, Checkbox, Controller, CrossAxisAlignment, Either, EnvScope, Flex,
+        Label, Maybe, Radio, SizedBox, Slider, TextBox, ViewSwitcher,
+    },
+    AppDelegate, AppLauncher, Application, ArcStr, Color, Command, Data, DelegateCtx, Env,
+    FileDialogOptions, FileInfo, FileSpec, Handled, KbKey, KeyEvent, Lens, LensExt, MouseButton,
+    MouseEvent, Selector, Target, TimerToken, Widget, WidgetExt, WidgetPod, WindowDesc, WindowId,
+    WindowState,
 };
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use qu::ick_use::*;
-use std::{error::Error, path::PathBuf, sync::Arc, thread, time::Duration};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use crate::widgets::{Icon, ZoomImage, NOTIFY_TRANSFORM, SET_SCALE, ZOOM};
+use crate::{
+    about::SHOW_ABOUT,
+    analysis::Exposure,
+    blink::{Blink, ADD_TO_BLINK, BLINK_STEP, TOGGLE_BLINKING},
+    cache::{CachedImage, ImageCache},
+    clipboard::{self, Pasted, COPY_FILE, COPY_PATH, COPY_TO_CLIPBOARD, PASTE_FROM_CLIPBOARD},
+    config::{self, Config, EXPORT_SETTINGS, IMPORT_SETTINGS},
+    dialog::{Dialog, Dialogs, Modal, Reply, OVERWRITE, SHOW_DIALOG},
+    dicom::Window,
+    email::SEND_EMAIL,
+    encode::EncodeOptions,
+    exif::TOGGLE_EXIF,
+    expr::Expr,
+    failures::{self, Failures},
+    fits::{Stretch, StretchKind},
+    folder_config::Direction,
+    history::History,
+    integrity::{self, Integrity, WRITE_MANIFEST},
+    keymap::{Action, Keymap},
+    library::{ImageList, NEXT_IMAGE, PREV_IMAGE},
+    loader::{
+        Loaded, UiMsg, CLIPBOARD_IMAGE, DIR_SCANNED, EMAIL_SENT, FILE_LOADED, FOLDER_STATS,
+        IMAGE_SAVED, MANIFEST_WRITTEN, NOTE_NOT_SAVED, NOTE_READ, PAIRS_COMPARED,
+        PALETTE_EXTRACTED, PASTED_IMAGE, PDF_EXPORTED, PREFETCH_DISTANCE, PREVIOUS_VERSION,
+        STDIN_IMAGE, UPSCALED, WALLPAPERS_EXPORTED,
+    },
+    macros::{Macro, Macros, Step, EXPORT_COPY, RUN_MACRO, SAVE_MACRO, TOGGLE_MACROS},
+    notes::{Note, EDIT_NOTE, SAVE_NOTE},
+    pairs::{Pairs, NEXT_PAIR, PREV_PAIR, SAVE_REPORT},
+    palette::{Palette, Swatch, EXTRACT_PALETTE},
+    pdf::{Fit, PageSize, PdfOptions},
+    profile::{self, InitialZoom, ProfileKind},
+    shell::REVEAL_FILE,
+    stats::{FolderStats, TOGGLE_FOLDER_STATS},
+    status::FileStatus,
+    theme::{Theme, TOGGLE_THEME},
+    toast::{self, Toast, Toasts, SHOW_TOAST},
+    upscale::{ExternalUpscaler, Upscaler, UPSCALE},
+    versions::COMPARE_WITH_PREVIOUS,
+    wallpaper::{EXPORT_WALLPAPERS, VIEW_TO_EXPORT},
+    widgets::{
+        self, CropAspect, Icon, ViewerState, ZoomImage, COPY_COLOR, CROPPED, CROP_APPLY, CROP_TO,
+        EDITING_TEXT, FLIP_H, FLIP_V, NOTIFY_TRANSFORM, ROTATE_CCW, ROTATE_CW, SET_SCALE,
+        TOGGLE_FULLSCREEN, TOGGLE_INSPECTOR, ZOOM, ZOOM_TO_SUBJECT,
+    },
+};
 use druid_material_icons::normal::{
-    action::{EXIT_TO_APP, SEARCH},
-    content::{ADD, REMOVE},
-    image::IMAGE,
+    action::{ASSESSMENT, EXIT_TO_APP, FINGERPRINT, INFO, SEARCH},
+    av::PLAYLIST_PLAY,
+    communication::EMAIL,
+    content::{ADD, REMOVE, SAVE},
+    device::WALLPAPER,
+    editor::FUNCTIONS,
+    file::FOLDER_OPEN,
+    image::{
+        CENTER_FOCUS_STRONG, COMPARE, CROP, FLIP, IMAGE, PALETTE, PHOTO_SIZE_SELECT_LARGE,
+        PICTURE_AS_PDF, ROTATE_LEFT, ROTATE_RIGHT,
+    },
 };
 
-const FILE_LOADED: Selector<SingleUse<Result<ImageBuf, Box<dyn Error + Send + Sync>>>> =
-    Selector::new("image-viewer.file-loaded");
-const ALL_IMAGES: FileSpec = FileSpec::new("Image", &["jpg", "jpeg", "gif", "bmp", "png"]);
+/// How long to show notices the user has to act on.
+const IMPORTANT_TOAST: Duration = Duration::from_secs(12);
+
+/// Go back to the previously viewed image.
+const HISTORY_BACK: Selector = Selector::new("image-viewer.history-back");
+/// Go forward again after going back.
+const HISTORY_FORWARD: Selector = Selector::new("image-viewer.history-forward");
+/// Draw this colour behind images from now on, whatever their folder says, or go back to what
+/// the folder, config file or profile says with `None`.
+const SET_BACKGROUND: Selector<Option<Color>> = Selector::new("image-viewer.set-background");
+/// Start or stop the slideshow.
+const TOGGLE_SLIDESHOW: Selector = Selector::new("image-viewer.toggle-slideshow");
+
+/// The extensions we know how to open.
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg",
+    "jpeg",
+    "gif",
+    "bmp",
+    "png",
+    "webp",
+    "svg",
+    "cr2",
+    "nef",
+    "arw",
+    "dng",
+    #[cfg(feature = "avif")]
+    "avif",
+    #[cfg(feature = "heif")]
+    "heic",
+    #[cfg(feature = "heif")]
+    "heif",
+    #[cfg(feature = "dicom")]
+    "dcm",
+    #[cfg(feature = "fits")]
+    "fits",
+    #[cfg(feature = "fits")]
+    "fit",
+    #[cfg(feature = "fits")]
+    "fts",
+];
+const ALL_IMAGES: FileSpec = FileSpec::new("Image", IMAGE_EXTENSIONS);
+/// The formats we can save edited images in.
+const SAVE_FORMATS: &[FileSpec] = &[
+    FileSpec::PNG,
+    FileSpec::JPG,
+    FileSpec::new("WebP", &["webp"]),
+];
+
+/// Export the images in the current folder to a PDF at the chosen path.
+const EXPORT_PDF: Selector<FileInfo> = Selector::new("image-viewer.export-pdf");
+/// The widest margin, in mm, that can be chosen for an exported PDF.
+const MAX_PDF_MARGIN: f64 = 40.;
+/// Choose where to save the current image.
+const SHOW_SAVE_AS: Selector = Selector::new("image-viewer.show-save-as");
+/// Save the current image as it is shown, cropped and turned, at the chosen path.
+const SAVE_IMAGE_AS: Selector<FileInfo> = Selector::new("image-viewer.save-image-as");
+/// Crop the current image, and save the result at the chosen path.
+const SAVE_CROP: Selector<FileInfo> = Selector::new("image-viewer.save-crop");
+/// The answer to whether to replace an existing checksum manifest.
+const MANIFEST_OVERWRITE: Selector<Reply> = Selector::new("image-viewer.manifest-overwrite");
+
+#[derive(Debug, Parser)]
+#[clap(about = "A simple image viewer")]
+struct Opt {
+    /// Register the viewer in "Open with" for image files, then exit (Windows only).
+    #[clap(long)]
+    register_file_types: bool,
+    /// Write the settings and keyboard shortcuts to this path as one file, then exit.
+    #[clap(long, value_name = "PATH")]
+    export_settings: Option<PathBuf>,
+    /// Replace the settings and keyboard shortcuts with the ones exported to this path, then
+    /// exit. The settings being replaced are kept in config.toml.bak.
+    #[clap(long, value_name = "PATH")]
+    import_settings: Option<PathBuf>,
+    /// Combine the images into a PDF at this path, one per page, then exit.
+    #[clap(long, value_name = "PATH")]
+    export_pdf: Option<PathBuf>,
+    /// The page size for --export-pdf.
+    #[clap(long, arg_enum, default_value = "a4")]
+    page_size: PageSize,
+    /// The page margin in mm for --export-pdf.
+    #[clap(long, default_value = "10")]
+    margin: f64,
+    /// How to fit images to the page for --export-pdf.
+    #[clap(long, arg_enum, default_value = "contain")]
+    fit: Fit,
+    /// How much memory to keep recently viewed images in, in MB.
+    #[clap(long, value_name = "MB", default_value = "512")]
+    cache_mb: usize,
+    /// The quality to save JPEG and WebP images at, from 1 to 100. WebP at 100 is lossless.
+    #[clap(long, default_value = "90")]
+    quality: u8,
+    /// Start in e-ink mode: dithered grey, with no animation.
+    #[clap(long)]
+    eink: bool,
+    /// Turn images a quarter turn when they fit the window better that way, for example on a
+    /// portrait monitor. The files aren't changed.
+    #[clap(long)]
+    auto_rotate: bool,
+    /// How long to show messages like "Copied to clipboard" for, in seconds. Errors stay up
+    /// three times as long.
+    #[clap(long, value_name = "SECONDS", default_value = "4")]
+    toast_secs: f64,
+    /// Start fullscreen, showing only the image. F11 or Escape leaves fullscreen.
+    #[clap(long)]
+    fullscreen: bool,
+    /// Start a slideshow, stepping to the next image this often, for example 5s or 500ms. F5
+    /// starts and stops the slideshow, at this interval or the one in the config file.
+    #[clap(long, value_name = "INTERVAL", parse(try_from_str = cli::parse_duration))]
+    slideshow: Option<Duration>,
+    /// What to draw behind images: black, grey, white, or a colour like #1e1e1e. The folder's
+    /// settings or the profile decide if this isn't given, and the config file isn't either.
+    #[clap(long, value_name = "COLOR", parse(try_from_str = cli::parse_color))]
+    background: Option<Color>,
+    /// Compare two folders, such as the output of two versions of a renderer. Images are paired
+    /// by name, and next and previous step through the pairs while the panel for them is open.
+    #[clap(long, number_of_values = 2, value_names = &["OLD", "NEW"])]
+    compare_dirs: Vec<PathBuf>,
+    /// Write how the --compare-dirs pairs compare to this path as CSV, then exit.
+    #[clap(long, value_name = "PATH", requires = "compare-dirs")]
+    pair_report: Option<PathBuf>,
+    /// How to zoom images when they are opened: fit, fit-whole, fit-width, or a scale like 100%.
+    /// The profile decides if this isn't given.
+    #[clap(long, parse(try_from_str = cli::parse_scale))]
+    scale: Option<InitialZoom>,
+    /// A program to upscale images with, such as realesrgan-ncnn-vulkan. The upscaled copy is
+    /// saved beside the image as a PNG, and shown.
+    #[clap(long, value_name = "PROGRAM")]
+    upscaler: Option<PathBuf>,
+    /// The arguments to run --upscaler with. {input}, {output} and {scale} are replaced with the
+    /// image, the file to write and --upscale-by.
+    #[clap(long, value_name = "ARGS", default_value = upscale::DEFAULT_ARGS)]
+    upscaler_args: String,
+    /// How many times bigger --upscaler makes images.
+    #[clap(long, default_value = "4")]
+    upscale_by: u32,
+    /// Images to open, directories to open the images in, or paths with * and ? in the file name.
+    /// The first image is shown, and the rest can be reached with history forward. A - shows the
+    /// image piped to standard input instead.
+    files: Vec<PathBuf>,
+}
 
 #[derive(Debug, Clone, Data, Lens)]
 struct AppData {
-    image: Option<Arc<ImageBuf>>,
+    /// The current image, and how it is positioned.
+    viewer: Option<ViewerState>,
+    /// The images we can step through with next/previous.
+    library: Option<ImageList>,
+    /// Possible problems with the current image.
+    exposure: Exposure,
+    /// Whether the current image matches its checksum.
+    integrity: Integrity,
+    /// The name of the file being loaded, if any. The previous image stays up until it's done.
+    loading: Option<ArcStr>,
     error: ArcStr,
-    info: ArcStr,
+    /// The file the image was read from, or `None` if it wasn't read from a file.
+    file: Option<FileStatus>,
+    /// The zoom, as reported by the image widget, or as typed into the status bar.
+    zoom: f64,
+    /// Whether a text box that reports its focus, like the zoom box, has the keyboard.
+    editing_text: bool,
+    /// Set when the image is drawn mirrored.
+    mirrored: ArcStr,
+    /// Whether the PDF export panel is open.
+    show_pdf: bool,
+    /// How to lay out the pages when exporting the folder to a PDF.
+    pdf_options: PdfOptions,
+    /// Whether the expression panel is open.
+    show_expression: bool,
+    /// The expression applied to the image while the panel is open, as typed.
+    expression: String,
+    /// Why `expression` couldn't be parsed.
+    expression_error: ArcStr,
+    /// Whether to draw for an e-ink display.
+    eink: bool,
+    /// Whether to turn images that fit the window better on their side.
+    auto_rotate: bool,
+    /// The dominant colours of the current image.
+    palette: Palette,
+    /// Whether the metadata panel is open.
+    show_exif: bool,
+    /// Whether the folder statistics panel is open.
+    show_folder_stats: bool,
+    /// The statistics for `library`, once they have been worked out.
+    folder_stats: Option<Arc<FolderStats>>,
+    /// The profile to show every image with, or `None` to pick one for each image.
+    profile: Option<ProfileKind>,
+    /// Short messages about what has happened, and the ones shown so far.
+    toasts: Toasts,
+    /// The open confirmation dialog, and any waiting behind it.
+    dialogs: Dialogs,
+    /// The files that wouldn't load this session.
+    failures: Failures,
+    /// How to zoom newly opened images, if not as the profile says.
+    #[data(same_fn = "PartialEq::eq")]
+    initial_zoom: Option<InitialZoom>,
+    /// How long to show each image for, if we are playing a slideshow.
+    #[data(same_fn = "PartialEq::eq")]
+    slideshow: Option<Duration>,
+    /// What to draw behind images whose folder doesn't say, from the command line or the config
+    /// file.
+    background: Option<Color>,
+    /// What to draw behind images, as chosen in the status bar, whatever their folder says.
+    chosen_background: Option<Color>,
+    /// Whether the window fills the screen with only the image.
+    fullscreen: bool,
+    theme: Theme,
+    /// The images to blink between.
+    blink: Blink,
+    /// The note on the current image.
+    note: Note,
+    /// The two folders being compared, if any.
+    pairs: Pairs,
+    macros: Macros,
 }
 
 impl AppData {
     fn new() -> Self {
         Self {
-            image: None,
+            viewer: None,
+            library: None,
+            exposure: Exposure::default(),
+            integrity: Integrity::default(),
+            loading: None,
             error: "".into(),
-            info: "".into(),
+            file: None,
+            zoom: 1.,
+            editing_text: false,
+            mirrored: "".into(),
+            show_pdf: false,
+            pdf_options: PdfOptions::default(),
+            show_expression: false,
+            expression: String::new(),
+            expression_error: "".into(),
+            eink: false,
+            auto_rotate: false,
+            palette: Palette::default(),
+            show_exif: false,
+            show_folder_stats: false,
+            folder_stats: None,
+            profile: None,
+            toasts: Toasts::new(Duration::from_secs(4)),
+            dialogs: Dialogs::default(),
+            failures: Failures::default(),
+            initial_zoom: None,
+            slideshow: None,
+            background: None,
+            chosen_background: None,
+            fullscreen: false,
+            theme: Theme::default(),
+            blink: Blink::default(),
+            note: Note::default(),
+            pairs: Pairs::default(),
+            macros: Macros::default(),
+        }
+    }
+
+    fn set_image(&mut self, loaded: Loaded) {
+        let viewer = ViewerState {
+            color_space: loaded.color_space,
+            unconverted: loaded.unconverted.map(Arc::new),
+            exif: loaded.exif.map(Arc::new),
+            animation: loaded.animation.map(Arc::new),
+            window: loaded
+                .dicom
+                .as_ref()
+                .map_or(Window::default(), |dicom| dicom.default_window),
+            dicom: loaded.dicom.map(Arc::new),
+            fits: loaded.fits.map(Arc::new),
+            svg: loaded.svg.map(Arc::new),
+            mips: Arc::new(loaded.mips),
+            auto_profile: profile::for_path(&loaded.path),
+            ..ViewerState::new(Arc::new(loaded.image))
+        };
+        self.file = Some(FileStatus::for_path(&loaded.path));
+        self.show(viewer, loaded.exposure, loaded.integrity);
+    }
+
+    /// Show an image that didn't come from a file, such as one pasted from the clipboard.
+    fn set_unsaved_image(&mut self, loaded: Loaded) {
+        self.set_image(loaded);
+        self.file = None;
+        if let Some(viewer) = self.viewer.as_mut() {
+            // There is no file behind it until it is saved.
+            viewer.edited = true;
+            viewer.pasted = true;
+        }
+    }
+
+    /// How to zoom images in the current folder, from the command line or else the folder's
+    /// settings, and what to draw behind them, as chosen in the status bar, the folder's
+    /// settings, or the command line or config file, in that order.
+    fn folder_settings(&self) -> (Option<InitialZoom>, Option<Color>) {
+        let config = self.library.as_ref().map(ImageList::config);
+        (
+            self.initial_zoom
+                .or_else(|| config.and_then(|config| config.zoom)),
+            self.chosen_background
+                .clone()
+                .or_else(|| config.and_then(|config| config.background.clone()))
+                .or_else(|| self.background.clone()),
+        )
+    }
+
+    /// Draw `color` behind every image from now on, or go back to the folder's settings.
+    fn set_background(&mut self, color: Option<Color>) {
+        self.chosen_background = color;
+        let (_, background) = self.folder_settings();
+        if let Some(viewer) = self.viewer.as_mut() {
+            viewer.background = background;
+        }
+    }
+
+    /// Show `viewer`, carrying over the settings that apply to every image.
+    fn show(&mut self, mut viewer: ViewerState, exposure: Exposure, integrity: Integrity) {
+        // Keep colour management off while flicking through images to compare.
+        viewer.convert_colors = self.viewer.as_ref().map_or(true, |v| v.convert_colors);
+        viewer.inspect = self.viewer.as_ref().map_or(false, |v| v.inspect);
+        viewer.eink = self.eink;
+        viewer.auto_rotate = self.auto_rotate;
+        let (initial_zoom, background) = self.folder_settings();
+        viewer.initial_zoom = initial_zoom;
+        viewer.background = background;
+        viewer.lock_transform = self.blink.playing;
+        viewer.profile = self.profile.unwrap_or(viewer.auto_profile);
+        let previous = self.viewer.replace(viewer);
+        self.exposure = exposure;
+        self.integrity = integrity;
+        self.error = "".into();
+        match previous {
+            // Reuse the parsed expression, so the viewer can tell it hasn't changed.
+            Some(previous) => {
+                if let Some(viewer) = self.viewer.as_mut() {
+                    viewer.expression = previous.expression;
+                }
+            }
+            None => self.apply_expression(),
         }
     }
 
-    fn set_image(&mut self, image: Arc<ImageBuf>) {
-        self.image = Some(image);
-        self.error = "".into()
+    /// Remember the image being shown, so it can be shown again without loading it.
+    fn cache_entry(&self, modified: Option<SystemTime>) -> Option<CachedImage> {
+        Some(CachedImage {
+            viewer: self.viewer.clone()?,
+            exposure: self.exposure,
+            integrity: self.integrity,
+            modified,
+        })
+    }
+
+    /// Parse `expression` and give it to the viewer, if the panel is open.
+    fn apply_expression(&mut self) {
+        let mut parsed = None;
+        self.expression_error = "".into();
+        if self.show_expression && !self.expression.trim().is_empty() {
+            match Expr::parse(&self.expression) {
+                Ok(expr) => parsed = Some(Arc::new(expr)),
+                Err(e) => self.expression_error = e.to_string().into(),
+            }
+        }
+        if let Some(viewer) = self.viewer.as_mut() {
+            viewer.expression = parsed;
+        }
     }
 
     fn set_error(&mut self, error: ArcStr) {
-        self.image = None;
+        self.viewer = None;
+        self.exposure = Exposure::default();
+        self.integrity = Integrity::default();
         self.error = error;
     }
 }
 
 #[qu::ick]
-pub fn main() -> Result {
-    let main_window = WindowDesc::new(ui_builder()).title("Image Viewer");
+pub fn main(opt: Opt) -> Result {
+    if opt.register_file_types {
+        shell::register_file_types(IMAGE_EXTENSIONS)?;
+        log::info!("registered file types");
+        return Ok(());
+    }
+    if opt.export_settings.is_some() || opt.import_settings.is_some() {
+        let dir = shell::config_dir().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "could not find the config folder")
+        })?;
+        let to_io = |e| io::Error::new(io::ErrorKind::Other, e);
+        if let Some(dest) = &opt.export_settings {
+            config::export(&dir, dest).map_err(to_io)?;
+            log::info!("wrote {}", dest.display());
+        }
+        if let Some(src) = &opt.import_settings {
+            config::import(src, &dir).map_err(to_io)?;
+            log::info!("imported settings from {}", src.display());
+        }
+        return Ok(());
+    }
+    let mut files = cli::expand_paths(&opt.files);
+    let read_stdin = files.iter().any(|path| path == Path::new("-"));
+    files.retain(|path| path != Path::new("-"));
+    if let Some(dest) = &opt.export_pdf {
+        let options = PdfOptions {
+            page_size: opt.page_size,
+            margin: opt.margin,
+            fit: opt.fit,
+        };
+        pdf::export(&files, dest, &options).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        log::info!("wrote {}", dest.display());
+        return Ok(());
+    }
+    if let (Some(dest), [old, new]) = (&opt.pair_report, &opt.compare_dirs[..]) {
+        let pairs = pairs::compare(old, new)?;
+        pairs::write_report(&pairs, dest)?;
+        log::info!("{}", pairs::summary(&pairs));
+        log::info!("wrote {}", dest.display());
+        return Ok(());
+    }
+    // This must happen before GTK starts.
+    shell::use_portals_if_sandboxed();
+
+    let (config, problems) = match shell::config_dir() {
+        Some(dir) => Config::load(&dir),
+        None => (Config::default(), vec![]),
+    };
+
+    // Half the budget for decoded images, half for their textures.
+    let budget = opt.cache_mb << 20;
+    let mut main_window = WindowDesc::new(ui_builder(budget / 2, &config)).title("Image Viewer");
+    if opt.fullscreen {
+        // Druid can't make a window fullscreen, so fill the screen without decorations instead.
+        main_window = main_window
+            .show_titlebar(false)
+            .set_window_state(WindowState::Maximized);
+    }
     // Set our initial data
-    let data = AppData::new();
+    let mut data = AppData::new();
+    data.eink = opt.eink;
+    data.auto_rotate = opt.auto_rotate;
+    data.initial_zoom = opt.scale;
+    data.slideshow = opt.slideshow;
+    data.background = opt.background.clone().or_else(|| config.background.clone());
+    data.fullscreen = opt.fullscreen;
+    data.theme = config.theme.unwrap_or_else(Theme::detect);
+    data.macros = Macros::new(config.macros);
+    data.toasts = Toasts::new(Duration::from_secs_f64(opt.toast_secs.max(0.)));
+    if !problems.is_empty() {
+        for problem in &problems {
+            log::warn!("{}", problem);
+        }
+        data.toasts.push(Toast::error(
+            "Some settings couldn't be read",
+            problems.join("\n"),
+        ));
+    }
     let launcher = AppLauncher::with_window(main_window);
 
     // worker thread for IO
-    let (ui_tx, ui_rx) = channel::unbounded::<UiMsg>();
-    let mut io_state = IoState::new(ui_rx, launcher.get_external_handle())?;
-    let io_thread = thread::spawn(move || io_state.run());
+    let (ui_tx, io_thread) = loader::spawn(launcher.get_external_handle());
+
+    if let [old, new] = &opt.compare_dirs[..] {
+        data.pairs.open(old.clone(), new.clone());
+        ui_tx.send(UiMsg::ComparePairs {
+            old: old.clone(),
+            new: new.clone(),
+        })?;
+    }
+    if read_stdin {
+        data.loading = Some("standard input".into());
+        ui_tx.send(UiMsg::ReadStdin)?;
+    } else if let Some(first) = files.first() {
+        data.loading = Some(file_name(first));
+        ui_tx.send(UiMsg::LoadImage(first.clone()))?;
+        ui_tx.send(UiMsg::ScanDir(first.clone()))?;
+    }
 
     launcher
         .delegate(Delegate {
             ui_tx: ui_tx.clone(),
+            history: History::with_entries(files),
+            cache: ImageCache::new(budget / 2),
+            save_options: EncodeOptions {
+                quality: opt.quality,
+            },
+            keymap: config.keymap,
+            slideshow: opt.slideshow.unwrap_or(config.slideshow),
+            upscaler: opt.upscaler.as_ref().map(|program| {
+                let upscaler = ExternalUpscaler::new(program.clone(), &opt.upscaler_args);
+                Arc::new(upscaler) as Arc<dyn Upscaler>
+            }),
+            upscale_by: opt.upscale_by,
         })
         .launch(data)
         .expect("launch failed");
 
-    // shut down gracefully
-    ui_tx.send(UiMsg::Shutdown).unwrap();
+    // shut down gracefully (the io thread may already have gone if it failed to start)
+    let _ = ui_tx.send(UiMsg::Shutdown);
     io_thread.join().unwrap();
     Ok(())
 }
 
-/// State for the i/o thread
-struct IoState {
-    ui_rx: Receiver<UiMsg>,
-    evt_sink: ExtEventSink,
-    open_file: Option<PathBuf>,
-    watcher: RecommendedWatcher,
-    watcher_rx: Receiver<Result<notify::Event, notify::Error>>,
-}
-
-impl IoState {
-    fn new(ui_rx: Receiver<UiMsg>, evt_sink: ExtEventSink) -> Result<Self> {
-        let (watcher_tx, watcher_rx) = channel::unbounded();
-        Ok(Self {
-            ui_rx,
-            evt_sink,
-            open_file: None,
-            watcher: notify::recommended_watcher(watcher_tx)?,
-            watcher_rx,
-        })
-    }
-    fn run(&mut self) {
-        loop {
-            channel::select! {
-                recv(self.ui_rx) -> msg => if !self.handle_ui(msg) {
-                    break;
-                },
-                recv(self.watcher_rx) -> msg => if !self.handle_notify(msg) {
-                    break;
-                }
-            }
-        }
-    }
-
-    // returns false on error
-    fn handle_ui(&mut self, msg: Result<UiMsg, RecvError>) -> bool {
-        match msg {
-            Ok(UiMsg::LoadImage(path)) => self.load_img(path),
-            Ok(UiMsg::Shutdown) | Err(_) => false,
-        }
-    }
-
-    fn handle_notify(
-        &mut self,
-        msg: Result<Result<notify::Event, notify::Error>, RecvError>,
-    ) -> bool {
-        let msg = match msg {
-            Ok(v) => v,
-            Err(e) => {
-                log::error!("{}", e);
-                return false;
-            }
-        };
-        let evt = match msg {
-            Ok(v) => v,
-            Err(e) => {
-                log::error!("{}", e);
-                return false;
-            }
-        };
-        match &evt.kind {
-            notify::EventKind::Modify(_) => {
-                if let Some(path) = self.open_file.take() {
-                    // sleep for a bit to let the write finish
-                    // TODO We need to pump events for the duration, otherwise they will back up.
-                    thread::sleep(Duration::from_millis(1000));
-                    self.load_img(path)
-                } else {
-                    true
-                }
-            }
-            _ => true,
-        }
-    }
-
-    fn load_img(&mut self, path: PathBuf) -> bool {
-        if let Some(prev) = self.open_file.as_ref() {
-            self.watcher.unwatch(prev).unwrap(); // TODO handle errors
-        }
-        self.open_file = None;
-        let image = ImageBuf::from_file(&path);
-        // only update state if the load was successful.
-        log::debug!("watching {}", path.display());
-        self.watcher
-            .watch(&path, RecursiveMode::NonRecursive)
-            .unwrap();
-        self.open_file = Some(path.clone());
-        if let Err(_) =
-            self.evt_sink
-                .submit_command(FILE_LOADED, SingleUse::new(image), Target::Global)
-        {
-            log::error!("should be unreachable");
-            false
-        } else {
-            true
-        }
-    }
-}
-
-fn ui_builder() -> impl Widget<AppData> {
+fn ui_builder(texture_budget: usize, config: &Config) -> impl Widget<AppData> {
     let ribbon = Flex::row()
         .with_child(open_button())
         .with_flex_spacer(1.)
         .with_child(zoom_out_button())
         .with_child(zoom_1_button())
         .with_child(zoom_fit_button())
+        .with_child(zoom_to_subject_button())
         .with_child(zoom_in_button())
+        .with_child(rotate_ccw_button())
+        .with_child(rotate_cw_button())
+        .with_child(flip_button("Flip H", FLIP_H))
+        .with_child(flip_button("Flip V", FLIP_V))
+        .with_child(crop_button())
+        .with_child(save_as_button())
+        .with_child(wallpaper_button())
+        .with_child(upscale_button())
+        .with_child(reveal_button())
         .with_flex_spacer(1.)
+        .with_child(expression_button())
+        .with_child(palette_button())
+        .with_child(export_pdf_button())
+        .with_child(email_button())
+        .with_child(manifest_button())
+        .with_child(folder_stats_button())
+        .with_child(blink_button())
+        .with_child(macros_button())
+        .with_child(about_button())
         .with_child(close_button());
-    Flex::column()
-        .with_child(ribbon)
+    let content = Flex::column()
+        .with_child(chrome(ribbon))
         .with_flex_child(
-            Maybe::or_empty(|| ZoomImage::new())
-                .lens(AppData::image)
-                .center(),
+            Flex::row()
+                .with_flex_child(
+                    Maybe::or_empty(|| {
+                        Flex::column()
+                            .with_flex_child(
+                                ZoomImage::new()
+                                    .snap_to_pixels(true)
+                                    .with_scrollbars(true)
+                                    .with_texture_budget(texture_budget)
+                                    .with_scale_range(config.min_zoom, config.max_zoom)
+                                    .with_animation_duration(config.animation)
+                                    .with_interpolation(config.interpolation)
+                                    .with_backdrop(config.transparency.clone()),
+                                1.0,
+                            )
+                            .with_child(Either::new(
+                                |data: &ViewerState, _| data.fits.is_some(),
+                                stretch_controls(),
+                                SizedBox::empty(),
+                            ))
+                            .with_child(Either::new(
+                                |data: &ViewerState, _| data.cropping,
+                                crop_controls(),
+                                SizedBox::empty(),
+                            ))
+                    })
+                    .lens(AppData::viewer)
+                    .center(),
+                    1.0,
+                )
+                .with_child(Either::new(
+                    |data: &AppData, _| data.show_exif && !data.fullscreen,
+                    exif_panel(),
+                    SizedBox::empty(),
+                ))
+                .with_child(Either::new(
+                    |data: &AppData, _| data.show_folder_stats && !data.fullscreen,
+                    folder_stats_panel(),
+                    SizedBox::empty(),
+                )),
             1.0,
         )
+        .with_child(Either::new(
+            |data: &AppData, _| data.show_expression && !data.fullscreen,
+            expression_panel(),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.palette.show && !data.fullscreen,
+            palette_panel(),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.toasts.show_history && !data.fullscreen,
+            toast::history_panel().lens(AppData::toasts),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.note.show && !data.fullscreen,
+            notes::panel().lens(AppData::note),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.blink.show && !data.fullscreen,
+            blink::panel().lens(AppData::blink),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.pairs.show && !data.fullscreen,
+            pairs::panel().lens(AppData::pairs),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.macros.show && !data.fullscreen,
+            macros::panel().lens(AppData::macros),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.show_pdf && !data.fullscreen,
+            pdf_panel(),
+            SizedBox::empty(),
+        ))
+        .with_child(Either::new(
+            |data: &AppData, _| data.failures.show && !data.fullscreen,
+            failures::panel().lens(AppData::failures),
+            SizedBox::empty(),
+        ))
         .with_child(
             Flex::row()
-                .with_child(Label::raw().lens(AppData::error))
                 .with_flex_spacer(1.)
-                .with_child(Label::raw().lens(AppData::info)),
+                .with_child(toast::stack().lens(AppData::toasts))
+                .padding(4.),
         )
+        .with_child(chrome(
+            Flex::row()
+                .with_child(Label::dynamic(|data: &AppData, _| {
+                    status::summary(
+                        data.file.as_ref(),
+                        data.viewer
+                            .as_ref()
+                            .map(|viewer| (viewer.image.width(), viewer.image.height())),
+                        // An image that isn't in a file isn't in the folder either.
+                        data.library
+                            .as_ref()
+                            .filter(|_| data.file.is_some())
+                            .map(|list| (list.position(), list.paths().len())),
+                    )
+                }))
+                .with_spacer(8.)
+                .with_child(status::zoom_entry().lens(AppData::zoom))
+                .with_spacer(8.)
+                .with_child(Label::raw().lens(AppData::error))
+                .with_child(
+                    Label::dynamic(|data: &Option<ArcStr>, _| match data {
+                        Some(name) => format!("Loading {}…", name),
+                        None => String::new(),
+                    })
+                    .lens(AppData::loading),
+                )
+                .with_flex_spacer(1.)
+                .with_child(
+                    Label::dynamic(|data: &Exposure, _| data.to_string())
+                        .with_text_color(Color::rgb8(0xff, 0xa0, 0x00))
+                        .lens(AppData::exposure),
+                )
+                .with_flex_spacer(1.)
+                .with_child(
+                    Label::dynamic(|data: &Option<ViewerState>, _| dicom_summary(data.as_ref()))
+                        .lens(AppData::viewer),
+                )
+                .with_spacer(8.)
+                .with_child(
+                    Label::dynamic(|data: &Option<ViewerState>, _| match data {
+                        Some(viewer) => viewer.color_space.to_string(),
+                        None => String::new(),
+                    })
+                    .lens(AppData::viewer),
+                )
+                .with_child(Checkbox::new("Convert").lens(AppData::viewer.map(
+                    |viewer: &Option<ViewerState>| {
+                        viewer.as_ref().map_or(true, |v| v.convert_colors)
+                    },
+                    |viewer: &mut Option<ViewerState>, convert| {
+                        if let Some(viewer) = viewer {
+                            viewer.convert_colors = convert;
+                        }
+                    },
+                )))
+                .with_child(Checkbox::new("Inspect").lens(AppData::viewer.map(
+                    |viewer: &Option<ViewerState>| viewer.as_ref().map_or(false, |v| v.inspect),
+                    |viewer: &mut Option<ViewerState>, inspect| {
+                        if let Some(viewer) = viewer {
+                            viewer.inspect = inspect;
+                        }
+                    },
+                )))
+                .with_child(Checkbox::new("E-ink").lens(lens::Identity.map(
+                    |data: &AppData| data.eink,
+                    |data: &mut AppData, eink| {
+                        data.eink = eink;
+                        if let Some(viewer) = data.viewer.as_mut() {
+                            viewer.eink = eink;
+                        }
+                    },
+                )))
+                .with_child(Checkbox::new("Auto-rotate").lens(lens::Identity.map(
+                    |data: &AppData| data.auto_rotate,
+                    |data: &mut AppData, auto_rotate| {
+                        data.auto_rotate = auto_rotate;
+                        if let Some(viewer) = data.viewer.as_mut() {
+                            viewer.auto_rotate = auto_rotate;
+                        }
+                    },
+                )))
+                .with_spacer(8.)
+                .with_child(profile_picker())
+                .with_spacer(8.)
+                .with_child(background_picker())
+                .with_spacer(8.)
+                .with_child(integrity_badge().lens(AppData::integrity))
+                .with_spacer(8.)
+                .with_child(notes::indicator().lens(AppData::note))
+                .with_spacer(8.)
+                .with_child(Label::raw().lens(AppData::mirrored))
+                .with_spacer(8.)
+                .with_child(
+                    Button::dynamic(|data: &AppData, _| {
+                        format!("{} theme", data.theme.toggled().name())
+                    })
+                    .on_click(|ctx, _, _| ctx.submit_command(TOGGLE_THEME)),
+                )
+                .with_child(
+                    Button::new("Messages").on_click(|_, data: &mut AppData, _| {
+                        data.toasts.show_history = !data.toasts.show_history;
+                    }),
+                )
+                .with_child(
+                    Button::dynamic(|data: &Failures, _| {
+                        format!("Failed ({})", data.entries.len())
+                    })
+                    .on_click(|_, data: &mut Failures, _| data.show = !data.show)
+                    .lens(AppData::failures),
+                ),
+        ));
+    let root = Modal::new(
+        content
+            .controller(Slideshow::default())
+            .controller(Blinker::default())
+            .controller(Fullscreen::default()),
+        dialog::view().lens(AppData::dialogs),
+        |data: &AppData| data.dialogs.is_open(),
+    );
+    EnvScope::new(|env, data: &AppData| data.theme.apply(env), root)
     //.debug_paint_layout()
 }
 
+/// `widget` on the theme's chrome colour, except in fullscreen, where only the image is shown.
+fn chrome(widget: impl Widget<AppData> + 'static) -> impl Widget<AppData> {
+    Either::new(
+        |data: &AppData, _| data.fullscreen,
+        SizedBox::empty(),
+        widget.background(theme::CHROME),
+    )
+}
+
 fn open_button() -> impl Widget<AppData> {
     BgHover::new(
         Flex::column()
-            .with_child(Icon::new(IMAGE, Color::WHITE).fix_height(30.))
+            .with_child(Icon::new(IMAGE, theme::ICON).fix_height(30.))
             // no need for spacer because of spacing around image
             .with_child(Label::new("Open"))
             .padding(4.)
@@ -226,7 +904,7 @@ const ZOOM_FACTOR: f64 = 1.5;
 fn zoom_out_button() -> impl Widget<AppData> {
     BgHover::new(
         Flex::column()
-            .with_child(Icon::new(REMOVE, Color::WHITE).fix_height(30.))
+            .with_child(Icon::new(REMOVE, theme::ICON).fix_height(30.))
             .with_child(Label::new(""))
             .padding(4.)
             .on_click(|ctx, _, _| {
@@ -238,7 +916,7 @@ fn zoom_out_button() -> impl Widget<AppData> {
 fn zoom_1_button() -> impl Widget<AppData> {
     BgHover::new(
         Flex::column()
-            .with_child(Icon::new(SEARCH, Color::WHITE).fix_height(30.))
+            .with_child(Icon::new(SEARCH, theme::ICON).fix_height(30.))
             .with_child(Label::new("100%"))
             .padding(4.)
             .on_click(|ctx, _, _| {
@@ -250,7 +928,7 @@ fn zoom_1_button() -> impl Widget<AppData> {
 fn zoom_in_button() -> impl Widget<AppData> {
     BgHover::new(
         Flex::column()
-            .with_child(Icon::new(ADD, Color::WHITE).fix_height(30.))
+            .with_child(Icon::new(ADD, theme::ICON).fix_height(30.))
             .with_child(Label::new(""))
             .padding(4.)
             .on_click(|ctx, _, _| {
@@ -262,7 +940,7 @@ fn zoom_in_button() -> impl Widget<AppData> {
 fn zoom_fit_button() -> impl Widget<AppData> {
     BgHover::new(
         Flex::column()
-            .with_child(Icon::new(SEARCH, Color::WHITE).fix_height(30.))
+            .with_child(Icon::new(SEARCH, theme::ICON).fix_height(30.))
             .with_child(Label::new("Fit"))
             .padding(4.)
             .on_click(|ctx, _, _| {
@@ -271,64 +949,1835 @@ fn zoom_fit_button() -> impl Widget<AppData> {
     )
 }
 
-fn close_button() -> impl Widget<AppData> {
+fn zoom_to_subject_button() -> impl Widget<AppData> {
     BgHover::new(
         Flex::column()
-            .with_child(Icon::new(EXIT_TO_APP, Color::WHITE).fix_height(30.))
-            .with_child(Label::new("Exit"))
+            .with_child(Icon::new(CENTER_FOCUS_STRONG, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Subject"))
             .padding(4.)
             .on_click(|ctx, _, _| {
-                ctx.submit_command(QUIT_APP);
+                ctx.submit_command(ZOOM_TO_SUBJECT);
             }),
     )
 }
 
-enum UiMsg {
-    LoadImage(PathBuf),
-    Shutdown,
+fn rotate_ccw_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(ROTATE_LEFT, theme::ICON).fix_height(30.))
+            .with_child(Label::new(""))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(ROTATE_CCW);
+            }),
+    )
 }
 
-struct Delegate {
-    ui_tx: channel::Sender<UiMsg>,
+fn rotate_cw_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(ROTATE_RIGHT, theme::ICON).fix_height(30.))
+            .with_child(Label::new(""))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(ROTATE_CW);
+            }),
+    )
 }
 
-impl AppDelegate<AppData> for Delegate {
-    fn command(
+/// Mirror the view with `cmd`, to check a composition without editing the file.
+fn flip_button(label: &str, cmd: Selector) -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(FLIP, theme::ICON).fix_height(30.))
+            .with_child(Label::new(label))
+            .padding(4.)
+            .on_click(move |ctx, _, _| {
+                ctx.submit_command(cmd);
+            }),
+    )
+}
+
+fn crop_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(CROP, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Crop"))
+            .padding(4.)
+            .on_click(|_, data: &mut AppData, _| {
+                if let Some(viewer) = data.viewer.as_mut() {
+                    viewer.cropping = !viewer.cropping;
+                }
+            }),
+    )
+}
+
+fn save_as_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(SAVE, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Save as"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(SHOW_SAVE_AS);
+            }),
+    )
+}
+
+fn reveal_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(FOLDER_OPEN, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Reveal"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(REVEAL_FILE);
+            }),
+    )
+}
+
+fn expression_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(FUNCTIONS, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Math"))
+            .padding(4.)
+            .on_click(|_, data: &mut AppData, _| {
+                data.show_expression = !data.show_expression;
+                data.apply_expression();
+            }),
+    )
+}
+
+/// A box to type a per-pixel expression into, which is applied as it is typed.
+fn expression_panel() -> impl Widget<AppData> {
+    Flex::row()
+        .with_child(Label::new("a ="))
+        .with_spacer(4.)
+        .with_flex_child(
+            TextBox::new()
+                .with_placeholder("a*1.2 + 0.05")
+                .expand_width()
+                .lens(AppData::expression)
+                .controller(ApplyExpression),
+            1.,
+        )
+        .with_spacer(8.)
+        .with_child(
+            Label::raw()
+                .with_text_color(Color::rgb8(0xff, 0x40, 0x40))
+                .lens(AppData::expression_error),
+        )
+        .padding(4.)
+}
+
+/// Steps to the next image every `AppData::slideshow`, stopping at the last one.
+struct Slideshow {
+    timer: TimerToken,
+}
+
+impl Default for Slideshow {
+    fn default() -> Self {
+        Self {
+            timer: TimerToken::INVALID,
+        }
+    }
+}
+
+impl<W: Widget<AppData>> Controller<AppData, W> for Slideshow {
+    fn event(
         &mut self,
-        _ctx: &mut DelegateCtx,
-        _target: Target,
-        cmd: &Command,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
         data: &mut AppData,
-        _env: &Env,
-    ) -> Handled {
-        if let Some(file) = cmd.get(OPEN_FILE) {
-            if let Err(e) = self.ui_tx.send(UiMsg::LoadImage(file.path().to_owned())) {
-                data.set_error(format!("error sending message to io thread: {}", e).into());
+        env: &Env,
+    ) {
+        match event {
+            Event::Timer(token) if *token == self.timer => {
+                // The slideshow may have been stopped since the timer was set.
+                self.timer = match data.slideshow {
+                    Some(interval) => {
+                        ctx.submit_command(NEXT_IMAGE);
+                        ctx.request_timer(interval)
+                    }
+                    None => TimerToken::INVALID,
+                };
             }
-            Handled::Yes
-        } else if let Some(img) = cmd.get(FILE_LOADED) {
-            match img.take().unwrap() {
-                Ok(img) => data.set_image(Arc::new(img)),
-                Err(e) => data.set_error(format!("error decoding/loading image: {}", e).into()),
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &AppData,
+        env: &Env,
+    ) {
+        if let (LifeCycle::WidgetAdded, Some(interval)) = (event, data.slideshow) {
+            self.timer = ctx.request_timer(interval);
+        }
+        child.lifecycle(ctx, event, data, env)
+    }
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &AppData,
+        data: &AppData,
+        env: &Env,
+    ) {
+        if let (Some(interval), None) = (data.slideshow, old_data.slideshow) {
+            self.timer = ctx.request_timer(interval);
+        }
+        child.update(ctx, old_data, data, env)
+    }
+}
+
+/// Sends `BLINK_STEP` every `Blink::interval` while blinking.
+struct Blinker {
+    timer: TimerToken,
+}
+
+impl Default for Blinker {
+    fn default() -> Self {
+        Self {
+            timer: TimerToken::INVALID,
+        }
+    }
+}
+
+impl<W: Widget<AppData>> Controller<AppData, W> for Blinker {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Timer(token) if *token == self.timer => {
+                self.timer = if data.blink.playing {
+                    ctx.submit_command(BLINK_STEP);
+                    ctx.request_timer(data.blink.interval())
+                } else {
+                    TimerToken::INVALID
+                };
             }
-            Handled::Yes
-        } else if let Some(trans) = cmd.get(NOTIFY_TRANSFORM) {
-            let (translate, scale) = trans.as_tuple();
-            data.info = format!(
-                "scale: {:4.0}% translate: ({:.0},{:.0})",
-                // little fiddle to get correct values
-                scale.recip() * 100.,
-                translate.x.max(0.),
-                translate.y.max(0.),
-            )
-            .into();
-            Handled::No
-        } else {
-            Handled::No
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &AppData,
+        data: &AppData,
+        env: &Env,
+    ) {
+        if data.blink.playing && !old_data.blink.playing {
+            self.timer = ctx.request_timer(data.blink.interval());
+        }
+        child.update(ctx, old_data, data, env)
+    }
+}
+
+/// Takes the window in and out of fullscreen, putting it back where it was afterwards.
+#[derive(Default)]
+struct Fullscreen {
+    /// The window's position, size and state before it went fullscreen. This is `None` if it
+    /// started fullscreen.
+    restore: Option<(Point, Size, WindowState)>,
+}
+
+impl<W: Widget<AppData>> Controller<AppData, W> for Fullscreen {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Command(cmd) if cmd.is(TOGGLE_FULLSCREEN) => {
+                let mut window = ctx.window().clone();
+                if data.fullscreen {
+                    window.show_titlebar(true);
+                    match self.restore.take() {
+                        Some((position, size, state)) => {
+                            window.set_window_state(state);
+                            if matches!(state, WindowState::Restored) {
+                                window.set_position(position);
+                                window.set_size(size);
+                            }
+                        }
+                        None => window.set_window_state(WindowState::Restored),
+                    }
+                } else {
+                    // Druid can't make a window fullscreen, so fill the screen without
+                    // decorations instead, as `--fullscreen` does.
+                    self.restore = Some((
+                        window.get_position(),
+                        window.get_size(),
+                        window.get_window_state(),
+                    ));
+                    window.show_titlebar(false);
+                    window.set_window_state(WindowState::Maximized);
+                }
+                data.fullscreen = !data.fullscreen;
+                // The status bar and panels go, and a text box that goes with the keyboard doesn't
+                // say it has lost it.
+                data.editing_text = false;
+                // Fit the image to the new size, rather than keeping the zoom it had in the
+                // smaller view.
+                ctx.submit_command(SET_SCALE.with(0.));
+                ctx.set_handled();
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
+
+/// Reparses the expression whenever the text box changes it.
+struct ApplyExpression;
+
+impl<W: Widget<AppData>> Controller<AppData, W> for ApplyExpression {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        let before = data.expression.clone();
+        child.event(ctx, event, data, env);
+        if data.expression != before {
+            data.apply_expression();
         }
     }
 }
 
+fn palette_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(PALETTE, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Palette"))
+            .padding(4.)
+            .on_click(|ctx, data: &mut AppData, _| {
+                data.palette.show = !data.palette.show;
+                if data.palette.show {
+                    ctx.submit_command(EXTRACT_PALETTE);
+                }
+            }),
+    )
+}
+
+/// The dominant colours of the image, with controls for how many to find and buttons to copy
+/// them.
+fn palette_panel() -> impl Widget<AppData> {
+    let size = Flex::row()
+        .with_child(
+            Slider::new()
+                .with_range(palette::MIN_SWATCHES as f64, palette::MAX_SWATCHES as f64)
+                .lens(Palette::size),
+        )
+        .with_child(Label::dynamic(|data: &Palette, _| {
+            format!("{} colours", data.size.round())
+        }))
+        .lens(AppData::palette);
+    Flex::row()
+        .with_child(size)
+        .with_child(Button::new("Extract").on_click(|ctx, _, _| {
+            ctx.submit_command(EXTRACT_PALETTE);
+        }))
+        .with_spacer(8.)
+        .with_flex_child(
+            swatches().lens(AppData::palette.then(Palette::swatches)),
+            1.,
+        )
+        .with_child(
+            Button::new("Copy CSS").on_click(|_, data: &mut AppData, _| {
+                let css = palette::to_css(&data.palette.swatches);
+                Application::global().clipboard().put_string(css);
+                data.toasts.push(Toast::info("Copied CSS to clipboard"));
+            }),
+        )
+        .with_child(
+            Button::new("Copy JSON").on_click(|_, data: &mut AppData, _| {
+                let json = palette::to_json(&data.palette.swatches);
+                Application::global().clipboard().put_string(json);
+                data.toasts.push(Toast::info("Copied JSON to clipboard"));
+            }),
+        )
+        .padding(4.)
+}
+
+/// A row of colour chips, each labelled with its hex value.
+fn swatches() -> impl Widget<Arc<Vec<Swatch>>> {
+    ViewSwitcher::new(
+        |swatches: &Arc<Vec<Swatch>>, _| swatches.clone(),
+        |swatches, _, _| {
+            let mut row = Flex::row();
+            for swatch in swatches.iter() {
+                let [r, g, b] = swatch.rgb;
+                row.add_child(
+                    Flex::column()
+                        .with_child(
+                            SizedBox::empty()
+                                .fix_size(32., 32.)
+                                .background(Color::rgb8(r, g, b)),
+                        )
+                        .with_child(Label::new(swatch.hex()).with_text_size(11.)),
+                );
+                row.add_spacer(4.);
+            }
+            Box::new(row)
+        },
+    )
+}
+
+/// The size of the current image and the camera settings it was taken with. Ctrl+I shows and
+/// hides it.
+fn exif_panel() -> impl Widget<AppData> {
+    let rows = ViewSwitcher::new(
+        |data: &Option<ViewerState>, _| {
+            data.as_ref()
+                .map(|viewer| (viewer.image.clone(), viewer.exif.clone()))
+        },
+        |data, _, _| {
+            let mut rows = vec![];
+            if let Some((image, exif)) = data {
+                rows.push((
+                    "Dimensions",
+                    format!("{} × {}", image.width(), image.height()),
+                ));
+                if let Some(exif) = exif {
+                    rows.extend(exif.rows());
+                }
+            }
+            let mut column = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+            for (name, value) in rows {
+                column.add_child(
+                    Label::new(name)
+                        .with_text_size(11.)
+                        .with_text_color(Color::grey8(0xa0)),
+                );
+                column.add_child(Label::new(value));
+                column.add_spacer(4.);
+            }
+            Box::new(column)
+        },
+    )
+    .lens(AppData::viewer);
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Metadata"))
+                .with_flex_spacer(1.)
+                .with_child(Button::new("×").on_click(|_, data: &mut AppData, _| {
+                    data.show_exif = false;
+                })),
+        )
+        .with_spacer(4.)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("Copy path").on_click(|ctx, _, _| ctx.submit_command(COPY_PATH)),
+                )
+                .with_spacer(4.)
+                .with_child(
+                    Button::new("Copy file").on_click(|ctx, _, _| ctx.submit_command(COPY_FILE)),
+                ),
+        )
+        .with_spacer(4.)
+        .with_child(rows)
+        .padding(4.)
+        .fix_width(220.)
+}
+
+fn folder_stats_panel() -> impl Widget<AppData> {
+    let rows = ViewSwitcher::new(
+        |data: &Option<Arc<FolderStats>>, _| data.clone(),
+        |data, _, _| {
+            let mut column = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+            let stats = match data {
+                Some(stats) => stats,
+                None => {
+                    column.add_child(Label::new("Counting…"));
+                    return Box::new(column);
+                }
+            };
+            for (name, value) in stats.rows() {
+                column.add_child(
+                    Label::new(name)
+                        .with_text_size(11.)
+                        .with_text_color(Color::grey8(0xa0)),
+                );
+                column.add_child(Label::new(value));
+                column.add_spacer(4.);
+            }
+            Box::new(column)
+        },
+    )
+    .lens(AppData::folder_stats);
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Folder"))
+                .with_flex_spacer(1.)
+                .with_child(Button::new("×").on_click(|_, data: &mut AppData, _| {
+                    data.show_folder_stats = false;
+                })),
+        )
+        .with_spacer(4.)
+        .with_child(rows)
+        .padding(4.)
+        .fix_width(220.)
+}
+
+fn folder_stats_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(ASSESSMENT, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Folder"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(TOGGLE_FOLDER_STATS);
+            }),
+    )
+}
+
+fn blink_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(COMPARE, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Blink"))
+            .padding(4.)
+            .on_click(|_, data: &mut AppData, _| {
+                data.blink.show = !data.blink.show;
+            }),
+    )
+}
+
+fn macros_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(PLAYLIST_PLAY, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Macros"))
+            .padding(4.)
+            .on_click(|ctx, _, _| ctx.submit_command(TOGGLE_MACROS)),
+    )
+}
+
+fn export_pdf_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(PICTURE_AS_PDF, theme::ICON).fix_height(30.))
+            .with_child(Label::new("PDF"))
+            .padding(4.)
+            .on_click(|_, data: &mut AppData, _| data.show_pdf = !data.show_pdf),
+    )
+}
+
+/// How to lay out the pages of a PDF of the folder, and a button to choose where to save it.
+fn pdf_panel() -> impl Widget<AppData> {
+    let page_sizes = Flex::row()
+        .with_child(Radio::new("A3", PageSize::A3))
+        .with_child(Radio::new("A4", PageSize::A4))
+        .with_child(Radio::new("A5", PageSize::A5))
+        .with_child(Radio::new("Letter", PageSize::Letter))
+        .with_child(Radio::new("Legal", PageSize::Legal))
+        .lens(PdfOptions::page_size);
+    let fits = Flex::row()
+        .with_child(Radio::new("Whole image", Fit::Contain))
+        .with_child(Radio::new("Fill the page", Fit::Cover))
+        .with_child(Radio::new("Actual size", Fit::Shrink))
+        .lens(PdfOptions::fit);
+    let margin = Flex::row()
+        .with_child(
+            Slider::new()
+                .with_range(0., MAX_PDF_MARGIN)
+                .lens(PdfOptions::margin),
+        )
+        .with_child(Label::dynamic(|data: &PdfOptions, _| {
+            format!("{} mm", data.margin.round())
+        }));
+    let options = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Page"))
+                .with_child(page_sizes),
+        )
+        .with_child(Flex::row().with_child(Label::new("Fit")).with_child(fits))
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Margin"))
+                .with_child(margin),
+        )
+        .lens(AppData::pdf_options);
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("PDF of this folder"))
+                .with_flex_spacer(1.)
+                .with_child(Button::new("Save PDF…").on_click(|ctx, _, _| {
+                    ctx.submit_command(
+                        SHOW_SAVE_PANEL.with(
+                            FileDialogOptions::new()
+                                .allowed_types(vec![FileSpec::PDF])
+                                .default_name("images.pdf")
+                                .accept_command(EXPORT_PDF),
+                        ),
+                    );
+                }))
+                .with_child(Button::new("×").on_click(|_, data: &mut AppData, _| {
+                    data.show_pdf = false;
+                })),
+        )
+        .with_spacer(4.)
+        .with_child(options)
+        .padding(4.)
+}
+
+fn wallpaper_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(WALLPAPER, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Wallpapers"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(EXPORT_WALLPAPERS);
+            }),
+    )
+}
+
+fn upscale_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(PHOTO_SIZE_SELECT_LARGE, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Upscale"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(UPSCALE);
+            }),
+    )
+}
+
+fn email_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(EMAIL, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Email"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(SEND_EMAIL);
+            }),
+    )
+}
+
+fn manifest_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(FINGERPRINT, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Checksums"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(WRITE_MANIFEST);
+            }),
+    )
+}
+
+/// Controls for the crop tool's aspect ratio, and buttons to apply or cancel the crop.
+fn crop_controls() -> impl Widget<ViewerState> {
+    let aspects = Flex::row()
+        .with_child(Radio::new("Free", CropAspect::Free))
+        .with_child(Radio::new("Original", CropAspect::Original))
+        .with_child(Radio::new("1:1", CropAspect::Ratio(1, 1)))
+        .with_child(Radio::new("4:3", CropAspect::Ratio(4, 3)))
+        .with_child(Radio::new("3:2", CropAspect::Ratio(3, 2)))
+        .with_child(Radio::new("16:9", CropAspect::Ratio(16, 9)))
+        .lens(ViewerState::crop_aspect);
+    Flex::row()
+        .with_child(Label::new("Aspect"))
+        .with_child(aspects)
+        .with_flex_spacer(1.)
+        .with_child(Button::new("Crop").on_click(|ctx, _, _| {
+            ctx.submit_command(CROP_APPLY.with(None));
+        }))
+        .with_child(Button::new("Crop and save as…").on_click(|ctx, _, _| {
+            ctx.submit_command(
+                SHOW_SAVE_PANEL.with(
+                    FileDialogOptions::new()
+                        .allowed_types(SAVE_FORMATS.to_vec())
+                        .default_name("cropped.png")
+                        .accept_command(SAVE_CROP),
+                ),
+            );
+        }))
+        .with_child(
+            Button::new("Cancel").on_click(|_, data: &mut ViewerState, _| data.cropping = false),
+        )
+        .padding(4.)
+}
+
+/// Controls for how FITS images are stretched.
+///
+/// The black and white points are set as percentiles of the histogram, so the sliders are useful
+/// whatever the range of the data.
+fn stretch_controls() -> impl Widget<ViewerState> {
+    let kinds = Flex::row()
+        .with_child(Radio::new("Linear", StretchKind::Linear))
+        .with_child(Radio::new("Log", StretchKind::Log))
+        .with_child(Radio::new("Asinh", StretchKind::Asinh))
+        .with_child(Radio::new("ZScale", StretchKind::ZScale))
+        .lens(Stretch::kind);
+    let limits = Label::dynamic(|data: &ViewerState, _| match &data.fits {
+        Some(fits) => {
+            let (lo, hi) = fits.limits(data.stretch);
+            format!("black: {:.4}  white: {:.4}", lo, hi)
+        }
+        None => String::new(),
+    });
+    Flex::row()
+        .with_child(kinds.lens(ViewerState::stretch))
+        .with_spacer(8.)
+        .with_child(Label::new("Black"))
+        .with_child(
+            Slider::new()
+                .lens(Stretch::black)
+                .lens(ViewerState::stretch),
+        )
+        .with_child(Label::new("White"))
+        .with_child(
+            Slider::new()
+                .lens(Stretch::white)
+                .lens(ViewerState::stretch),
+        )
+        .with_spacer(8.)
+        .with_child(limits)
+        .padding(4.)
+}
+
+/// Key tags and the current window, if the image is DICOM.
+fn dicom_summary(viewer: Option<&ViewerState>) -> String {
+    let viewer = match viewer {
+        Some(viewer) => viewer,
+        None => return String::new(),
+    };
+    match &viewer.dicom {
+        Some(dicom) => {
+            let mut out = String::new();
+            for (name, value) in &dicom.tags {
+                out.push_str(&format!("{}: {}  ", name, value));
+            }
+            out.push_str(&format!(
+                "W: {:.0} L: {:.0}",
+                viewer.window.width, viewer.window.center
+            ));
+            out
+        }
+        None => String::new(),
+    }
+}
+
+/// Choose a profile for every image, or leave it to the rules.
+fn profile_picker() -> impl Widget<AppData> {
+    let mut row = Flex::row().with_child(Radio::new("Auto", None));
+    for kind in ProfileKind::ALL {
+        row.add_child(Radio::new(kind.name(), Some(kind)));
+    }
+    row.lens(lens::Identity.map(
+        |data: &AppData| data.profile,
+        |data: &mut AppData, profile| {
+            data.profile = profile;
+            if let Some(viewer) = data.viewer.as_mut() {
+                viewer.profile = profile.unwrap_or(viewer.auto_profile);
+            }
+        },
+    ))
+}
+
+/// Choose a neutral colour to draw behind every image, since a photo can look quite different
+/// against the window background. Other colours can be set on the command line or in the config
+/// file, which is what "Auto" goes back to unless the folder says otherwise.
+fn background_picker() -> impl Widget<AppData> {
+    let mut row = Flex::row().with_child(
+        Label::new("Auto").on_click(|ctx, _, _| ctx.submit_command(SET_BACKGROUND.with(None))),
+    );
+    for grey in [0x00, 0x80, 0xff] {
+        let color = Color::grey8(grey);
+        row.add_child(
+            SizedBox::empty()
+                .fix_size(14., 14.)
+                .background(color.clone())
+                .border(Color::grey8(0x80), 1.)
+                .on_click(move |ctx, _, _| {
+                    ctx.submit_command(SET_BACKGROUND.with(Some(color.clone())))
+                })
+                .padding((4., 0.)),
+        );
+    }
+    row
+}
+
+fn integrity_badge() -> impl Widget<Integrity> {
+    ViewSwitcher::new(
+        |data: &Integrity, _| *data,
+        |data, _, _| match data {
+            Integrity::Unknown => Box::new(Label::new("")),
+            Integrity::Verified => {
+                Box::new(Label::new("✔ verified").with_text_color(Color::rgb8(0x40, 0xc0, 0x40)))
+            }
+            Integrity::Corrupt => {
+                Box::new(Label::new("✘ corrupt").with_text_color(Color::rgb8(0xff, 0x40, 0x40)))
+            }
+        },
+    )
+}
+
+fn about_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(INFO, theme::ICON).fix_height(30.))
+            .with_child(Label::new("About"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(SHOW_ABOUT);
+            }),
+    )
+}
+
+fn close_button() -> impl Widget<AppData> {
+    BgHover::new(
+        Flex::column()
+            .with_child(Icon::new(EXIT_TO_APP, theme::ICON).fix_height(30.))
+            .with_child(Label::new("Exit"))
+            .padding(4.)
+            .on_click(|ctx, _, _| {
+                ctx.submit_command(QUIT_APP);
+            }),
+    )
+}
+
+/// The file name part of `path`, for display.
+fn file_name(path: &Path) -> ArcStr {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .as_ref()
+        .into()
+}
+
+/// The command for a shortcut, if it does anything right now.
+fn action_command(action: Action, key: &KbKey, data: &AppData) -> Option<Command> {
+    let cmd = match action {
+        Action::Next | Action::Prev => {
+            // Folders of manga and the like can ask for the arrows to go the way they are read.
+            let rtl = matches!(key, KbKey::ArrowLeft | KbKey::ArrowRight)
+                && data.library.as_ref().map_or(false, |list| {
+                    list.config().direction == Direction::RightToLeft
+                });
+            if (action == Action::Next) != rtl {
+                NEXT_IMAGE.into()
+            } else {
+                PREV_IMAGE.into()
+            }
+        }
+        Action::HistoryBack => HISTORY_BACK.into(),
+        Action::HistoryForward => HISTORY_FORWARD.into(),
+        Action::ZoomIn => ZOOM.with(ZOOM_FACTOR),
+        Action::ZoomOut => ZOOM.with(ZOOM_FACTOR.recip()),
+        Action::ActualSize => SET_SCALE.with(1.),
+        Action::Fit => SET_SCALE.with(0.),
+        Action::ZoomToSubject => ZOOM_TO_SUBJECT.into(),
+        Action::RotateCw => ROTATE_CW.into(),
+        Action::RotateCcw => ROTATE_CCW.into(),
+        Action::FlipH => FLIP_H.into(),
+        Action::FlipV => FLIP_V.into(),
+        Action::Fullscreen => TOGGLE_FULLSCREEN.into(),
+        Action::LeaveFullscreen if data.fullscreen => TOGGLE_FULLSCREEN.into(),
+        Action::LeaveFullscreen => return None,
+        Action::Metadata => TOGGLE_EXIF.into(),
+        Action::Copy => COPY_TO_CLIPBOARD.into(),
+        Action::CopyPath => COPY_PATH.into(),
+        Action::Paste => PASTE_FROM_CLIPBOARD.into(),
+        Action::Upscale => UPSCALE.into(),
+        Action::Slideshow => TOGGLE_SLIDESHOW.into(),
+        Action::Theme => TOGGLE_THEME.into(),
+        Action::Note => EDIT_NOTE.into(),
+        Action::ComparePrevious => COMPARE_WITH_PREVIOUS.into(),
+        Action::Inspect => TOGGLE_INSPECTOR.into(),
+        Action::CopyColor => COPY_COLOR.into(),
+        Action::Macros => TOGGLE_MACROS.into(),
+    };
+    Some(cmd)
+}
+
+/// The step of a macro that `cmd` takes, if it is one that can be recorded.
+fn macro_step(cmd: &Command, data: &AppData) -> Option<Step> {
+    let viewer = data.viewer.as_ref()?;
+    if cmd.is(ROTATE_CW) {
+        Some(Step::RotateCw)
+    } else if cmd.is(ROTATE_CCW) {
+        Some(Step::RotateCcw)
+    } else if cmd.is(FLIP_H) {
+        Some(Step::FlipH)
+    } else if cmd.is(FLIP_V) {
+        Some(Step::FlipV)
+    } else if cmd.is(CROP_APPLY) {
+        // A crop to no particular shape can't be made again on another image.
+        match viewer.crop_aspect {
+            CropAspect::Free => None,
+            aspect => Some(Step::Crop(aspect)),
+        }
+    } else if let Some(&aspect) = cmd.get(CROP_TO) {
+        Some(Step::Crop(aspect))
+    } else if let Some(file) = cmd.get(SAVE_IMAGE_AS) {
+        Step::export(file.path().extension()?.to_str()?)
+    } else if let Some((_, ext)) = cmd.get(EXPORT_COPY) {
+        Step::export(ext)
+    } else {
+        None
+    }
+}
+
+struct Delegate {
+    ui_tx: channel::Sender<UiMsg>,
+    history: History,
+    /// Recently viewed images, by path.
+    cache: ImageCache<PathBuf, CachedImage>,
+    /// How to encode images we save.
+    save_options: EncodeOptions,
+    keymap: Keymap,
+    /// How long to show each image for when the slideshow is started.
+    slideshow: Duration,
+    /// What to upscale images with, if the user has set something up.
+    upscaler: Option<Arc<dyn Upscaler>>,
+    upscale_by: u32,
+}
+
+impl Delegate {
+    /// Show the image at `path`, recording it in the history if `record` is set, and keeping the
+    /// image list in sync.
+    fn show_image(&mut self, path: PathBuf, record: bool, data: &mut AppData) {
+        if record {
+            self.history.push(path.clone());
+        }
+        let in_list = match data.library.as_mut() {
+            Some(list) => list.select(&path),
+            None => false,
+        };
+        if !in_list {
+            // We've moved to a different directory.
+            data.library = None;
+            let _ = self.ui_tx.send(UiMsg::ScanDir(path.clone()));
+        }
+        self.load_image(path, data);
+        self.prefetch(data);
+    }
+
+    /// The file the current image was loaded from, unless it came from somewhere else.
+    fn current_file(&self, data: &AppData) -> Option<PathBuf> {
+        if data.viewer.as_ref().map_or(true, |viewer| viewer.pasted) {
+            return None;
+        }
+        self.history.current().map(Path::to_owned)
+    }
+
+    /// Show the current pair of images being compared. Both are blinked between, if both are
+    /// there and blinking is on.
+    fn show_pair(&mut self, data: &mut AppData) {
+        let pair = match data.pairs.current() {
+            Some(pair) => pair.clone(),
+            None => return,
+        };
+        match (pair.old, pair.new) {
+            (Some(old), Some(new)) => {
+                data.blink.frames = Arc::new(vec![old.into(), new.as_path().into()]);
+                // Blinking starts from the new image, which is the one shown.
+                data.blink.current = 1;
+                self.show_image(new, true, data);
+            }
+            (Some(path), None) | (None, Some(path)) => {
+                data.blink.playing = false;
+                self.show_image(path, true, data);
+            }
+            (None, None) => (),
+        }
+    }
+
+    /// Work out the statistics for the current image list in the background.
+    fn request_folder_stats(&self, data: &mut AppData) {
+        data.folder_stats = None;
+        if let Some(list) = data.library.as_ref() {
+            let _ = self.ui_tx.send(UiMsg::FolderStats(list.paths().to_vec()));
+        }
+    }
+
+    /// Find the palette of the current image on the decode pool, if the palette panel is open.
+    fn request_palette(&self, data: &AppData) {
+        if !data.palette.show {
+            return;
+        }
+        if let Some(viewer) = data.viewer.as_ref() {
+            let _ = self.ui_tx.send(UiMsg::ExtractPalette {
+                image: viewer.image.clone(),
+                count: data.palette.size.round() as usize,
+            });
+        }
+    }
+
+    /// Start decoding the images either side of the current one.
+    fn prefetch(&self, data: &AppData) {
+        if let Some(list) = data.library.as_ref() {
+            let _ = self
+                .ui_tx
+                .send(UiMsg::Prefetch(list.neighbours(PREFETCH_DISTANCE)));
+        }
+    }
+
+    fn load_image(&mut self, path: PathBuf, data: &mut AppData) {
+        self.switch_note(&path, data);
+        // The file may have been changed while we were looking at something else.
+        if self
+            .cache
+            .get(&path)
+            .map_or(false, |cached| !cached.is_fresh(&path))
+        {
+            self.cache.remove(&path);
+        }
+        if let Some(cached) = self.cache.get(&path) {
+            log::debug!("showing cached {}", path.display());
+            data.loading = None;
+            data.show(cached.viewer.clone(), cached.exposure, cached.integrity);
+            self.request_palette(data);
+            data.file = Some(FileStatus::for_path(&path));
+            let _ = self.ui_tx.send(UiMsg::Watch(path));
+            return;
+        }
+        data.loading = Some(file_name(&path));
+        if let Err(e) = self.ui_tx.send(UiMsg::LoadImage(path)) {
+            data.loading = None;
+            data.set_error(format!("error sending message to io thread: {}", e).into());
+        }
+    }
+
+    /// Move the note over to the image at `path`, saving any changes to the note on the image we
+    /// are leaving.
+    fn switch_note(&mut self, path: &Path, data: &mut AppData) {
+        if data.note.path.as_deref() == Some(path) {
+            return;
+        }
+        self.save_note(data);
+        data.note.open(path);
+        let _ = self.ui_tx.send(UiMsg::ReadNote(path.to_owned()));
+    }
+
+    fn save_note(&mut self, data: &mut AppData) {
+        if let Some(image) = data.note.unsaved() {
+            let _ = self.ui_tx.send(UiMsg::SaveNote {
+                image: image.to_owned(),
+                text: data.note.text.clone(),
+            });
+            data.note.saved = data.note.text.clone();
+        }
+    }
+}
+
+impl AppDelegate<AppData> for Delegate {
+    fn event(
+        &mut self,
+        ctx: &mut DelegateCtx,
+        _window_id: WindowId,
+        event: Event,
+        data: &mut AppData,
+        _env: &Env,
+    ) -> Option<Event> {
+        if data.dialogs.is_open() {
+            let replies = match &event {
+                Event::KeyDown(KeyEvent {
+                    key: KbKey::Enter, ..
+                }) => data.dialogs.accept(),
+                Event::KeyDown(KeyEvent {
+                    key: KbKey::Escape, ..
+                }) => data.dialogs.cancel(),
+                // Shortcuts shouldn't act on the window behind the dialog.
+                _ => return Some(event),
+            };
+            for reply in replies {
+                ctx.submit_command(reply);
+            }
+            return None;
+        }
+        let cmd = match &event {
+            Event::KeyDown(key) => match self.keymap.action(key) {
+                // The expression box needs typing and Ctrl+C for its own text.
+                Some((chord, _)) if data.show_expression && chord.is_text_editing() => {
+                    return Some(event)
+                }
+                // As do the note editor and the other text boxes, which also need the keys for
+                // moving around in them.
+                Some((chord, _))
+                    if (data.note.show || data.editing_text)
+                        && (chord.is_text_editing() || chord.is_text_navigation()) =>
+                {
+                    return Some(event)
+                }
+                Some((_, action)) => match action_command(action, &key.key, data) {
+                    Some(cmd) => cmd,
+                    None => return Some(event),
+                },
+                None => return Some(event),
+            },
+            Event::MouseDown(MouseEvent {
+                button: MouseButton::X1,
+                ..
+            }) => HISTORY_BACK.into(),
+            Event::MouseDown(MouseEvent {
+                button: MouseButton::X2,
+                ..
+            }) => HISTORY_FORWARD.into(),
+            _ => return Some(event),
+        };
+        ctx.submit_command(cmd);
+        None
+    }
+
+    fn command(
+        &mut self,
+        ctx: &mut DelegateCtx,
+        _target: Target,
+        cmd: &Command,
+        data: &mut AppData,
+        _env: &Env,
+    ) -> Handled {
+        if let Some(step) = macro_step(cmd, data) {
+            data.macros.performed(step);
+        }
+        if let Some(file) = cmd.get(OPEN_FILE) {
+            self.show_image(file.path().to_owned(), true, data);
+            Handled::Yes
+        } else if cmd.is(HISTORY_BACK) {
+            if let Some(path) = self.history.back() {
+                let path = path.to_owned();
+                self.show_image(path, false, data);
+            }
+            Handled::Yes
+        } else if cmd.is(HISTORY_FORWARD) {
+            if let Some(path) = self.history.forward() {
+                let path = path.to_owned();
+                self.show_image(path, false, data);
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_MACROS) {
+            data.macros.show = !data.macros.show;
+            // The name box doesn't say it has lost the keyboard when it goes.
+            data.editing_text = false;
+            Handled::Yes
+        } else if cmd.is(SAVE_MACRO) {
+            let name = data.macros.name.trim();
+            if name.is_empty() {
+                data.toasts.push(Toast::info("Type a name for the macro"));
+                return Handled::Yes;
+            }
+            if data.macros.steps.is_empty() {
+                data.toasts.push(Toast::info(
+                    "Record some steps first, or use the recent ones",
+                ));
+                return Handled::Yes;
+            }
+            let saved = Macro {
+                name: name.into(),
+                steps: data.macros.steps.clone(),
+            };
+            let result = match shell::config_dir() {
+                Some(dir) => config::save_macro(&dir, &saved),
+                None => Err("could not find the config folder".into()),
+            };
+            match result {
+                Ok(()) => {
+                    data.toasts
+                        .push(Toast::info(format!("Saved macro {}", saved.name)));
+                    data.macros.recording = false;
+                    data.macros.name.clear();
+                    data.macros.add(saved);
+                }
+                Err(e) => data.toasts.push(Toast::error("Could not save macro", e)),
+            }
+            Handled::Yes
+        } else if let Some(run) = cmd.get(RUN_MACRO) {
+            if data.viewer.is_none() {
+                data.toasts
+                    .push(Toast::info(format!("Open an image to run {} on", run.name)));
+                return Handled::Yes;
+            }
+            // The steps are carried out in order, each on the image the one before left.
+            for step in run.steps.iter() {
+                ctx.submit_command(step.command(&run.name));
+            }
+            Handled::Yes
+        } else if let Some((name, ext)) = cmd.get(EXPORT_COPY) {
+            match (data.viewer.as_ref(), self.current_file(data)) {
+                (Some(viewer), Some(path)) => {
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    let name = name.replace(|c: char| matches!(c, '/' | '\\' | ':'), "-");
+                    let dest = path.with_file_name(format!("{}-{}.{}", stem, name, ext));
+                    let _ = self.ui_tx.send(UiMsg::SaveImage {
+                        image: viewer.image.clone(),
+                        orientation: widgets::exif_orientation(viewer.transform),
+                        dest,
+                        options: self.save_options,
+                    });
+                }
+                _ => data.toasts.push(Toast::info(
+                    "Only images opened from a file can be exported by a macro",
+                )),
+            }
+            Handled::Yes
+        } else if cmd.is(EDIT_NOTE) {
+            if data.note.show {
+                self.save_note(data);
+                data.note.show = false;
+            } else {
+                match self.current_file(data) {
+                    Some(path) => {
+                        self.switch_note(&path, data);
+                        data.note.show = true;
+                    }
+                    None => data
+                        .toasts
+                        .push(Toast::info("Only images opened from a file can have notes")),
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(SAVE_NOTE) {
+            self.save_note(data);
+            Handled::Yes
+        } else if let Some(result) = cmd.get(NOTE_READ) {
+            let (image, text) = result.take().unwrap();
+            // Don't replace anything typed while the note was being read.
+            let current = data.note.path.as_deref() == Some(&*image);
+            if current && data.note.unsaved().is_none() {
+                match text {
+                    Ok(text) => {
+                        data.note.text = text.clone();
+                        data.note.saved = text;
+                    }
+                    Err(e) => data
+                        .toasts
+                        .push(Toast::error("Could not read the note", e.to_string())),
+                }
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(NOTE_NOT_SAVED) {
+            let (image, e) = result.take().unwrap();
+            data.toasts.push(Toast::error(
+                format!("Could not save the note on {}", file_name(&image)),
+                e.to_string(),
+            ));
+            Handled::Yes
+        } else if let Some(color) = cmd.get(SET_BACKGROUND) {
+            data.set_background(color.clone());
+            Handled::Yes
+        } else if cmd.is(TOGGLE_THEME) {
+            data.theme = data.theme.toggled();
+            Handled::Yes
+        } else if cmd.is(TOGGLE_SLIDESHOW) {
+            data.slideshow = match data.slideshow {
+                Some(_) => None,
+                None => Some(self.slideshow),
+            };
+            Handled::Yes
+        } else if data.pairs.is_active() && (cmd.is(NEXT_IMAGE) || cmd.is(PREV_IMAGE)) {
+            // Step through the pairs instead of the folder.
+            ctx.submit_command(if cmd.is(NEXT_IMAGE) {
+                NEXT_PAIR
+            } else {
+                PREV_PAIR
+            });
+            Handled::Yes
+        } else if cmd.is(NEXT_PAIR) || cmd.is(PREV_PAIR) {
+            if data.pairs.step(cmd.is(NEXT_PAIR)) {
+                self.show_pair(data);
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(PAIRS_COMPARED) {
+            match result.take().unwrap() {
+                Ok(pairs) => {
+                    data.toasts.push(Toast::info(pairs::summary(&pairs)));
+                    data.pairs.pairs = Some(Arc::new(pairs));
+                    data.pairs.current = 0;
+                    self.show_pair(data);
+                }
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not compare the folders", e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(SAVE_REPORT) {
+            if let Some(pairs) = data.pairs.pairs.as_ref() {
+                // The report is small, so isn't worth sending to the io thread.
+                match pairs::write_report(pairs, file.path()) {
+                    Ok(()) => data.toasts.push(Toast::info(format!(
+                        "Saved report to {}",
+                        file.path().display()
+                    ))),
+                    Err(e) => data
+                        .toasts
+                        .push(Toast::error("Could not save the report", e.to_string())),
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(NEXT_IMAGE) || cmd.is(PREV_IMAGE) {
+            let list = match data.library.as_mut() {
+                Some(list) => list,
+                None => return Handled::Yes,
+            };
+            let start = list.clone();
+            let path = loop {
+                let path = if cmd.is(NEXT_IMAGE) {
+                    list.next()
+                } else {
+                    list.prev()
+                };
+                match path {
+                    Some(path) if data.failures.skips(path) => continue,
+                    path => break path.map(Path::to_owned),
+                }
+            };
+            if path.is_none() {
+                // Everything the way we were going failed, so stay where we are.
+                *list = start;
+            }
+            if let Some(path) = path {
+                self.history.push(path.clone());
+                self.load_image(path, data);
+                self.prefetch(data);
+            }
+            Handled::Yes
+        } else if cmd.is(WRITE_MANIFEST) {
+            let path = match self.current_file(data) {
+                Some(path) => path,
+                None => {
+                    data.toasts.push(Toast::info(
+                        "Only images opened from a file have a folder to write a manifest for",
+                    ));
+                    return Handled::Yes;
+                }
+            };
+            let dir = match path.parent() {
+                Some(dir) if dir != Path::new("") => dir.to_owned(),
+                _ => PathBuf::from("."),
+            };
+            let manifest = integrity::manifest_path(&dir);
+            if manifest.exists() {
+                data.dialogs
+                    .show(Dialog::overwrite(&manifest, MANIFEST_OVERWRITE));
+            } else {
+                let _ = self.ui_tx.send(UiMsg::WriteManifest(dir));
+            }
+            Handled::Yes
+        } else if let Some(reply) = cmd.get(MANIFEST_OVERWRITE) {
+            if reply.choice == OVERWRITE {
+                if let Some(dir) = reply.subject.as_deref().and_then(Path::parent) {
+                    let _ = self.ui_tx.send(UiMsg::WriteManifest(dir.to_owned()));
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(REVEAL_FILE) || cmd.is(COPY_PATH) || cmd.is(COPY_FILE) {
+            let path = match self.current_file(data) {
+                Some(path) => path,
+                None => {
+                    data.toasts
+                        .push(Toast::info("The image isn't saved in a file"));
+                    return Handled::Yes;
+                }
+            };
+            if cmd.is(REVEAL_FILE) {
+                if let Err(e) = shell::reveal(&path) {
+                    data.toasts
+                        .push(Toast::error("Could not show the file", e.to_string()));
+                }
+            } else if cmd.is(COPY_PATH) {
+                clipboard::put_path(&path);
+                data.toasts.push(Toast::info("Copied path to clipboard"));
+            } else {
+                clipboard::put_file(&path);
+                data.toasts.push(Toast::info("Copied file to clipboard"));
+            }
+            Handled::Yes
+        } else if cmd.is(COPY_TO_CLIPBOARD) {
+            if let Some(viewer) = data.viewer.as_ref() {
+                let orientation = widgets::exif_orientation(viewer.transform);
+                // Only offer the file if pasting it would give what is on screen.
+                let path = self
+                    .history
+                    .current()
+                    .filter(|_| !viewer.edited && orientation == 1)
+                    .map(Path::to_owned);
+                let _ = self.ui_tx.send(UiMsg::CopyImage {
+                    image: viewer.image.clone(),
+                    orientation,
+                    path,
+                });
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(CLIPBOARD_IMAGE) {
+            match result.take().unwrap() {
+                Ok(image) => {
+                    image.put();
+                    data.toasts.push(Toast::info("Copied image to clipboard"));
+                }
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not copy image", e.to_string())),
+            }
+            Handled::Yes
+        } else if cmd.is(PASTE_FROM_CLIPBOARD) {
+            match clipboard::get() {
+                Some(Pasted::Image(png)) => {
+                    data.loading = Some("pasted image".into());
+                    let _ = self.ui_tx.send(UiMsg::DecodePasted(png));
+                }
+                Some(Pasted::File(path)) => self.show_image(path, true, data),
+                None => data
+                    .toasts
+                    .push(Toast::info("There is no image on the clipboard")),
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(PASTED_IMAGE) {
+            data.loading = None;
+            match result.take().unwrap() {
+                Ok(loaded) => {
+                    data.set_unsaved_image(loaded);
+                    self.request_palette(data);
+                }
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not paste image", e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(STDIN_IMAGE) {
+            data.loading = None;
+            match result.take().unwrap() {
+                Ok(loaded) => {
+                    data.set_unsaved_image(loaded);
+                    self.request_palette(data);
+                }
+                Err(e) => {
+                    data.set_error(format!("error reading image from standard input: {}", e).into())
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(SHOW_SAVE_AS) {
+            if let Some(viewer) = data.viewer.as_ref() {
+                let name = match self.history.current().and_then(Path::file_stem) {
+                    _ if viewer.pasted => "pasted".into(),
+                    Some(stem) => stem.to_string_lossy(),
+                    None => "image".into(),
+                };
+                ctx.submit_command(
+                    SHOW_SAVE_PANEL.with(
+                        FileDialogOptions::new()
+                            .allowed_types(SAVE_FORMATS.to_vec())
+                            .default_name(format!("{} edited.png", name))
+                            .accept_command(SAVE_IMAGE_AS),
+                    ),
+                );
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(SAVE_IMAGE_AS) {
+            if let Some(viewer) = data.viewer.as_ref() {
+                let _ = self.ui_tx.send(UiMsg::SaveImage {
+                    image: viewer.image.clone(),
+                    orientation: widgets::exif_orientation(viewer.transform),
+                    dest: file.path().to_owned(),
+                    options: self.save_options,
+                });
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(SAVE_CROP) {
+            ctx.submit_command(CROP_APPLY.with(Some(file.path().to_owned())));
+            Handled::Yes
+        } else if let Some(cropped) = cmd.get(CROPPED) {
+            let (width, height) = (cropped.image.width(), cropped.image.height());
+            data.toasts
+                .push(Toast::info(format!("Cropped to {}×{}", width, height)));
+            if let Some(dest) = &cropped.save_to {
+                let _ = self.ui_tx.send(UiMsg::SaveImage {
+                    image: cropped.image.clone(),
+                    orientation: 1,
+                    dest: dest.clone(),
+                    options: self.save_options,
+                });
+            }
+            Handled::Yes
+        } else if cmd.is(UPSCALE) {
+            match (&self.upscaler, self.current_file(data)) {
+                (None, _) => data.toasts.push(Toast::info(
+                    "Choose a program to upscale with, with --upscaler",
+                )),
+                (Some(_), None) => data
+                    .toasts
+                    .push(Toast::info("Save the image before upscaling it")),
+                (Some(upscaler), Some(path)) => {
+                    data.toasts
+                        .push(Toast::info(format!("Upscaling {}…", file_name(&path))));
+                    let _ = self.ui_tx.send(UiMsg::Upscale {
+                        path,
+                        upscaler: upscaler.clone(),
+                        scale: self.upscale_by,
+                    });
+                }
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(UPSCALED) {
+            match result.take().unwrap() {
+                Ok(path) => self.show_image(path, true, data),
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not upscale image", e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(view) = cmd.get(VIEW_TO_EXPORT) {
+            match (self.current_file(data), data.viewer.as_ref()) {
+                (Some(original), Some(viewer)) => {
+                    let _ = self.ui_tx.send(UiMsg::ExportWallpapers {
+                        image: viewer.image.clone(),
+                        view: *view,
+                        original,
+                        options: self.save_options,
+                    });
+                }
+                _ => data.toasts.push(Toast::info(
+                    "Save the image before exporting wallpapers from it",
+                )),
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(WALLPAPERS_EXPORTED) {
+            match result.take().unwrap() {
+                Ok(exported) if exported.written.is_empty() => data.toasts.push(Toast::info(
+                    "The view is smaller than every wallpaper size, so nothing was written",
+                )),
+                Ok(exported) => {
+                    for path in &exported.written {
+                        log::info!("wrote {}", path.display());
+                    }
+                    let mut text = format!("Wrote {} wallpapers", exported.written.len());
+                    if exported.skipped > 0 {
+                        text += &format!(
+                            ", leaving out {} sizes bigger than the view",
+                            exported.skipped
+                        );
+                    }
+                    data.toasts.push(Toast::info(text));
+                }
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not export wallpapers", e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(IMAGE_SAVED) {
+            match result.take().unwrap() {
+                Ok(path) => {
+                    log::info!("wrote {}", path.display());
+                    data.toasts
+                        .push(Toast::info(format!("Saved {}", path.display())));
+                }
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not save image", e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(dialog) = cmd.get(SHOW_DIALOG) {
+            data.dialogs.show(dialog.clone());
+            Handled::Yes
+        } else if let Some(result) = cmd.get(MANIFEST_WRITTEN) {
+            match result.take().unwrap() {
+                Ok(path) => {
+                    log::info!("wrote {}", path.display());
+                    data.toasts.push(Toast::info(format!(
+                        "Wrote checksums to {}",
+                        path.display()
+                    )));
+                    // The manifest was made from the file as it is now, so it matches by
+                    // definition. A sidecar would still win, so only fill in the gap.
+                    if data.integrity == Integrity::Unknown && data.viewer.is_some() {
+                        data.integrity = Integrity::Verified;
+                    }
+                }
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not write checksums", e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(EXPORT_PDF) {
+            match data.library.as_ref() {
+                Some(list) => {
+                    let options = PdfOptions {
+                        margin: data.pdf_options.margin.round(),
+                        ..data.pdf_options.clone()
+                    };
+                    let _ = self.ui_tx.send(UiMsg::ExportPdf {
+                        images: list.paths().to_vec(),
+                        dest: file.path().to_owned(),
+                        options,
+                    });
+                }
+                None => data.toasts.push(Toast::info(
+                    "Open an image from a folder to export the folder to a PDF",
+                )),
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(PDF_EXPORTED) {
+            match result.take().unwrap() {
+                Ok(path) => {
+                    log::info!("wrote {}", path.display());
+                    data.toasts
+                        .push(Toast::info(format!("Saved PDF to {}", path.display())));
+                }
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not export PDF", e.to_string())),
+            }
+            Handled::Yes
+        } else if let Some(toast) = cmd.get(SHOW_TOAST) {
+            data.toasts.push(toast.clone());
+            Handled::Yes
+        } else if cmd.is(TOGGLE_EXIF) {
+            data.show_exif = !data.show_exif;
+            Handled::Yes
+        } else if cmd.is(SEND_EMAIL) {
+            match self.current_file(data) {
+                Some(path) => {
+                    let _ = self.ui_tx.send(UiMsg::SendEmail {
+                        images: vec![path],
+                        max_size: Some(email::DEFAULT_MAX_SIZE),
+                    });
+                }
+                None => data
+                    .toasts
+                    .push(Toast::info("Only images opened from a file can be emailed")),
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(EMAIL_SENT) {
+            if let Err(e) = result.take().unwrap() {
+                data.toasts
+                    .push(Toast::error("Could not send email", e.to_string()));
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(EXPORT_SETTINGS) {
+            // The settings are small, so aren't worth sending to the io thread.
+            let result = match shell::config_dir() {
+                Some(dir) => config::export(&dir, file.path()),
+                None => Err("could not find the config folder".into()),
+            };
+            match result {
+                Ok(()) => data.toasts.push(Toast::info(format!(
+                    "Exported settings to {}",
+                    file.path().display()
+                ))),
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not export settings", e)),
+            }
+            Handled::Yes
+        } else if let Some(file) = cmd.get(IMPORT_SETTINGS) {
+            let result = match shell::config_dir() {
+                Some(dir) => config::import(file.path(), &dir),
+                None => Err("could not find the config folder".into()),
+            };
+            match result {
+                // The settings are read once, as the widgets are made. The user has to act on
+                // this, so it stays up longer than the usual notice.
+                Ok(()) => data.toasts.push(
+                    Toast::info("Imported settings. Restart Image Viewer to use them")
+                        .with_duration(IMPORTANT_TOAST),
+                ),
+                Err(e) => data
+                    .toasts
+                    .push(Toast::error("Could not import settings", e)),
+            }
+            Handled::Yes
+        } else if cmd.is(SHOW_ABOUT) {
+            ctx.new_window(about::window());
+            Handled::Yes
+        } else if let Some(list) = cmd.get(DIR_SCANNED) {
+            let mut list = match list.take() {
+                Some(list) => list,
+                None => return Handled::Yes,
+            };
+            // The user may have moved to another folder while this one was being read, in which
+            // case the list for that folder is on its way.
+            let current = self.history.current();
+            if !current.map_or(false, |path| list.select(path)) {
+                return Handled::Yes;
+            }
+            data.library = Some(list);
+            // The image may have been shown before we had read the folder's settings.
+            let (initial_zoom, background) = data.folder_settings();
+            if let Some(viewer) = data.viewer.as_mut() {
+                viewer.initial_zoom = initial_zoom;
+                viewer.background = background;
+            }
+            self.prefetch(data);
+            if data.show_folder_stats {
+                self.request_folder_stats(data);
+            }
+            Handled::Yes
+        } else if cmd.is(ADD_TO_BLINK) {
+            match self.current_file(data) {
+                Some(path) => data.blink.add(&path),
+                None => data
+                    .toasts
+                    .push(Toast::info("Only images opened from a file can be blinked")),
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_BLINKING) {
+            if data.blink.playing {
+                data.blink.playing = false;
+                // Go back to the image we were on before.
+                if let Some(path) = self.history.current() {
+                    let path = path.to_owned();
+                    self.load_image(path, data);
+                }
+            } else if data.blink.can_play() {
+                data.blink.playing = true;
+                let frames = data.blink.frames.iter().map(|frame| frame.to_path_buf());
+                let _ = self.ui_tx.send(UiMsg::Prefetch(frames.collect()));
+            } else {
+                data.toasts
+                    .push(Toast::info("Add two or more images to blink between them"));
+            }
+            Handled::Yes
+        } else if cmd.is(COMPARE_WITH_PREVIOUS) {
+            match self.current_file(data) {
+                Some(path) => {
+                    let _ = self.ui_tx.send(UiMsg::FindPreviousVersion(path));
+                }
+                None => data.toasts.push(Toast::info(
+                    "Only images opened from a file have earlier versions",
+                )),
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(PREVIOUS_VERSION) {
+            let (path, result) = result.take().unwrap();
+            // The user may have moved on while we were looking.
+            if self.current_file(data).as_deref() != Some(&*path) {
+                return Handled::Yes;
+            }
+            match result {
+                Ok(old) => {
+                    // The current image stays in the history, so stopping comes back to it.
+                    data.blink.frames = Arc::new(vec![path.into(), old.into()]);
+                    data.blink.current = 0;
+                    data.blink.show = true;
+                    if !data.blink.playing {
+                        ctx.submit_command(TOGGLE_BLINKING);
+                    }
+                }
+                Err(e) => data.toasts.push(Toast::error(
+                    format!("No earlier version of {}", file_name(&path)),
+                    e.to_string(),
+                )),
+            }
+            Handled::Yes
+        } else if cmd.is(BLINK_STEP) {
+            // Skip a turn rather than queue up loads faster than we can show them.
+            if data.blink.playing && data.loading.is_none() {
+                if let Some(path) = data.blink.step() {
+                    self.load_image(path.to_path_buf(), data);
+                }
+            }
+            Handled::Yes
+        } else if cmd.is(EXTRACT_PALETTE) {
+            self.request_palette(data);
+            Handled::Yes
+        } else if let Some(result) = cmd.get(PALETTE_EXTRACTED) {
+            let (image, swatches) = result.take().unwrap();
+            // Drop the palette of an image we have since moved on from.
+            if data
+                .viewer
+                .as_ref()
+                .map_or(false, |viewer| Arc::ptr_eq(&viewer.image, &image))
+            {
+                data.palette.swatches = Arc::new(swatches);
+            }
+            Handled::Yes
+        } else if cmd.is(TOGGLE_FOLDER_STATS) {
+            data.show_folder_stats = !data.show_folder_stats;
+            if data.show_folder_stats {
+                self.request_folder_stats(data);
+            }
+            Handled::Yes
+        } else if let Some(result) = cmd.get(FOLDER_STATS) {
+            let (first, stats) = result.take().unwrap();
+            // Drop the statistics for a folder we have since left.
+            let current = data.library.as_ref().and_then(|list| list.paths().first());
+            if first.as_ref() == current {
+                data.folder_stats = Some(Arc::new(stats));
+            }
+            Handled::Yes
+        } else if let Some(img) = cmd.get(FILE_LOADED) {
+            data.loading = None;
+            match img.take().unwrap() {
+                Ok(loaded) => {
+                    let (path, modified) = (loaded.path.clone(), loaded.modified);
+                    data.failures.forget(&path);
+                    data.set_image(loaded);
+                    self.request_palette(data);
+                    if let Some(entry) = data.cache_entry(modified) {
+                        let bytes = entry.bytes();
+                        self.cache.insert(path, entry, bytes);
+                    }
+                }
+                Err(e) => {
+                    data.failures.record(&e.path, e.error.to_string());
+                    data.set_error(format!("error decoding/loading image: {}", e.error).into());
+                }
+            }
+            Handled::Yes
+        } else if let Some(trans) = cmd.get(NOTIFY_TRANSFORM) {
+            // `trans` maps the widget to the image, so it shrinks by as much as the image is
+            // zoomed.
+            data.zoom = trans.determinant().abs().sqrt().recip();
+            data.mirrored = if trans.determinant() < 0. {
+                "mirrored".into()
+            } else {
+                "".into()
+            };
+            Handled::No
+        } else if let Some(&editing) = cmd.get(EDITING_TEXT) {
+            data.editing_text = editing;
+            Handled::Yes
+        } else {
+            Handled::No
+        }
+    }
+
+    fn window_removed(
+        &mut self,
+        _id: WindowId,
+        data: &mut AppData,
+        _env: &Env,
+        _ctx: &mut DelegateCtx,
+    ) {
+        // The io thread writes it before it shuts down.
+        self.save_note(data);
+    }
+}
+
 struct BgHover<T, W> {
     hot: bool,
     inner: WidgetPod<T, W>,
@@ -368,7 +2817,7 @@ impl<T: Data, W: Widget<T>> Widget<T> for BgHover<T, W> {
     fn paint(&mut self, ctx: &mut PaintCtx, data: &T, env: &Env) {
         if self.hot {
             let r = ctx.size().to_rect();
-            let bg = ctx.solid_brush(env.get(theme::BUTTON_DARK));
+            let bg = ctx.solid_brush(env.get(druid::theme::BUTTON_DARK));
             ctx.fill(r, &bg);
         }
         self.inner.paint(ctx, data, env)
@@ -0,0 +1,122 @@
+//! Notes on images, kept as plain text beside them, as `photo.jpg.note.txt`. Being plain files,
+//! they go wherever the image goes, and desktop search finds them.
+use druid::{
+    widget::{prelude::*, Button, CrossAxisAlignment, Flex, Label, TextBox},
+    Data, Lens, Selector, WidgetExt,
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Open or close the note editor for the current image.
+pub const EDIT_NOTE: Selector = Selector::new("image-viewer.edit-note");
+/// Save the note being edited.
+pub const SAVE_NOTE: Selector = Selector::new("image-viewer.save-note");
+
+/// The note on one image, as it is being edited.
+#[derive(Debug, Clone, Default, Data, Lens)]
+pub struct Note {
+    /// The image the note is on.
+    pub path: Option<Arc<Path>>,
+    pub text: String,
+    /// The text as it is in the file.
+    pub saved: String,
+    /// Whether the editor is open.
+    pub show: bool,
+}
+
+impl Note {
+    /// Start on the note for `path`, which is empty until it has been read.
+    pub fn open(&mut self, path: &Path) {
+        self.path = Some(path.into());
+        self.text.clear();
+        self.saved.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    /// The image whose note has changes that haven't been saved, if there are any.
+    pub fn unsaved(&self) -> Option<&Path> {
+        self.path.as_deref().filter(|_| self.text != self.saved)
+    }
+}
+
+/// The file the note on `image` is kept in.
+pub fn sidecar_path(image: &Path) -> PathBuf {
+    let mut name = image.file_name().unwrap_or_default().to_owned();
+    name.push(".note.txt");
+    image.with_file_name(name)
+}
+
+/// Read the note on `image`, which is empty if there isn't one.
+pub fn read(image: &Path) -> io::Result<String> {
+    match fs::read_to_string(sidecar_path(image)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+/// Write the note on `image`. An empty note removes the file, so images without notes don't
+/// have files beside them.
+pub fn write(image: &Path, text: &str) -> io::Result<()> {
+    let path = sidecar_path(image);
+    if !text.trim().is_empty() {
+        return fs::write(path, text);
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// For the status bar: shows that the image has a note, and opens it when clicked.
+pub fn indicator() -> impl Widget<Note> {
+    Label::dynamic(|data: &Note, _| {
+        let label = if data.is_empty() { "" } else { "✎ Note" };
+        label.to_string()
+    })
+    .on_click(|ctx, _, _| ctx.submit_command(EDIT_NOTE))
+}
+
+/// The note editor.
+pub fn panel() -> impl Widget<Note> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::dynamic(|data: &Note, _| {
+                    match data.path.as_deref().and_then(Path::file_name) {
+                        Some(name) => format!("Note on {}", name.to_string_lossy()),
+                        None => "Note".to_string(),
+                    }
+                }))
+                .with_flex_spacer(1.)
+                .with_child(
+                    Button::dynamic(|data: &Note, _| {
+                        let label = if data.unsaved().is_some() {
+                            "Save"
+                        } else {
+                            "Saved"
+                        };
+                        label.to_string()
+                    })
+                    .on_click(|ctx, _, _| ctx.submit_command(SAVE_NOTE)),
+                )
+                .with_child(Button::new("×").on_click(|ctx, _, _| {
+                    ctx.submit_command(EDIT_NOTE);
+                })),
+        )
+        .with_spacer(4.)
+        .with_child(
+            TextBox::multiline()
+                .with_placeholder("Write a note on this image")
+                .lens(Note::text)
+                .expand_width()
+                .fix_height(80.),
+        )
+        .padding(4.)
+}
@@ -0,0 +1,348 @@
+//! Comparing two folders of images, such as the output of two versions of a renderer. Files are
+//! paired by name and each pair is compared pixel by pixel, so a regression shows up as a pair
+//! that differs. The user steps through the pairs, blinking each, and can save a report.
+use druid::{
+    commands::SHOW_SAVE_PANEL,
+    widget::{prelude::*, Button, CrossAxisAlignment, Flex, Label},
+    Color, Data, FileDialogOptions, FileInfo, FileSpec, Lens, Selector, WidgetExt,
+};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt::{self, Write as _},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{blink::TOGGLE_BLINKING, decode, library, pixel_ops};
+
+/// Show the next pair.
+pub const NEXT_PAIR: Selector = Selector::new("image-viewer.next-pair");
+/// Show the previous pair.
+pub const PREV_PAIR: Selector = Selector::new("image-viewer.prev-pair");
+/// Save the report at the chosen path.
+pub const SAVE_REPORT: Selector<FileInfo> = Selector::new("image-viewer.save-report");
+
+/// How the two images in a pair compare.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Same,
+    Differs(Difference),
+    /// The images aren't the same size, so can't be compared pixel by pixel.
+    SizeDiffers {
+        old: (usize, usize),
+        new: (usize, usize),
+    },
+    OnlyOld,
+    OnlyNew,
+    /// One of the images couldn't be read.
+    Failed(String),
+}
+
+/// How much two images of the same size differ.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Difference {
+    /// How many pixels differ in any channel.
+    pub changed: usize,
+    pub pixels: usize,
+    /// The biggest difference in any channel, out of 255.
+    pub max: u8,
+    /// The mean difference over every channel of every pixel, out of 255.
+    pub mean: f64,
+}
+
+impl Difference {
+    /// The percentage of pixels that differ.
+    pub fn changed_percent(&self) -> f64 {
+        self.changed as f64 * 100. / self.pixels.max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pair {
+    pub name: String,
+    pub old: Option<PathBuf>,
+    pub new: Option<PathBuf>,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Default, Data, Lens)]
+pub struct Pairs {
+    /// The folders being compared, old then new.
+    pub dirs: Option<Arc<(PathBuf, PathBuf)>>,
+    /// The pairs, in order of name, or `None` until they have been compared.
+    pub pairs: Option<Arc<Vec<Pair>>>,
+    /// The index of the pair being shown.
+    pub current: usize,
+    /// Whether the panel is open.
+    pub show: bool,
+}
+
+impl Pairs {
+    /// Start comparing `old` with `new`.
+    pub fn open(&mut self, old: PathBuf, new: PathBuf) {
+        self.dirs = Some(Arc::new((old, new)));
+        self.pairs = None;
+        self.current = 0;
+        self.show = true;
+    }
+
+    /// Whether next and previous should step through the pairs, rather than the folder.
+    pub fn is_active(&self) -> bool {
+        self.show && self.pairs.as_ref().map_or(false, |pairs| !pairs.is_empty())
+    }
+
+    pub fn current(&self) -> Option<&Pair> {
+        self.pairs.as_ref()?.get(self.current)
+    }
+
+    /// Move to the next or previous pair. Returns whether there was one.
+    pub fn step(&mut self, forward: bool) -> bool {
+        let len = self.pairs.as_ref().map_or(0, |pairs| pairs.len());
+        let next = if forward {
+            Some(self.current + 1).filter(|&next| next < len)
+        } else {
+            self.current.checked_sub(1)
+        };
+        match next {
+            Some(next) => {
+                self.current = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Pair the images in `old_dir` and `new_dir` by name, and compare each pair. This decodes every
+/// image, so should be run in the background.
+pub fn compare(old_dir: &Path, new_dir: &Path) -> io::Result<Vec<Pair>> {
+    let mut names: BTreeMap<OsString, (bool, bool)> = BTreeMap::new();
+    for name in image_names(old_dir)? {
+        names.entry(name).or_default().0 = true;
+    }
+    for name in image_names(new_dir)? {
+        names.entry(name).or_default().1 = true;
+    }
+    Ok(names
+        .into_iter()
+        .map(|(name, (in_old, in_new))| {
+            let old = in_old.then(|| old_dir.join(&name));
+            let new = in_new.then(|| new_dir.join(&name));
+            let outcome = match (&old, &new) {
+                (Some(old), Some(new)) => diff(old, new),
+                (Some(_), None) => Outcome::OnlyOld,
+                _ => Outcome::OnlyNew,
+            };
+            Pair {
+                name: name.to_string_lossy().into_owned(),
+                old,
+                new,
+                outcome,
+            }
+        })
+        .collect())
+}
+
+fn image_names(dir: &Path) -> io::Result<Vec<OsString>> {
+    let mut names = vec![];
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() && library::is_image(&entry.path()) {
+            names.push(entry.file_name());
+        }
+    }
+    Ok(names)
+}
+
+/// Compare two images as they decode. Images that differ only in how they store their pixels
+/// (RGB rather than RGBA, say) count as the same.
+fn diff(old: &Path, new: &Path) -> Outcome {
+    let (old, new) = match (decode::open(old), decode::open(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => return Outcome::Failed(e.to_string()),
+    };
+    let sizes = ((old.width(), old.height()), (new.width(), new.height()));
+    if sizes.0 != sizes.1 {
+        return Outcome::SizeDiffers {
+            old: sizes.0,
+            new: sizes.1,
+        };
+    }
+    let (old, new) = (pixel_ops::to_rgba(&old), pixel_ops::to_rgba(&new));
+    let mut out = vec![0; old.len()];
+    pixel_ops::abs_diff(&old, &new, &mut out);
+    let mut difference = Difference {
+        changed: 0,
+        pixels: out.len() / 4,
+        max: 0,
+        mean: 0.,
+    };
+    let mut total = 0u64;
+    for px in out.chunks_exact(4) {
+        if px.iter().any(|&d| d > 0) {
+            difference.changed += 1;
+        }
+        for &d in px {
+            difference.max = difference.max.max(d);
+            total += u64::from(d);
+        }
+    }
+    if difference.changed == 0 {
+        return Outcome::Same;
+    }
+    difference.mean = total as f64 / out.len() as f64;
+    Outcome::Differs(difference)
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Same => f.write_str("The same"),
+            Outcome::Differs(d) => write!(
+                f,
+                "{} pixels differ ({:.2}%), by up to {}",
+                d.changed,
+                d.changed_percent(),
+                d.max
+            ),
+            Outcome::SizeDiffers { old, new } => write!(
+                f,
+                "Different sizes: {}×{} and {}×{}",
+                old.0, old.1, new.0, new.1
+            ),
+            Outcome::OnlyOld => f.write_str("Only in the old folder"),
+            Outcome::OnlyNew => f.write_str("Only in the new folder"),
+            Outcome::Failed(e) => write!(f, "Could not read: {}", e),
+        }
+    }
+}
+
+/// A line summing up how the pairs compare, like "230 pairs: 212 the same, 18 differ".
+pub fn summary(pairs: &[Pair]) -> String {
+    let count = |f: fn(&Outcome) -> bool| pairs.iter().filter(|pair| f(&pair.outcome)).count();
+    let parts = [
+        (count(|o| *o == Outcome::Same), "the same"),
+        (
+            count(|o| matches!(o, Outcome::Differs(_) | Outcome::SizeDiffers { .. })),
+            "differ",
+        ),
+        (count(|o| *o == Outcome::OnlyOld), "only in the old folder"),
+        (count(|o| *o == Outcome::OnlyNew), "only in the new folder"),
+        (count(|o| matches!(o, Outcome::Failed(_))), "unreadable"),
+    ];
+    let parts: Vec<_> = parts
+        .iter()
+        .filter(|&&(count, _)| count > 0)
+        .map(|(count, what)| format!("{} {}", count, what))
+        .collect();
+    match pairs.len() {
+        0 => "No images to compare".to_string(),
+        1 => format!("1 pair: {}", parts.join(", ")),
+        n => format!("{} pairs: {}", n, parts.join(", ")),
+    }
+}
+
+/// The comparison of every pair, as CSV.
+pub fn report(pairs: &[Pair]) -> String {
+    let mut csv =
+        String::from("name,result,changed pixels,changed %,max difference,mean difference\n");
+    for pair in pairs {
+        let result = match &pair.outcome {
+            Outcome::Same => "same",
+            Outcome::Differs(_) => "differs",
+            Outcome::SizeDiffers { .. } => "size differs",
+            Outcome::OnlyOld => "only old",
+            Outcome::OnlyNew => "only new",
+            Outcome::Failed(_) => "unreadable",
+        };
+        // Writing to a `String` can't fail.
+        let _ = write!(csv, "{},{}", csv_field(&pair.name), result);
+        let _ = match &pair.outcome {
+            Outcome::Differs(d) => writeln!(
+                csv,
+                ",{},{:.4},{},{:.4}",
+                d.changed,
+                d.changed_percent(),
+                d.max,
+                d.mean
+            ),
+            Outcome::Same => writeln!(csv, ",0,0,0,0"),
+            _ => writeln!(csv, ",,,,"),
+        };
+    }
+    csv
+}
+
+/// Quote `field` if it would otherwise be read as more than one field.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+pub fn write_report(pairs: &[Pair], path: &Path) -> io::Result<()> {
+    fs::write(path, report(pairs))
+}
+
+/// The summary, the pair being shown, and the controls for stepping through them.
+pub fn panel() -> impl Widget<Pairs> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::dynamic(|data: &Pairs, _| match &data.dirs {
+                    Some(dirs) => {
+                        format!("Comparing {} with {}", dirs.0.display(), dirs.1.display())
+                    }
+                    None => "Compare folders".to_string(),
+                }))
+                .with_flex_spacer(1.)
+                .with_child(Button::new("‹ Previous").on_click(|ctx, _, _| {
+                    ctx.submit_command(PREV_PAIR);
+                }))
+                .with_child(Button::new("Next ›").on_click(|ctx, _, _| {
+                    ctx.submit_command(NEXT_PAIR);
+                }))
+                .with_child(Button::new("Blink").on_click(|ctx, _, _| {
+                    ctx.submit_command(TOGGLE_BLINKING);
+                }))
+                .with_child(Button::new("Save report").on_click(|ctx, _, _| {
+                    ctx.submit_command(
+                        SHOW_SAVE_PANEL.with(
+                            FileDialogOptions::new()
+                                .allowed_types(vec![FileSpec::new("CSV", &["csv"])])
+                                .default_name("report.csv")
+                                .accept_command(SAVE_REPORT),
+                        ),
+                    );
+                }))
+                .with_child(Button::new("×").on_click(|_, data: &mut Pairs, _| data.show = false)),
+        )
+        .with_spacer(4.)
+        .with_child(
+            Label::dynamic(|data: &Pairs, _| match &data.pairs {
+                Some(pairs) => summary(pairs),
+                None => "Comparing…".to_string(),
+            })
+            .with_text_size(11.)
+            .with_text_color(Color::grey8(0xa0)),
+        )
+        .with_child(Label::dynamic(|data: &Pairs, _| {
+            let len = data.pairs.as_ref().map_or(0, |pairs| pairs.len());
+            match data.current() {
+                Some(pair) => format!(
+                    "{}/{}  {}: {}",
+                    data.current + 1,
+                    len,
+                    pair.name,
+                    pair.outcome
+                ),
+                None => String::new(),
+            }
+        }))
+        .padding(4.)
+}
@@ -0,0 +1,152 @@
+//! Finding the dominant colours of an image, by median cut.
+use druid::{piet::ImageFormat, Data, ImageBuf, Lens, Selector};
+use std::{fmt::Write, sync::Arc};
+
+/// Find the palette of the current image again, with as many colours as are chosen.
+pub const EXTRACT_PALETTE: Selector = Selector::new("image-viewer.extract-palette");
+
+/// We only look at about this many pixels, spread evenly over the image.
+const MAX_SAMPLES: usize = 1 << 16;
+pub const MIN_SWATCHES: usize = 4;
+pub const MAX_SWATCHES: usize = 16;
+
+/// One colour of a palette.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Swatch {
+    pub rgb: [u8; 3],
+    /// The fraction of the image that is nearest this colour.
+    pub share: f64,
+}
+
+impl Swatch {
+    /// The colour as `#rrggbb`.
+    pub fn hex(&self) -> String {
+        let [r, g, b] = self.rgb;
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+/// The palette panel.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct Palette {
+    pub show: bool,
+    /// How many swatches to extract. A float so it can be set with a slider.
+    pub size: f64,
+    /// Most common first.
+    pub swatches: Arc<Vec<Swatch>>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            show: false,
+            size: 8.,
+            swatches: Arc::new(vec![]),
+        }
+    }
+}
+
+/// Split the colours of `image` into (up to) `count` groups, and return their averages, most
+/// common first. Mostly transparent pixels are ignored.
+pub fn extract(image: &ImageBuf, count: usize) -> Vec<Swatch> {
+    let mut samples = sample(image);
+    let total = samples.len() as f64;
+    let mut boxes: Vec<&mut [[u8; 3]]> = vec![];
+    if !samples.is_empty() {
+        boxes.push(&mut samples);
+    }
+    while boxes.len() < count {
+        // Split the box covering the widest range of any one channel, at its median.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(idx, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (idx, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range);
+        let (idx, channel) = match widest {
+            // A box of one colour can't be split, and has a range of 0.
+            Some((idx, channel, range)) if range > 0 => (idx, channel),
+            _ => break,
+        };
+        let colors = boxes.swap_remove(idx);
+        colors.sort_unstable_by_key(|px| px[channel]);
+        // Split where the value changes, so the same colour never ends up in two boxes.
+        let median = colors[colors.len() / 2][channel];
+        let mut split = colors.partition_point(|px| px[channel] < median);
+        if split == 0 {
+            split = colors.partition_point(|px| px[channel] <= median);
+        }
+        let (lo, hi) = colors.split_at_mut(split);
+        boxes.push(lo);
+        boxes.push(hi);
+    }
+    let mut swatches: Vec<Swatch> = boxes
+        .iter()
+        .map(|colors| {
+            let mut sum = [0u64; 3];
+            for px in colors.iter() {
+                for (sum, &value) in sum.iter_mut().zip(px) {
+                    *sum += value as u64;
+                }
+            }
+            let avg = |c: usize| (sum[c] as f64 / colors.len() as f64).round() as u8;
+            Swatch {
+                rgb: [avg(0), avg(1), avg(2)],
+                share: colors.len() as f64 / total,
+            }
+        })
+        .collect();
+    swatches.sort_by(|a, b| b.share.partial_cmp(&a.share).unwrap());
+    swatches
+}
+
+/// Pick out up to `MAX_SAMPLES` opaque-ish pixels. We don't convert the whole image first, as
+/// that would mean copying every pixel of large photos.
+fn sample(image: &ImageBuf) -> Vec<[u8; 3]> {
+    let bpp = image.format().bytes_per_pixel();
+    let pixels = image.raw_pixels();
+    let step = (pixels.len() / bpp / MAX_SAMPLES).max(1);
+    let pixels = pixels.chunks_exact(bpp).step_by(step);
+    match image.format() {
+        ImageFormat::Grayscale => pixels.map(|px| [px[0]; 3]).collect(),
+        ImageFormat::Rgb => pixels.map(|px| [px[0], px[1], px[2]]).collect(),
+        _ => pixels
+            .filter(|px| px[3] >= 128)
+            .map(|px| [px[0], px[1], px[2]])
+            .collect(),
+    }
+}
+
+/// The channel with the biggest spread of values, and the spread.
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let min = colors.iter().map(|px| px[c]).min().unwrap_or(0);
+            let max = colors.iter().map(|px| px[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/// The palette as CSS custom properties.
+pub fn to_css(swatches: &[Swatch]) -> String {
+    let mut out = String::from(":root {\n");
+    for (idx, swatch) in swatches.iter().enumerate() {
+        // Writing to a `String` can't fail.
+        let _ = writeln!(out, "  --palette-{}: {};", idx + 1, swatch.hex());
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The palette as a JSON array of hex strings.
+pub fn to_json(swatches: &[Swatch]) -> String {
+    let hexes: Vec<String> = swatches
+        .iter()
+        .map(|swatch| format!("\"{}\"", swatch.hex()))
+        .collect();
+    format!("[{}]", hexes.join(", "))
+}
@@ -0,0 +1,234 @@
+//! Combining images into a PDF, one image per page.
+//!
+//! Images are stored losslessly (deflated RGB), so a PDF of scans looks exactly like the scans.
+use clap::ArgEnum;
+use druid::{piet::ImageFormat, Data, ImageBuf, Lens};
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{decode, pixel_ops};
+
+/// PDF units (points) per mm.
+const POINTS_PER_MM: f64 = 72. / 25.4;
+
+/// Portrait page sizes, in points.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum, Data)]
+pub enum PageSize {
+    A3,
+    A4,
+    A5,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    fn points(self) -> (f64, f64) {
+        match self {
+            PageSize::A3 => (842., 1191.),
+            PageSize::A4 => (595., 842.),
+            PageSize::A5 => (420., 595.),
+            PageSize::Letter => (612., 792.),
+            PageSize::Legal => (612., 1008.),
+        }
+    }
+}
+
+/// How each image is sized on its page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum, Data)]
+pub enum Fit {
+    /// As large as possible while showing the whole image.
+    Contain,
+    /// Fill the area inside the margins, cropping the image.
+    Cover,
+    /// At 1 pixel per point, unless that would not fit, in which case like `Contain`.
+    Shrink,
+}
+
+#[derive(Debug, Clone, Data, Lens)]
+pub struct PdfOptions {
+    pub page_size: PageSize,
+    /// The margin on every side, in mm.
+    pub margin: f64,
+    pub fit: Fit,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self {
+            page_size: PageSize::A4,
+            margin: 10.,
+            fit: Fit::Contain,
+        }
+    }
+}
+
+/// Write `images` to a PDF at `dest`, one per page.
+///
+/// Each page is turned to landscape if the image on it is wider than it is tall.
+pub fn export(
+    images: &[PathBuf],
+    dest: &Path,
+    options: &PdfOptions,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if images.is_empty() {
+        return Err("no images to export".into());
+    }
+    let mut pdf = PdfWriter::new(BufWriter::new(File::create(dest)?), images.len())?;
+    for (idx, path) in images.iter().enumerate() {
+        // Decode one at a time, so we only ever hold one image in memory.
+        let image = decode::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        pdf.write_page(idx, &image, options)?;
+    }
+    pdf.finish()?;
+    Ok(())
+}
+
+/// Writes a PDF with a fixed number of pages, keeping track of where each object starts for the
+/// cross-reference table.
+///
+/// Object 1 is the catalog and 2 the page tree; page `i` uses objects `3 + 3i` (the page),
+/// `4 + 3i` (its content) and `5 + 3i` (its image).
+struct PdfWriter<W> {
+    out: W,
+    /// Bytes written so far.
+    offset: usize,
+    /// The offset of each object, indexed by object number - 1.
+    offsets: Vec<usize>,
+}
+
+impl<W: Write> PdfWriter<W> {
+    fn new(out: W, pages: usize) -> io::Result<Self> {
+        let mut pdf = Self {
+            out,
+            offset: 0,
+            offsets: vec![0; 2 + 3 * pages],
+        };
+        // The comment with high bytes tells tools the file is binary.
+        pdf.write(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n")?;
+        Ok(pdf)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.offset += bytes.len();
+        Ok(())
+    }
+
+    fn begin_object(&mut self, id: usize) -> io::Result<()> {
+        self.offsets[id - 1] = self.offset;
+        self.write(format!("{} 0 obj\n", id).as_bytes())
+    }
+
+    fn write_object(&mut self, id: usize, body: &str) -> io::Result<()> {
+        self.begin_object(id)?;
+        self.write(body.as_bytes())?;
+        self.write(b"\nendobj\n")
+    }
+
+    fn write_stream(&mut self, id: usize, dict: &str, data: &[u8]) -> io::Result<()> {
+        self.begin_object(id)?;
+        self.write(format!("<< {} /Length {} >>\nstream\n", dict, data.len()).as_bytes())?;
+        self.write(data)?;
+        self.write(b"\nendstream\nendobj\n")
+    }
+
+    fn write_page(&mut self, idx: usize, image: &ImageBuf, options: &PdfOptions) -> io::Result<()> {
+        let (page_id, content_id, image_id) = (3 + 3 * idx, 4 + 3 * idx, 5 + 3 * idx);
+        let (img_w, img_h) = (image.width() as f64, image.height() as f64);
+        let (mut page_w, mut page_h) = options.page_size.points();
+        if img_w > img_h {
+            std::mem::swap(&mut page_w, &mut page_h);
+        }
+
+        // The area inside the margins, never less than a point so we don't divide by zero.
+        let margin = options.margin.max(0.) * POINTS_PER_MM;
+        let area_w = (page_w - 2. * margin).max(1.);
+        let area_h = (page_h - 2. * margin).max(1.);
+        let contain = (area_w / img_w).min(area_h / img_h);
+        let scale = match options.fit {
+            Fit::Contain => contain,
+            Fit::Cover => (area_w / img_w).max(area_h / img_h),
+            Fit::Shrink => contain.min(1.),
+        };
+        let (draw_w, draw_h) = (img_w * scale, img_h * scale);
+        let (x, y) = ((page_w - draw_w) / 2., (page_h - draw_h) / 2.);
+
+        self.write_object(
+            page_id,
+            &format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] \
+                 /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>",
+                page_w, page_h, image_id, content_id
+            ),
+        )?;
+        // Clip to the margins, which only makes a difference for `Cover`.
+        let content = format!(
+            "q {:.2} {:.2} {:.2} {:.2} re W n {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im0 Do Q",
+            margin, margin, area_w, area_h, draw_w, draw_h, x, y
+        );
+        self.write_stream(content_id, "", content.as_bytes())?;
+
+        let rgb = to_rgb(image);
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&rgb, 6);
+        self.write_stream(
+            image_id,
+            &format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+                 /BitsPerComponent 8 /Filter /FlateDecode",
+                image.width(),
+                image.height()
+            ),
+            &compressed,
+        )
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let pages = (self.offsets.len() - 2) / 3;
+        let kids = (0..pages)
+            .map(|idx| format!("{} 0 R", 3 + 3 * idx))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.write_object(1, "<< /Type /Catalog /Pages 2 0 R >>")?;
+        self.write_object(
+            2,
+            &format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids, pages),
+        )?;
+
+        let xref_offset = self.offset;
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            xref_offset
+        ));
+        self.write(xref.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+/// Flatten `image` to RGB, putting anything transparent on white paper.
+pub fn to_rgb(image: &ImageBuf) -> Vec<u8> {
+    let premultiplied = image.format() == ImageFormat::RgbaPremul;
+    pixel_ops::to_rgba(image)
+        .chunks_exact(4)
+        .flat_map(|px| {
+            let alpha = px[3] as u32;
+            let over_white = |c: u8| {
+                let c = if premultiplied {
+                    c as u32
+                } else {
+                    c as u32 * alpha / 255
+                };
+                (c + 255 - alpha) as u8
+            };
+            [over_white(px[0]), over_white(px[1]), over_white(px[2])]
+        })
+        .collect()
+}
@@ -0,0 +1,322 @@
+//! Hot pixel loops.
+//!
+//! Each operation has a scalar implementation, and some have a SIMD implementation that is used
+//! when the `simd` feature is enabled and the target supports it. Both must give exactly the same
+//! results.
+//!
+//! Unless otherwise stated, images are tightly packed 8-bit RGBA.
+use druid::{piet::ImageFormat, ImageBuf};
+use std::borrow::Cow;
+
+/// Get the pixels of `image` as RGBA, converting if necessary.
+///
+/// Premultiplied images are returned as they are.
+pub fn to_rgba(image: &ImageBuf) -> Cow<[u8]> {
+    let pixels = image.raw_pixels();
+    match image.format() {
+        ImageFormat::Rgb => Cow::Owned(
+            pixels
+                .chunks_exact(3)
+                .flat_map(|px| [px[0], px[1], px[2], 0xff])
+                .collect(),
+        ),
+        ImageFormat::Grayscale => {
+            Cow::Owned(pixels.iter().flat_map(|&g| [g, g, g, 0xff]).collect())
+        }
+        _ => Cow::Borrowed(pixels),
+    }
+}
+
+/// Halve an RGBA image in both dimensions by averaging each 2x2 block.
+///
+/// An odd last row or column is dropped. Returns the new pixels, width and height.
+pub fn halve(src: &[u8], width: usize, height: usize) -> (Vec<u8>, usize, usize) {
+    assert_eq!(src.len(), width * height * 4, "pixel buffer has wrong size");
+    let (out_w, out_h) = (width / 2, height / 2);
+    let mut out = vec![0; out_w * out_h * 4];
+    for y in 0..out_h {
+        let top = &src[2 * y * width * 4..(2 * y + 1) * width * 4];
+        let bottom = &src[(2 * y + 1) * width * 4..(2 * y + 2) * width * 4];
+        let row = &mut out[y * out_w * 4..(y + 1) * out_w * 4];
+        halve_row(top, bottom, row);
+    }
+    (out, out_w, out_h)
+}
+
+fn halve_row(top: &[u8], bottom: &[u8], out: &mut [u8]) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    let done = simd::halve_row(top, bottom, out);
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    let done = 0;
+    scalar::halve_row(&top[done * 2..], &bottom[done * 2..], &mut out[done..]);
+}
+
+/// Replace every byte `b` in `pixels` with `lut[b]`, leaving alpha alone.
+///
+/// There is no byte gather instruction, so this is scalar everywhere, but it is here so callers
+/// don't each write their own loop.
+pub fn apply_lut(pixels: &mut [u8], lut: &[u8; 256]) {
+    for px in pixels.chunks_exact_mut(4) {
+        px[0] = lut[px[0] as usize];
+        px[1] = lut[px[1] as usize];
+        px[2] = lut[px[2] as usize];
+    }
+}
+
+/// Copy one channel (0 = red, 3 = alpha) out of an RGBA buffer.
+pub fn extract_channel(src: &[u8], channel: usize) -> Vec<u8> {
+    assert!(channel < 4, "channel must be in 0..4, got {}", channel);
+    let mut out = vec![0; src.len() / 4];
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    let done = simd::extract_channel(src, channel, &mut out);
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    let done = 0;
+    scalar::extract_channel(&src[done * 4..], channel, &mut out[done..]);
+    out
+}
+
+/// Per-byte absolute difference of `a` and `b`, written to `out`.
+///
+/// # Panics
+///
+/// Panics if the three buffers aren't the same length.
+pub fn abs_diff(a: &[u8], b: &[u8], out: &mut [u8]) {
+    assert!(
+        a.len() == b.len() && a.len() == out.len(),
+        "buffers must be the same length"
+    );
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    let done = simd::abs_diff(a, b, out);
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    let done = 0;
+    scalar::abs_diff(&a[done..], &b[done..], &mut out[done..]);
+}
+
+/// 4x4 Bayer matrix, for ordered dithering.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Convert RGBA pixels in rows of `width` to 16 level grey, one byte per pixel, with an ordered
+/// dither. Transparent pixels are put on white.
+///
+/// The dither pattern is aligned to the top left, so images cut into pieces whose offsets are
+/// multiples of 4 match up.
+pub fn dither_grey(src: &[u8], width: usize, premultiplied: bool) -> Vec<u8> {
+    // The gap between levels.
+    const STEP: f32 = 255. / 15.;
+    src.chunks_exact(4)
+        .enumerate()
+        .map(|(idx, px)| {
+            let (x, y) = (idx % width, idx / width);
+            let luma = 0.2126 * px[0] as f32 + 0.7152 * px[1] as f32 + 0.0722 * px[2] as f32;
+            let alpha = px[3] as f32 / 255.;
+            let luma = if premultiplied {
+                luma + 255. * (1. - alpha)
+            } else {
+                luma * alpha + 255. * (1. - alpha)
+            };
+            let threshold = (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.;
+            let level = (luma / STEP + threshold - 0.5).round().max(0.).min(15.);
+            (level * STEP).round() as u8
+        })
+        .collect()
+}
+
+mod scalar {
+    pub fn halve_row(top: &[u8], bottom: &[u8], out: &mut [u8]) {
+        for (idx, px) in out.chunks_exact_mut(4).enumerate() {
+            for c in 0..4 {
+                let (l, r) = (idx * 8 + c, idx * 8 + 4 + c);
+                // Average the pairs first then the results, rounding up each time, to match
+                // `_mm_avg_epu8`.
+                let t = avg(top[l], top[r]);
+                let b = avg(bottom[l], bottom[r]);
+                px[c] = avg(t, b);
+            }
+        }
+    }
+
+    pub fn extract_channel(src: &[u8], channel: usize, out: &mut [u8]) {
+        for (px, out) in src.chunks_exact(4).zip(out) {
+            *out = px[channel];
+        }
+    }
+
+    pub fn abs_diff(a: &[u8], b: &[u8], out: &mut [u8]) {
+        for ((a, b), out) in a.iter().zip(b).zip(out) {
+            *out = if a > b { a - b } else { b - a };
+        }
+    }
+
+    fn avg(a: u8, b: u8) -> u8 {
+        ((a as u16 + b as u16 + 1) >> 1) as u8
+    }
+}
+
+/// SSE2 versions. These handle as many whole vectors as they can and return how many output
+/// bytes they wrote; the caller finishes the tail with the scalar version.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd {
+    use std::arch::x86_64::*;
+
+    pub fn halve_row(top: &[u8], bottom: &[u8], out: &mut [u8]) -> usize {
+        // 8 input pixels (2 vectors) per row make 4 output pixels (1 vector).
+        let chunks = out.len() / 16;
+        for i in 0..chunks {
+            // SAFETY: SSE2 is always available on x86_64, and all loads and stores are unaligned
+            // and within the slices' bounds.
+            unsafe {
+                let t0 = _mm_loadu_si128(top.as_ptr().add(i * 32) as *const __m128i);
+                let t1 = _mm_loadu_si128(top.as_ptr().add(i * 32 + 16) as *const __m128i);
+                let b0 = _mm_loadu_si128(bottom.as_ptr().add(i * 32) as *const __m128i);
+                let b1 = _mm_loadu_si128(bottom.as_ptr().add(i * 32 + 16) as *const __m128i);
+                let t = avg_pairs(t0, t1);
+                let b = avg_pairs(b0, b1);
+                _mm_storeu_si128(
+                    out.as_mut_ptr().add(i * 16) as *mut __m128i,
+                    _mm_avg_epu8(t, b),
+                );
+            }
+        }
+        chunks * 16
+    }
+
+    /// Average horizontally adjacent pixels across `a` and `b` (4 pixels each), giving 4 pixels.
+    unsafe fn avg_pairs(a: __m128i, b: __m128i) -> __m128i {
+        // Gather even pixels and odd pixels (as 32-bit lanes) into their own vectors.
+        let a = _mm_shuffle_epi32(a, 0b11_01_10_00);
+        let b = _mm_shuffle_epi32(b, 0b11_01_10_00);
+        let even = _mm_unpacklo_epi64(a, b);
+        let odd = _mm_unpackhi_epi64(a, b);
+        _mm_avg_epu8(even, odd)
+    }
+
+    pub fn extract_channel(src: &[u8], channel: usize, out: &mut [u8]) -> usize {
+        // 16 input pixels (4 vectors) make 16 output bytes (1 vector).
+        let chunks = out.len() / 16;
+        for i in 0..chunks {
+            // SAFETY: see `halve_row`.
+            unsafe {
+                let shift = _mm_cvtsi32_si128(channel as i32 * 8);
+                let load = |j: usize| {
+                    let v = _mm_loadu_si128(src.as_ptr().add(i * 64 + j * 16) as *const __m128i);
+                    _mm_and_si128(_mm_srl_epi32(v, shift), _mm_set1_epi32(0xff))
+                };
+                // Each 32-bit lane now holds one value, so packing twice puts them side by side.
+                let lo = _mm_packs_epi32(load(0), load(1));
+                let hi = _mm_packs_epi32(load(2), load(3));
+                _mm_storeu_si128(
+                    out.as_mut_ptr().add(i * 16) as *mut __m128i,
+                    _mm_packus_epi16(lo, hi),
+                );
+            }
+        }
+        chunks * 16
+    }
+
+    pub fn abs_diff(a: &[u8], b: &[u8], out: &mut [u8]) -> usize {
+        let chunks = out.len() / 16;
+        for i in 0..chunks {
+            // SAFETY: see `halve_row`.
+            unsafe {
+                let va = _mm_loadu_si128(a.as_ptr().add(i * 16) as *const __m128i);
+                let vb = _mm_loadu_si128(b.as_ptr().add(i * 16) as *const __m128i);
+                let diff = _mm_or_si128(_mm_subs_epu8(va, vb), _mm_subs_epu8(vb, va));
+                _mm_storeu_si128(out.as_mut_ptr().add(i * 16) as *mut __m128i, diff);
+            }
+        }
+        chunks * 16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that cover the whole range, the same every run.
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// Widths that fill whole vectors, leave a tail, and are odd.
+    const WIDTHS: &[usize] = &[1, 2, 3, 7, 8, 9, 15, 16, 17, 31, 33, 64, 101];
+
+    #[test]
+    fn halve_matches_scalar() {
+        for &width in WIDTHS {
+            for &height in &[1, 2, 3, 5, 8] {
+                let src = noise(width * height * 4, (width * 31 + height) as u32);
+                let (out, out_w, out_h) = halve(&src, width, height);
+                assert_eq!((out_w, out_h), (width / 2, height / 2));
+                let mut expected = vec![0; out.len()];
+                for y in 0..out_h {
+                    let top = &src[2 * y * width * 4..(2 * y + 1) * width * 4];
+                    let bottom = &src[(2 * y + 1) * width * 4..(2 * y + 2) * width * 4];
+                    let row = &mut expected[y * out_w * 4..(y + 1) * out_w * 4];
+                    scalar::halve_row(top, bottom, row);
+                }
+                assert_eq!(out, expected, "{}×{}", width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn apply_lut_maps_colours_but_not_alpha() {
+        let mut lut = [0; 256];
+        for (idx, entry) in lut.iter_mut().enumerate() {
+            *entry = 255 - idx as u8;
+        }
+        for &width in WIDTHS {
+            let src = noise(width * 4, width as u32);
+            let mut out = src.clone();
+            apply_lut(&mut out, &lut);
+            for (px, orig) in out.chunks_exact(4).zip(src.chunks_exact(4)) {
+                assert_eq!(
+                    &px[..3],
+                    &[
+                        lut[orig[0] as usize],
+                        lut[orig[1] as usize],
+                        lut[orig[2] as usize]
+                    ]
+                );
+                assert_eq!(px[3], orig[3], "alpha is left alone");
+            }
+        }
+    }
+
+    #[test]
+    fn extract_channel_matches_scalar() {
+        for &width in WIDTHS {
+            let src = noise(width * 4, width as u32);
+            for channel in 0..4 {
+                let mut expected = vec![0; width];
+                scalar::extract_channel(&src, channel, &mut expected);
+                assert_eq!(expected[width - 1], src[(width - 1) * 4 + channel]);
+                assert_eq!(
+                    extract_channel(&src, channel),
+                    expected,
+                    "{} pixels, channel {}",
+                    width,
+                    channel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn abs_diff_matches_scalar() {
+        for len in (0..70).chain([255, 256, 257, 1001].iter().copied()) {
+            let (a, b) = (noise(len, 1), noise(len, 2));
+            let mut out = vec![0; len];
+            abs_diff(&a, &b, &mut out);
+            let mut expected = vec![0; len];
+            scalar::abs_diff(&a, &b, &mut expected);
+            assert_eq!(out, expected, "length {}", len);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Settings profiles, which bundle how an image is drawn for a kind of image.
+//!
+//! Photos, pixel art and scanned documents want different things: pixel art should be crisp and
+//! shown at whole number scales, and a scanned page should be smooth, on white, and fill the
+//! width of the window. A profile is picked for each image by the rules below, or the user can
+//! choose one for every image.
+use druid::{Color, Data};
+use std::path::Path;
+
+use crate::widgets::InterpolationPolicy;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Data)]
+pub enum ProfileKind {
+    Photos,
+    PixelArt,
+    Scans,
+}
+
+impl Default for ProfileKind {
+    fn default() -> Self {
+        ProfileKind::Photos
+    }
+}
+
+/// How big to show an image when it is first opened.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InitialZoom {
+    /// The whole image, as big as fits.
+    Fit,
+    /// The largest whole number scale that fits, or as `Fit` if the image is too big for 100%.
+    FitWhole,
+    /// The width of the window, from the top of the image.
+    FitWidth,
+    /// This scale, from the middle of the image.
+    Scale(f64),
+}
+
+/// The settings a profile bundles.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub interpolation: InterpolationPolicy,
+    /// What to draw behind the image, or `None` for the window background.
+    pub background: Option<Color>,
+    pub initial_zoom: InitialZoom,
+    /// Whether to draw the overlay scrollbars.
+    pub scrollbars: bool,
+}
+
+impl ProfileKind {
+    pub const ALL: [ProfileKind; 3] = [
+        ProfileKind::Photos,
+        ProfileKind::PixelArt,
+        ProfileKind::Scans,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProfileKind::Photos => "Photos",
+            ProfileKind::PixelArt => "Pixel art",
+            ProfileKind::Scans => "Scans",
+        }
+    }
+
+    pub fn settings(self) -> Profile {
+        match self {
+            ProfileKind::Photos => Profile {
+                interpolation: InterpolationPolicy::default(),
+                background: None,
+                initial_zoom: InitialZoom::Fit,
+                scrollbars: true,
+            },
+            ProfileKind::PixelArt => Profile {
+                interpolation: InterpolationPolicy {
+                    nearest_above: 1.,
+                    ..InterpolationPolicy::default()
+                },
+                background: Some(Color::grey8(0x30)),
+                initial_zoom: InitialZoom::FitWhole,
+                // They would hide the pixels along the edges.
+                scrollbars: false,
+            },
+            ProfileKind::Scans => Profile {
+                // Text looks blocky with nearest neighbour, however far in we zoom.
+                interpolation: InterpolationPolicy {
+                    nearest_above: f64::INFINITY,
+                    ..InterpolationPolicy::default()
+                },
+                background: Some(Color::WHITE),
+                initial_zoom: InitialZoom::FitWidth,
+                scrollbars: true,
+            },
+        }
+    }
+}
+
+/// Part of a path a rule matches on.
+enum Rule {
+    /// Any folder in the path whose name contains this, ignoring case.
+    Folder(&'static str),
+    /// The file extension, ignoring case.
+    Extension(&'static str),
+}
+
+/// Checked in order: folder rules come first, so a folder of scanned JPEGs counts as scans.
+/// Anything else, including camera RAW files, is a photo.
+const RULES: &[(Rule, ProfileKind)] = &[
+    (Rule::Folder("scan"), ProfileKind::Scans),
+    (Rule::Folder("document"), ProfileKind::Scans),
+    (Rule::Folder("sprite"), ProfileKind::PixelArt),
+    (Rule::Folder("pixel"), ProfileKind::PixelArt),
+    (Rule::Folder("dcim"), ProfileKind::Photos),
+    (Rule::Folder("photo"), ProfileKind::Photos),
+    (Rule::Extension("tif"), ProfileKind::Scans),
+    (Rule::Extension("tiff"), ProfileKind::Scans),
+];
+
+/// The profile the rules pick for the image at `path`.
+pub fn for_path(path: &Path) -> ProfileKind {
+    let folders: Vec<String> = path
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.iter())
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .collect();
+    let matches = |rule: &Rule| match rule {
+        Rule::Folder(name) => folders.iter().any(|folder| folder.contains(name)),
+        Rule::Extension(ext) => path
+            .extension()
+            .map_or(false, |actual| actual.eq_ignore_ascii_case(ext)),
+    };
+    RULES
+        .iter()
+        .find(|(rule, _)| matches(rule))
+        .map_or(ProfileKind::default(), |(_, kind)| *kind)
+}
@@ -0,0 +1,136 @@
+//! Camera RAW files.
+//!
+//! By default we show the JPEG preview the camera embeds, which is quick and is what the camera
+//! showed on its own screen. With the `raw-demosaic` feature the sensor data is developed
+//! instead.
+//!
+//! The formats we handle (CR2, NEF, ARW and DNG) are all TIFF underneath, with previews in one
+//! of the IFDs.
+use druid::ImageBuf;
+use std::{collections::HashSet, error::Error, fs, path::Path};
+
+use crate::{decode, exif, tiff::Tiff};
+
+type RawResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+const EXTENSIONS: &[&str] = &["cr2", "nef", "arw", "dng"];
+
+const COMPRESSION: u16 = 0x0103;
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const SUB_IFDS: u16 = 0x014a;
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+/// Old and new style JPEG compression.
+const JPEG_COMPRESSION: &[u32] = &[6, 7];
+
+pub fn is_raw(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| {
+        EXTENSIONS
+            .iter()
+            .any(|known| ext.eq_ignore_ascii_case(known))
+    })
+}
+
+pub fn open(path: &Path) -> RawResult<ImageBuf> {
+    #[cfg(feature = "raw-demosaic")]
+    match develop(path) {
+        Ok(image) => return Ok(image),
+        Err(e) => log::warn!("could not develop {}, using preview: {}", path.display(), e),
+    }
+    open_preview(path)
+}
+
+/// Develop the sensor data.
+#[cfg(feature = "raw-demosaic")]
+fn develop(path: &Path) -> RawResult<ImageBuf> {
+    let image = imagepipe::simple_decode_8bit(path, 0, 0)?;
+    Ok(ImageBuf::from_raw(
+        image.data,
+        druid::piet::ImageFormat::Rgb,
+        image.width,
+        image.height,
+    ))
+}
+
+/// Decode the largest JPEG preview in the file.
+pub fn open_preview(path: &Path) -> RawResult<ImageBuf> {
+    let bytes = fs::read(path)?;
+    let tiff = Tiff::new(&bytes).ok_or("not a TIFF based RAW file")?;
+    let mut best: Option<&[u8]> = None;
+    for (offset, len) in jpegs(&tiff) {
+        let jpeg = match bytes.get(offset..offset.saturating_add(len)) {
+            Some(jpeg) if is_decodable_jpeg(jpeg) => jpeg,
+            _ => continue,
+        };
+        if best.map_or(true, |best| jpeg.len() > best.len()) {
+            best = Some(jpeg);
+        }
+    }
+    let jpeg = best.ok_or("no preview found")?;
+    let image = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg)?;
+    // The preview is stored as the sensor sees it, and the RAW file says which way is up.
+    let orientation = exif::parse(&bytes).and_then(|exif| exif.orientation);
+    Ok(exif::orient(
+        decode::from_dynamic_image(image),
+        orientation.unwrap_or(1),
+    ))
+}
+
+/// Check `jpeg` is a JPEG we can decode. Raw sensor data is often stored as lossless JPEG, which
+/// looks like a preview from the outside.
+fn is_decodable_jpeg(jpeg: &[u8]) -> bool {
+    if !jpeg.starts_with(&[0xff, 0xd8]) {
+        return false;
+    }
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() && jpeg[pos] == 0xff {
+        match jpeg[pos + 1] {
+            // Baseline, extended and progressive.
+            0xc0 | 0xc1 | 0xc2 => return true,
+            // Any other start of frame (lossless, hierarchical, arithmetic).
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => return false,
+            _ => {
+                let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+                pos += 2 + len;
+            }
+        }
+    }
+    false
+}
+
+/// The (offset, length) of every JPEG referenced from any IFD.
+fn jpegs(tiff: &Tiff) -> Vec<(usize, usize)> {
+    let mut out = vec![];
+    let mut queue: Vec<usize> = tiff.first_ifd().into_iter().collect();
+    // Guard against files whose IFDs point at each other.
+    let mut seen = HashSet::new();
+    while let Some(ifd) = queue.pop() {
+        if ifd == 0 || !seen.insert(ifd) {
+            continue;
+        }
+        let (entries, next) = match tiff.ifd(ifd) {
+            Some(ifd) => ifd,
+            None => continue,
+        };
+        let first = |tag| {
+            entries
+                .iter()
+                .find(|entry| entry.tag == tag)
+                .and_then(|entry| tiff.int(entry))
+        };
+        if let (Some(offset), Some(len)) = (first(JPEG_OFFSET), first(JPEG_LENGTH)) {
+            out.push((offset as usize, len as usize));
+        }
+        if first(COMPRESSION).map_or(false, |c| JPEG_COMPRESSION.contains(&c)) {
+            if let (Some(offset), Some(len)) = (first(STRIP_OFFSETS), first(STRIP_BYTE_COUNTS)) {
+                out.push((offset as usize, len as usize));
+            }
+        }
+        if let Some(subs) = entries.iter().find(|entry| entry.tag == SUB_IFDS) {
+            queue.extend(tiff.ints(subs).into_iter().map(|offset| offset as usize));
+        }
+        queue.extend(next);
+    }
+    out
+}
@@ -0,0 +1,292 @@
+//! Integration with the desktop shell.
+use druid::Selector;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Show the current image in the file manager.
+pub const REVEAL_FILE: Selector = Selector::new("image-viewer.reveal-file");
+
+/// When we are sandboxed (Flatpak or Snap), get GTK to go through the XDG desktop portal for file
+/// dialogs.
+///
+/// The portal's file chooser runs outside the sandbox, and the paths it gives back point into the
+/// document portal, which we can read like any other file. Outside a sandbox we leave GTK's choice
+/// of dialog alone, so files are opened by their real paths.
+#[cfg(target_os = "linux")]
+pub fn use_portals_if_sandboxed() {
+    if is_sandboxed() && std::env::var_os("GTK_USE_PORTAL").is_none() {
+        log::debug!("running in a sandbox, using the desktop portal for file access");
+        std::env::set_var("GTK_USE_PORTAL", "1");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn use_portals_if_sandboxed() {}
+
+/// Whether we are running under Flatpak or Snap.
+pub fn is_sandboxed() -> bool {
+    cfg!(target_os = "linux")
+        && (std::path::Path::new("/.flatpak-info").exists() || std::env::var_os("SNAP").is_some())
+}
+
+/// Open a new message in the user's mail client with `attachments` attached.
+///
+/// Windows has no command line way to do this; it would need MAPI.
+#[cfg(target_os = "linux")]
+pub fn send_by_email(attachments: &[PathBuf]) -> io::Result<()> {
+    let mut cmd = std::process::Command::new("xdg-email");
+    for path in attachments {
+        cmd.arg("--attach").arg(path);
+    }
+    run(cmd)
+}
+
+#[cfg(target_os = "macos")]
+pub fn send_by_email(attachments: &[PathBuf]) -> io::Result<()> {
+    // Opening files with Mail starts a new message with them attached.
+    let mut cmd = std::process::Command::new("open");
+    cmd.args(&["-a", "Mail"]).args(attachments);
+    run(cmd)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn send_by_email(_attachments: &[PathBuf]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sending email is only supported on Linux and macOS",
+    ))
+}
+
+/// Open the folder containing `path` in the file manager, with `path` selected.
+#[cfg(target_os = "linux")]
+pub fn reveal(path: &Path) -> io::Result<()> {
+    let path = absolute(path)?;
+    // Nautilus, Dolphin, Nemo and others select the file when asked through this interface.
+    let mut cmd = std::process::Command::new("dbus-send");
+    cmd.args(&[
+        "--session",
+        "--dest=org.freedesktop.FileManager1",
+        "--type=method_call",
+        "/org/freedesktop/FileManager1",
+        "org.freedesktop.FileManager1.ShowItems",
+    ])
+    .arg(format!(
+        "array:string:{}",
+        crate::clipboard::file_url(&path)
+    ))
+    .arg("string:");
+    run(cmd).or_else(|e| {
+        // Without a file manager that implements it, the best we can do is open the folder.
+        log::debug!("could not select file in file manager: {}", e);
+        let mut cmd = std::process::Command::new("xdg-open");
+        cmd.arg(path.parent().unwrap_or(&path));
+        run(cmd)
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn reveal(path: &Path) -> io::Result<()> {
+    let mut cmd = std::process::Command::new("open");
+    cmd.arg("-R").arg(absolute(path)?);
+    run(cmd)
+}
+
+#[cfg(windows)]
+pub fn reveal(path: &Path) -> io::Result<()> {
+    use std::os::windows::process::CommandExt;
+    // Explorer wants the quotes around the path only, not the whole argument, so we can't let
+    // `Command` quote it. It exits with 1 even when it works, so the status is no use either.
+    std::process::Command::new("explorer")
+        .raw_arg(format!("/select,\"{}\"", absolute(path)?.display()))
+        .spawn()?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn reveal(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "showing files in the file manager isn't supported on this platform",
+    ))
+}
+
+/// The folder our settings files go in: `$XDG_CONFIG_HOME/image-viewer` (usually
+/// `~/.config/image-viewer`) on Linux, `~/Library/Application Support/image-viewer` on macOS, and
+/// `%APPDATA%\image-viewer` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    use std::env::var_os;
+    let base = if cfg!(windows) {
+        PathBuf::from(var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(base.join("image-viewer"))
+}
+
+/// Whether the desktop is set to prefer dark windows, or `None` if it doesn't say or we can't
+/// find out. This is GNOME's setting, which other desktops and the settings portal mostly follow.
+#[cfg(target_os = "linux")]
+pub fn prefers_dark() -> Option<bool> {
+    let out = std::process::Command::new("gsettings")
+        .args(&["get", "org.gnome.desktop.interface", "color-scheme"])
+        .output()
+        .ok()?;
+    match String::from_utf8_lossy(&out.stdout)
+        .trim()
+        .trim_matches('\'')
+    {
+        "prefer-dark" => Some(true),
+        "default" | "prefer-light" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether the system is set to dark mode. `AppleInterfaceStyle` is only there in dark mode.
+#[cfg(target_os = "macos")]
+pub fn prefers_dark() -> Option<bool> {
+    let out = std::process::Command::new("defaults")
+        .args(&["read", "-g", "AppleInterfaceStyle"])
+        .output()
+        .ok()?;
+    Some(out.status.success() && String::from_utf8_lossy(&out.stdout).trim() == "Dark")
+}
+
+/// Whether apps are set to use the dark theme.
+#[cfg(windows)]
+pub fn prefers_dark() -> Option<bool> {
+    let out = std::process::Command::new("reg")
+        .args(&[
+            "query",
+            r"HKCU\Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
+            "/v",
+            "AppsUseLightTheme",
+        ])
+        .output()
+        .ok()?;
+    // The value is on a line like `    AppsUseLightTheme    REG_DWORD    0x0`.
+    let text = String::from_utf8_lossy(&out.stdout);
+    let line = text
+        .lines()
+        .find(|line| line.contains("AppsUseLightTheme"))?;
+    match line.split_whitespace().last()? {
+        "0x0" => Some(true),
+        _ => Some(false),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn prefers_dark() -> Option<bool> {
+    None
+}
+
+/// `path`, made absolute if it is relative, for handing to another program. We don't
+/// canonicalize, because on Windows that gives `\\?\` paths that Explorer doesn't understand.
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    Ok(std::env::current_dir()?.join(path))
+}
+
+/// Run `cmd`, turning a failure exit status into an error.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn run(mut cmd: std::process::Command) -> io::Result<()> {
+    let status = cmd.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("`{:?}` failed ({})", cmd, status),
+        ))
+    }
+}
+
+/// The ProgID we register our file associations under.
+#[cfg(windows)]
+const PROG_ID: &str = "ImageViewer.Image";
+
+/// Make the viewer show up in "Open with" for `extensions`.
+///
+/// Everything goes under `HKEY_CURRENT_USER`, so this doesn't need admin rights and doesn't take
+/// over the user's existing default programs.
+#[cfg(windows)]
+pub fn register_file_types(extensions: &[&str]) -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let exe_name = exe
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("image-viewer.exe");
+    let open_command = format!("\"{}\" \"%1\"", exe.display());
+    let classes = r"HKCU\Software\Classes";
+
+    let prog_id = format!(r"{}\{}", classes, PROG_ID);
+    reg_add(&prog_id, None, "Image")?;
+    reg_add(
+        &format!(r"{}\DefaultIcon", prog_id),
+        None,
+        &format!("{},0", exe.display()),
+    )?;
+    register_open_verb(&prog_id, &open_command)?;
+
+    let app = format!(r"{}\Applications\{}", classes, exe_name);
+    register_open_verb(&app, &open_command)?;
+    for ext in extensions {
+        let ext = format!(".{}", ext);
+        reg_add(&format!(r"{}\SupportedTypes", app), Some(&ext), "")?;
+        reg_add(
+            &format!(r"{}\{}\OpenWithProgids", classes, ext),
+            Some(PROG_ID),
+            "",
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+pub fn register_file_types(_extensions: &[&str]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "registering file types is only supported on Windows",
+    ))
+}
+
+/// Register the `open` verb under `key`.
+///
+/// We use the "Player" multi-select model so Explorer doesn't refuse selections of more than 15
+/// files.
+#[cfg(windows)]
+fn register_open_verb(key: &str, command: &str) -> io::Result<()> {
+    reg_add(
+        &format!(r"{}\shell\open", key),
+        Some("MultiSelectModel"),
+        "Player",
+    )?;
+    reg_add(&format!(r"{}\shell\open\command", key), None, command)
+}
+
+/// Set a string value in the registry, using `reg.exe` so we don't need bindings to the registry
+/// API. `None` means the key's default value.
+#[cfg(windows)]
+fn reg_add(key: &str, value: Option<&str>, data: &str) -> io::Result<()> {
+    let mut cmd = std::process::Command::new("reg");
+    cmd.args(&["add", key]);
+    match value {
+        Some(value) => cmd.args(&["/v", value]),
+        None => cmd.arg("/ve"),
+    };
+    let status = cmd.args(&["/t", "REG_SZ", "/d", data, "/f"]).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("`reg add {}` failed ({})", key, status),
+        ))
+    }
+}
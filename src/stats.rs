@@ -0,0 +1,175 @@
+//! A summary of the images in the navigation list: how many of each format, how big, when they
+//! were taken, and with what.
+use druid::Selector;
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::exif;
+
+/// Open or close the folder statistics panel.
+pub const TOGGLE_FOLDER_STATS: Selector = Selector::new("image-viewer.toggle-folder-stats");
+
+/// Images are counted in these size groups, by megapixels. The last group has no upper limit.
+const DIMENSION_BUCKETS: &[(f64, &str)] = &[
+    (1., "Under 1 MP"),
+    (4., "1 to 4 MP"),
+    (12., "4 to 12 MP"),
+    (24., "12 to 24 MP"),
+    (f64::INFINITY, "24 MP and over"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FolderStats {
+    pub files: usize,
+    pub total_bytes: u64,
+    /// Upper case extensions and how many files have them, most common first.
+    pub formats: Vec<(String, usize)>,
+    /// How many images are in each of `DIMENSION_BUCKETS`, followed by those we couldn't read the
+    /// size of. Empty groups are left out.
+    pub dimensions: Vec<(&'static str, usize)>,
+    /// The earliest and latest dates, as `YYYY:MM:DD`. The EXIF date taken is used where there is
+    /// one, and the file's modified time otherwise.
+    pub dates: Option<(String, String)>,
+    /// Cameras and how many images each took, most first.
+    pub cameras: Vec<(String, usize)>,
+}
+
+impl FolderStats {
+    /// Read the size, headers and EXIF of each of `paths`. This reads every file in full, so
+    /// should be run in the background.
+    pub fn compute(paths: &[PathBuf]) -> Self {
+        let mut formats = HashMap::new();
+        let mut dimensions = vec![0; DIMENSION_BUCKETS.len() + 1];
+        let mut cameras = HashMap::new();
+        let mut stats = FolderStats::default();
+        for path in paths {
+            let meta = match fs::metadata(path) {
+                Ok(meta) => meta,
+                // It has gone since we listed the folder.
+                Err(_) => continue,
+            };
+            stats.files += 1;
+            stats.total_bytes += meta.len();
+            let ext = path
+                .extension()
+                .map_or("None".into(), |ext| ext.to_string_lossy().to_uppercase());
+            *formats.entry(ext).or_insert(0) += 1;
+
+            let bucket = match image::image_dimensions(path) {
+                Ok((width, height)) => {
+                    let megapixels = width as f64 * height as f64 / 1e6;
+                    DIMENSION_BUCKETS
+                        .iter()
+                        .position(|&(below, _)| megapixels < below)
+                        .unwrap_or(DIMENSION_BUCKETS.len() - 1)
+                }
+                Err(_) => DIMENSION_BUCKETS.len(),
+            };
+            dimensions[bucket] += 1;
+
+            let exif = exif::read(path);
+            if let Some(camera) = exif.as_ref().and_then(exif::Exif::camera) {
+                *cameras.entry(camera).or_insert(0) += 1;
+            }
+            let date = exif
+                .and_then(|exif| exif.taken)
+                .map(|taken| taken.chars().take(10).collect())
+                .or_else(|| meta.modified().ok().and_then(exif_date));
+            if let Some(date) = date {
+                stats.dates = Some(match stats.dates.take() {
+                    Some((first, last)) => (first.min(date.clone()), last.max(date)),
+                    None => (date.clone(), date),
+                });
+            }
+        }
+        stats.formats = most_first(formats);
+        stats.cameras = most_first(cameras);
+        stats.dimensions = DIMENSION_BUCKETS
+            .iter()
+            .map(|&(_, name)| name)
+            .chain(Some("Unknown"))
+            .zip(dimensions)
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        stats
+    }
+
+    /// Labels and values for the panel.
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let list = |items: &[(String, usize)]| {
+            items
+                .iter()
+                .map(|(name, count)| format!("{}: {}", name, count))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let mut rows = vec![
+            ("Files", self.files.to_string()),
+            ("Total size", format_bytes(self.total_bytes)),
+            ("Formats", list(&self.formats)),
+            (
+                "Dimensions",
+                self.dimensions
+                    .iter()
+                    .map(|(name, count)| format!("{}: {}", name, count))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        ];
+        if let Some((first, last)) = &self.dates {
+            let dates = if first == last {
+                first.replace(':', "-")
+            } else {
+                format!("{} to {}", first.replace(':', "-"), last.replace(':', "-"))
+            };
+            rows.push(("Dates", dates));
+        }
+        if !self.cameras.is_empty() {
+            rows.push(("Cameras", list(&self.cameras)));
+        }
+        rows
+    }
+}
+
+/// The entries of `counts`, most common first, and in name order when they are as common.
+fn most_first(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+    counts
+}
+
+/// `bytes` in the largest unit that keeps it at least 1.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["bytes", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000. && unit + 1 < UNITS.len() {
+        size /= 1000.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// The UTC date of `time` in the EXIF style, `YYYY:MM:DD`, so it sorts with EXIF dates.
+fn exif_date(time: SystemTime) -> Option<String> {
+    let days = time.duration_since(UNIX_EPOCH).ok()?.as_secs() / 86_400;
+    // From Howard Hinnant's `civil_from_days`, for days since 1970 only.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    Some(format!("{:04}:{:02}:{:02}", year, month, day))
+}
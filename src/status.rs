@@ -0,0 +1,124 @@
+//! The parts of the status bar that describe the image being shown: its file, its size, where it
+//! is in the folder, and how far it is zoomed, which can be typed in.
+use druid::{
+    text::{Formatter, Selection, Validation, ValidationError},
+    widget::{prelude::*, Controller, TextBox, ValueTextBox},
+    ArcStr, Data, WidgetExt,
+};
+use std::{fs, io, path::Path};
+
+use crate::{
+    cli::parse_scale,
+    profile::InitialZoom,
+    stats::format_bytes,
+    widgets::{ReportFocus, SET_SCALE},
+};
+
+/// The file the current image was read from.
+#[derive(Debug, Clone, Data)]
+pub struct FileStatus {
+    pub name: ArcStr,
+    /// The size of the file, if we could read it.
+    pub bytes: Option<u64>,
+}
+
+impl FileStatus {
+    pub fn for_path(path: &Path) -> Self {
+        Self {
+            name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .as_ref()
+                .into(),
+            bytes: fs::metadata(path).ok().map(|meta| meta.len()),
+        }
+    }
+}
+
+/// The file's name and size, the image's dimensions, and its position in the folder, like
+/// "photo.jpg  4000 × 3000  2.4 MB  14/230".
+pub fn summary(
+    file: Option<&FileStatus>,
+    size: Option<(usize, usize)>,
+    position: Option<(usize, usize)>,
+) -> String {
+    let mut parts = vec![];
+    if let Some(file) = file {
+        parts.push(file.name.to_string());
+    }
+    if let Some((width, height)) = size {
+        parts.push(format!("{} × {}", width, height));
+    }
+    if let Some(bytes) = file.and_then(|file| file.bytes) {
+        parts.push(format_bytes(bytes));
+    }
+    if let Some((index, len)) = position {
+        parts.push(format!("{}/{}", index + 1, len));
+    }
+    parts.join("  ")
+}
+
+/// The zoom as a percentage. Clicking it lets the user type a scale, like `150%` or `fit`, which is
+/// applied when they press enter.
+pub fn zoom_entry() -> impl Widget<f64> {
+    ValueTextBox::new(TextBox::new(), ZoomFormatter)
+        .controller(ApplyZoom)
+        .controller(ReportFocus)
+        .fix_width(64.)
+}
+
+/// Shows the zoom as a percentage, and reads it back the way `--scale` does, except that a bare
+/// number is a percentage too. "Fit" is read as 0, which `SET_SCALE` takes to mean fit.
+struct ZoomFormatter;
+
+impl Formatter<f64> for ZoomFormatter {
+    fn format(&self, value: &f64) -> String {
+        format!("{:.0}%", value * 100.)
+    }
+
+    fn validate_partial_input(&self, _input: &str, _sel: &Selection) -> Validation {
+        Validation::success()
+    }
+
+    fn value(&self, input: &str) -> Result<f64, ValidationError> {
+        let input = input.trim();
+        let scale = if input.parse::<f64>().is_ok() {
+            parse_scale(&format!("{}%", input))
+        } else {
+            parse_scale(input)
+        };
+        match scale {
+            Ok(InitialZoom::Scale(scale)) => Ok(scale),
+            Ok(InitialZoom::Fit) => Ok(0.),
+            Ok(_) => Err(invalid(
+                "only fit or a scale like 150% can be typed here".into(),
+            )),
+            Err(e) => Err(invalid(e)),
+        }
+    }
+}
+
+fn invalid(msg: String) -> ValidationError {
+    ValidationError::new(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Zooms the image to the scale typed into the zoom box.
+struct ApplyZoom;
+
+impl<W: Widget<f64>> Controller<f64, W> for ApplyZoom {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut f64,
+        env: &Env,
+    ) {
+        let before = *data;
+        child.event(ctx, event, data, env);
+        if !before.same(data) {
+            ctx.submit_command(SET_SCALE.with(*data));
+        }
+    }
+}
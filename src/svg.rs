@@ -0,0 +1,71 @@
+//! SVG images, which we rasterize at whatever scale they are shown at.
+//!
+//! The parsed tree can't be sent between threads, so the loader only keeps the source, and the
+//! widget parses it again when it first draws.
+use druid::{kurbo::Rect, piet::ImageFormat, ImageBuf, Size};
+use std::{error::Error, fmt, fs, path::Path};
+
+type SvgResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// The source of an SVG image.
+pub struct SvgImage {
+    data: Vec<u8>,
+    /// The size of the document, in pixels at 100%.
+    pub size: Size,
+}
+
+impl fmt::Debug for SvgImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SvgImage")
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl SvgImage {
+    pub fn parse(&self) -> SvgResult<usvg::Tree> {
+        let opt = usvg::Options::default();
+        Ok(usvg::Tree::from_data(&self.data, &opt.to_ref())?)
+    }
+}
+
+pub fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("svg"))
+}
+
+/// Read the SVG at `path`, and render it at 100% for anything that needs pixels.
+pub fn open(path: &Path) -> SvgResult<(SvgImage, ImageBuf)> {
+    let data = fs::read(path)?;
+    let mut svg = SvgImage {
+        data,
+        size: Size::ZERO,
+    };
+    let tree = svg.parse()?;
+    let size = tree.svg_node().size;
+    svg.size = Size::new(size.width(), size.height());
+    let image = render(&tree, svg.size.to_rect(), 1.).ok_or("SVG has no area")?;
+    Ok((svg, image))
+}
+
+/// Render the part of the document in `region` (in document pixels) at `scale`.
+pub fn render(tree: &usvg::Tree, region: Rect, scale: f64) -> Option<ImageBuf> {
+    let width = (region.width() * scale).ceil() as u32;
+    let height = (region.height() * scale).ceil() as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)?;
+    let transform = tiny_skia::Transform::from_row(
+        scale as f32,
+        0.,
+        0.,
+        scale as f32,
+        (-region.x0 * scale) as f32,
+        (-region.y0 * scale) as f32,
+    );
+    resvg::render(tree, usvg::FitTo::Original, transform, pixmap.as_mut())?;
+    Some(ImageBuf::from_raw(
+        pixmap.take(),
+        ImageFormat::RgbaPremul,
+        width as usize,
+        height as usize,
+    ))
+}
@@ -0,0 +1,77 @@
+//! Dark and light colours for everything around the image. The colours are kept in the `Env`, on
+//! top of druid's own theme keys, so switching theme only means changing `AppData::theme`.
+use druid::{theme, Color, Data, Env, Key, Selector};
+
+use crate::shell;
+
+/// Switch between the dark and light themes.
+pub const TOGGLE_THEME: Selector = Selector::new("image-viewer.toggle-theme");
+
+/// Behind the ribbon and the status bar.
+pub const CHROME: Key<Color> = Key::new("image-viewer.theme.chrome");
+/// The ribbon's icons.
+pub const ICON: Key<Color> = Key::new("image-viewer.theme.icon");
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Data)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::Dark
+    }
+}
+
+impl Theme {
+    /// The theme the OS is set to prefer, or dark if we can't tell.
+    pub fn detect() -> Self {
+        match shell::prefers_dark() {
+            Some(false) => Theme::Light,
+            _ => Theme::Dark,
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            Theme::Dark => Theme::Light,
+            Theme::Light => Theme::Dark,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+
+    /// Set the colours for this theme in `env`, which should start as druid's default theme.
+    pub fn apply(self, env: &mut Env) {
+        match self {
+            // Druid's theme is already dark, so only our own keys need setting.
+            Theme::Dark => {
+                let window = env.get(theme::WINDOW_BACKGROUND_COLOR);
+                env.set(CHROME, window);
+                env.set(ICON, Color::WHITE);
+            }
+            Theme::Light => {
+                env.set(theme::WINDOW_BACKGROUND_COLOR, Color::grey8(0xf4));
+                env.set(CHROME, Color::grey8(0xe4));
+                env.set(ICON, Color::grey8(0x30));
+                env.set(theme::TEXT_COLOR, Color::grey8(0x20));
+                env.set(theme::PLACEHOLDER_COLOR, Color::grey8(0x80));
+                env.set(theme::BACKGROUND_LIGHT, Color::WHITE);
+                env.set(theme::BACKGROUND_DARK, Color::grey8(0xe8));
+                env.set(theme::FOREGROUND_LIGHT, Color::grey8(0x90));
+                env.set(theme::FOREGROUND_DARK, Color::grey8(0x70));
+                env.set(theme::BUTTON_LIGHT, Color::grey8(0xfa));
+                env.set(theme::BUTTON_DARK, Color::grey8(0xd0));
+                env.set(theme::BORDER_LIGHT, Color::grey8(0xc8));
+                env.set(theme::BORDER_DARK, Color::grey8(0xa8));
+                env.set(theme::CURSOR_COLOR, Color::BLACK);
+            }
+        }
+    }
+}
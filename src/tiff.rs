@@ -0,0 +1,145 @@
+//! Reading TIFF structures, which EXIF metadata and most camera RAW formats are built from.
+use std::convert::TryInto;
+
+/// A TIFF file (or EXIF block), borrowed.
+pub struct Tiff<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+/// One tag of an IFD.
+#[derive(Debug, Copy, Clone)]
+pub struct Entry {
+    pub tag: u16,
+    kind: u16,
+    count: usize,
+    /// Where the value starts. Values that fit in 4 bytes are stored in the entry itself.
+    data: usize,
+}
+
+impl<'a> Tiff<'a> {
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        let little_endian = match bytes.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(Self {
+            bytes,
+            little_endian,
+        })
+    }
+
+    /// The offset of the first IFD.
+    pub fn first_ifd(&self) -> Option<usize> {
+        self.u32(4).map(|offset| offset as usize)
+    }
+
+    /// Read the entries of the IFD at `pos`, and the offset of the next IFD, if there is one.
+    pub fn ifd(&self, pos: usize) -> Option<(Vec<Entry>, Option<usize>)> {
+        let count = self.u16(pos)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for idx in 0..count {
+            let entry = pos + 2 + idx * 12;
+            let kind = self.u16(entry + 2)?;
+            let count = self.u32(entry + 4)? as usize;
+            let data = if count.saturating_mul(type_size(kind)) <= 4 {
+                entry + 8
+            } else {
+                self.u32(entry + 8)? as usize
+            };
+            entries.push(Entry {
+                tag: self.u16(entry)?,
+                kind,
+                count,
+                data,
+            });
+        }
+        let next = self
+            .u32(pos + 2 + count * 12)
+            .filter(|&next| next != 0)
+            .map(|next| next as usize);
+        Some((entries, next))
+    }
+
+    /// The values of an integer (`BYTE`, `SHORT`, `LONG` or `IFD`) entry. At most 1024 are read.
+    pub fn ints(&self, entry: &Entry) -> Vec<u32> {
+        (0..entry.count.min(1024))
+            .map_while(|idx| match entry.kind {
+                1 => self.bytes.get(entry.data + idx).map(|&b| b as u32),
+                3 => self.u16(entry.data + idx * 2).map(u32::from),
+                4 | 13 => self.u32(entry.data + idx * 4),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The first value of an integer entry.
+    pub fn int(&self, entry: &Entry) -> Option<u32> {
+        self.ints(entry).first().copied()
+    }
+
+    /// The first value of a `RATIONAL` or `SRATIONAL` entry.
+    pub fn rational(&self, entry: &Entry) -> Option<f64> {
+        let (num, denom) = (self.u32(entry.data)?, self.u32(entry.data + 4)?);
+        let (num, denom) = match entry.kind {
+            5 => (num as f64, denom as f64),
+            10 => (num as i32 as f64, denom as i32 as f64),
+            _ => return None,
+        };
+        if denom == 0. {
+            None
+        } else {
+            Some(num / denom)
+        }
+    }
+
+    /// The value of an `ASCII` entry, without its terminator or any padding.
+    pub fn ascii(&self, entry: &Entry) -> Option<String> {
+        if entry.kind != 2 {
+            return None;
+        }
+        let bytes = self
+            .bytes
+            .get(entry.data..entry.data.checked_add(entry.count)?)?;
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end_matches('\0').trim();
+        if text.is_empty() {
+            None
+        } else {
+            Some(text.to_owned())
+        }
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let b = self.bytes.get(pos..pos.checked_add(2)?)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let b = self.bytes.get(pos..pos.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+}
+
+/// The size in bytes of one value of each field type.
+fn type_size(kind: u16) -> usize {
+    match kind {
+        // SHORT and SSHORT
+        3 | 8 => 2,
+        // LONG, SLONG, FLOAT and IFD
+        4 | 9 | 11 | 13 => 4,
+        // RATIONAL, SRATIONAL and DOUBLE
+        5 | 10 | 12 => 8,
+        // BYTE, ASCII, SBYTE and UNDEFINED
+        _ => 1,
+    }
+}
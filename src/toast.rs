@@ -0,0 +1,242 @@
+//! Short messages that pop up to say something has happened, and go away on their own.
+//!
+//! Anything with a `DelegateCtx`, `EventCtx` or `ExtEventSink` can submit `SHOW_TOAST`. Code that
+//! already has the `AppData` can push to `AppData::toasts` directly.
+use druid::{
+    widget::{prelude::*, Button, Controller, CrossAxisAlignment, Flex, Label, ViewSwitcher},
+    ArcStr, Color, Data, Lens, Selector, TimerToken, WidgetExt,
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Show a toast.
+pub const SHOW_TOAST: Selector<Toast> = Selector::new("image-viewer.show-toast");
+
+/// How many toasts are on screen at once. Older ones are dropped to make room.
+const MAX_SHOWN: usize = 4;
+/// How many toasts the history keeps.
+const MAX_HISTORY: usize = 100;
+/// Errors stay up this many times longer than other toasts, as there is more to read.
+const ERROR_FACTOR: u32 = 3;
+
+#[derive(Debug, Clone, Data)]
+pub struct Toast {
+    /// Set when the toast is shown, so it can be found again to dismiss it.
+    id: u64,
+    pub text: ArcStr,
+    /// More about an error, shown in the history.
+    pub details: Option<ArcStr>,
+    pub error: bool,
+    /// How long to show the toast for, if not the default.
+    #[data(same_fn = "PartialEq::eq")]
+    pub duration: Option<Duration>,
+}
+
+impl Toast {
+    pub fn info(text: impl Into<ArcStr>) -> Self {
+        Self {
+            id: 0,
+            text: text.into(),
+            details: None,
+            error: false,
+            duration: None,
+        }
+    }
+
+    pub fn error(text: impl Into<ArcStr>, details: impl Into<ArcStr>) -> Self {
+        Self {
+            details: Some(details.into()),
+            error: true,
+            ..Self::info(text)
+        }
+    }
+
+    /// Builder-style method to show the toast for `duration`.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Data, Lens)]
+pub struct Toasts {
+    /// The toasts on screen, oldest first.
+    pub shown: Arc<Vec<Toast>>,
+    /// The toasts shown so far, newest first.
+    pub history: Arc<Vec<Toast>>,
+    /// Whether the history panel is open.
+    pub show_history: bool,
+    /// How long to show toasts that don't say.
+    #[data(same_fn = "PartialEq::eq")]
+    pub duration: Duration,
+    next_id: u64,
+}
+
+impl Toasts {
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            shown: Arc::new(vec![]),
+            history: Arc::new(vec![]),
+            show_history: false,
+            duration,
+            next_id: 1,
+        }
+    }
+
+    pub fn push(&mut self, mut toast: Toast) {
+        if toast.error {
+            log::error!("{}: {}", toast.text, toast.details.as_deref().unwrap_or(""));
+        }
+        toast.id = self.next_id;
+        self.next_id += 1;
+        let history = Arc::make_mut(&mut self.history);
+        history.insert(0, toast.clone());
+        history.truncate(MAX_HISTORY);
+        let shown = Arc::make_mut(&mut self.shown);
+        shown.push(toast);
+        if shown.len() > MAX_SHOWN {
+            shown.remove(0);
+        }
+    }
+
+    pub fn dismiss(&mut self, id: u64) {
+        if self.shown.iter().any(|toast| toast.id == id) {
+            Arc::make_mut(&mut self.shown).retain(|toast| toast.id != id);
+        }
+    }
+
+    /// How long `toast` stays up.
+    fn duration(&self, toast: &Toast) -> Duration {
+        toast.duration.unwrap_or(if toast.error {
+            self.duration * ERROR_FACTOR
+        } else {
+            self.duration
+        })
+    }
+}
+
+/// The toasts on screen, stacked oldest at the top.
+pub fn stack() -> impl Widget<Toasts> {
+    ViewSwitcher::new(
+        |data: &Toasts, _| data.shown.clone(),
+        |shown, _, _| {
+            let mut column = Flex::column().cross_axis_alignment(CrossAxisAlignment::End);
+            for toast in shown.iter() {
+                column.add_child(bubble(toast));
+                column.add_spacer(4.);
+            }
+            Box::new(column)
+        },
+    )
+    .controller(Expire::default())
+}
+
+fn bubble(toast: &Toast) -> impl Widget<Toasts> {
+    let id = toast.id;
+    let mut row = Flex::row().with_child(Label::new(toast.text.clone()).with_text_color(
+        if toast.error {
+            Color::rgb8(0xff, 0x80, 0x80)
+        } else {
+            Color::WHITE
+        },
+    ));
+    if toast.details.is_some() {
+        row.add_spacer(8.);
+        row.add_child(
+            Button::new("Details").on_click(|_, data: &mut Toasts, _| data.show_history = true),
+        );
+    }
+    row.with_spacer(8.)
+        .with_child(Button::new("×").on_click(move |_, data: &mut Toasts, _| data.dismiss(id)))
+        .padding((8., 4.))
+        .background(Color::rgba8(0x20, 0x20, 0x20, 0xe0))
+}
+
+/// Every toast shown so far, with the details of errors.
+pub fn history_panel() -> impl Widget<Toasts> {
+    let entries = ViewSwitcher::new(
+        |data: &Toasts, _| data.history.clone(),
+        |history, _, _| {
+            let mut column = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+            if history.is_empty() {
+                column.add_child(Label::new("Nothing yet"));
+            }
+            for toast in history.iter() {
+                let label = Label::new(toast.text.clone());
+                column.add_child(if toast.error {
+                    label.with_text_color(Color::rgb8(0xff, 0x80, 0x80))
+                } else {
+                    label
+                });
+                if let Some(details) = &toast.details {
+                    column.add_child(
+                        Label::new(details.clone())
+                            .with_text_size(11.)
+                            .with_text_color(Color::grey8(0xa0)),
+                    );
+                }
+                column.add_spacer(4.);
+            }
+            Box::new(column)
+        },
+    );
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Messages"))
+                .with_flex_spacer(1.)
+                .with_child(
+                    Button::new("×").on_click(|_, data: &mut Toasts, _| data.show_history = false),
+                ),
+        )
+        .with_spacer(4.)
+        .with_child(entries)
+        .padding(4.)
+}
+
+/// Dismisses each toast once it has been up for its duration.
+#[derive(Default)]
+struct Expire {
+    /// The toast each timer is for.
+    timers: HashMap<TimerToken, u64>,
+}
+
+impl<W: Widget<Toasts>> Controller<Toasts, W> for Expire {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut Toasts,
+        env: &Env,
+    ) {
+        if let Event::Timer(token) = event {
+            if let Some(id) = self.timers.remove(token) {
+                data.dismiss(id);
+                ctx.set_handled();
+                return;
+            }
+        }
+        child.event(ctx, event, data, env)
+    }
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &Toasts,
+        data: &Toasts,
+        env: &Env,
+    ) {
+        for toast in data.shown.iter() {
+            if !self.timers.values().any(|&id| id == toast.id) {
+                let token = ctx.request_timer(data.duration(toast));
+                self.timers.insert(token, toast.id);
+            }
+        }
+        // Forget the timers of toasts that were dismissed by hand.
+        self.timers
+            .retain(|_, id| data.shown.iter().any(|toast| toast.id == *id));
+        child.update(ctx, old_data, data, env)
+    }
+}
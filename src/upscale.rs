@@ -0,0 +1,118 @@
+//! Making images bigger with a tool that does it better than resampling, such as an AI model.
+//!
+//! The viewer doesn't do the upscaling itself. An `Upscaler` is handed the file and writes a new
+//! one, which is kept beside the original so the next request for it is instant. The only
+//! implementation runs a program the user names on the command line, for example
+//! `--upscaler realesrgan-ncnn-vulkan`.
+use druid::Selector;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Upscale the current image and show the result.
+pub const UPSCALE: Selector = Selector::new("image-viewer.upscale");
+
+/// The arguments `ExternalUpscaler` passes if the user doesn't give any. These suit the
+/// Real-ESRGAN ncnn builds.
+pub const DEFAULT_ARGS: &str = "-i {input} -o {output} -s {scale}";
+
+pub trait Upscaler: Send + Sync {
+    /// Write `input`, `scale` times bigger, to `output` as a PNG. This can take a while, so
+    /// shouldn't be called on the UI thread.
+    fn upscale(
+        &self,
+        input: &Path,
+        output: &Path,
+        scale: u32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// An upscaler that runs a program.
+pub struct ExternalUpscaler {
+    program: PathBuf,
+    /// The arguments, in which `{input}`, `{output}` and `{scale}` are replaced.
+    args: Vec<String>,
+}
+
+impl ExternalUpscaler {
+    /// Run `program` with `args`, split on whitespace.
+    pub fn new(program: PathBuf, args: &str) -> Self {
+        Self {
+            program,
+            args: args.split_whitespace().map(str::to_owned).collect(),
+        }
+    }
+}
+
+impl Upscaler for ExternalUpscaler {
+    fn upscale(
+        &self,
+        input: &Path,
+        output: &Path,
+        scale: u32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut cmd = Command::new(&self.program);
+        for arg in &self.args {
+            match arg.as_str() {
+                // Paths are passed as they are, in case they aren't UTF-8.
+                "{input}" => cmd.arg(input),
+                "{output}" => cmd.arg(output),
+                arg => cmd.arg(arg.replace("{scale}", &scale.to_string())),
+            };
+        }
+        log::debug!("running {:?}", cmd);
+        let out = cmd
+            .output()
+            .map_err(|e| format!("could not run {}: {}", self.program.display(), e))?;
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            return Err(format!(
+                "{} failed ({}): {}",
+                self.program.display(),
+                out.status,
+                stderr.trim()
+            )
+            .into());
+        }
+        if !output.is_file() {
+            return Err(format!(
+                "{} didn't write {}",
+                self.program.display(),
+                output.display()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Where the upscaled copy of `original` is kept: beside it, as `photo.upscaled-4x.png`.
+pub fn cached_path(original: &Path, scale: u32) -> PathBuf {
+    let stem = original.file_stem().unwrap_or(original.as_os_str());
+    original.with_file_name(format!(
+        "{}.upscaled-{}x.png",
+        stem.to_string_lossy(),
+        scale
+    ))
+}
+
+/// Upscale `original`, unless there is already a copy newer than it, and return the copy's path.
+pub fn upscale_cached(
+    upscaler: &dyn Upscaler,
+    original: &Path,
+    scale: u32,
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let cached = cached_path(original, scale);
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    if let (Some(copy), Some(source)) = (modified(&cached), modified(original)) {
+        if copy >= source {
+            log::debug!("using upscaled copy {}", cached.display());
+            return Ok(cached);
+        }
+    }
+    upscaler.upscale(original, &cached, scale)?;
+    Ok(cached)
+}
@@ -0,0 +1,105 @@
+//! Finding an earlier version of an image to compare it with: the last committed version if the
+//! image is in a git repository, or else a backup beside it, like `photo.png.bak`.
+use druid::Selector;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// Blink the current image against its previous version.
+pub const COMPARE_WITH_PREVIOUS: Selector = Selector::new("image-viewer.compare-with-previous");
+
+/// The earlier version of `path`, as a file that can be opened like any other. Versions from git
+/// are written to the temporary folder, named after the commit, like `photo@1a2b3c4.png`.
+pub fn previous_version(path: &Path) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    match from_git(path) {
+        Ok(Some(old)) => return Ok(old),
+        Ok(None) => (),
+        Err(e) => log::debug!("no previous version of {} in git: {}", path.display(), e),
+    }
+    backup(path).ok_or_else(|| {
+        format!(
+            "{} isn't in a git repository with an earlier version, and has no backup",
+            path.display()
+        )
+        .into()
+    })
+}
+
+/// The version of `path` in git before the one we have: the committed version if the file has
+/// changed since, or else the version before the last commit that changed it.
+fn from_git(path: &Path) -> Result<Option<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let dir = dir.unwrap_or_else(|| Path::new("."));
+    let name = path.file_name().ok_or("no file name")?;
+    let git = |args: &[&str]| {
+        let mut cmd = Command::new("git");
+        cmd.arg("-C").arg(dir).args(args);
+        cmd
+    };
+    let changed = !git(&["diff", "--quiet", "HEAD", "--"])
+        .arg(name)
+        .stderr(Stdio::null())
+        .status()?
+        .success();
+    let out = git(&["log", "-n", "2", "--format=%h", "--"])
+        .arg(name)
+        .output()?;
+    if !out.status.success() {
+        return Err(failed(&out));
+    }
+    let log = String::from_utf8(out.stdout)?;
+    let mut commits = log.lines();
+    let rev = if changed {
+        commits.next()
+    } else {
+        commits.nth(1)
+    };
+    let rev = match rev {
+        Some(rev) => rev,
+        None => return Ok(None),
+    };
+    let blob = git(&["show", &format!("{}:./{}", rev, name.to_string_lossy())]).output()?;
+    if !blob.status.success() {
+        return Err(failed(&blob));
+    }
+    let dest_dir = std::env::temp_dir().join("image-viewer");
+    fs::create_dir_all(&dest_dir)?;
+    let stem = path.file_stem().unwrap_or(name).to_string_lossy();
+    // Keep the extension, which is how we tell what format the file is in.
+    let dest = match path.extension() {
+        Some(ext) => dest_dir.join(format!("{}@{}.{}", stem, rev, ext.to_string_lossy())),
+        None => dest_dir.join(format!("{}@{}", stem, rev)),
+    };
+    fs::write(&dest, blob.stdout)?;
+    Ok(Some(dest))
+}
+
+/// What git said went wrong.
+fn failed(out: &Output) -> Box<dyn Error + Send + Sync> {
+    String::from_utf8_lossy(&out.stderr)
+        .trim()
+        .to_owned()
+        .into()
+}
+
+/// A backup of `path` beside it, trying the ways editors name them in turn.
+fn backup(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let stem = path.file_stem()?.to_string_lossy().into_owned();
+    let ext = path
+        .extension()
+        .map_or(String::new(), |ext| format!(".{}", ext.to_string_lossy()));
+    [
+        format!("{}.bak", name),
+        format!("{}~", name),
+        format!("{}.orig", name),
+        format!("{}.bak{}", stem, ext),
+        format!("{}.orig{}", stem, ext),
+    ]
+    .iter()
+    .map(|backup| path.with_file_name(backup))
+    .find(|backup| backup.is_file())
+}
@@ -0,0 +1,131 @@
+//! Exporting the part of the image in view at the sizes of common screens, for use as wallpaper.
+//!
+//! Each size is cut from the middle of the view at that size's shape, as large as the view
+//! allows, then scaled down to fit exactly. Sizes bigger than the cut would need scaling up, which
+//! only makes a blurry wallpaper, so they are left out.
+use druid::{kurbo::Rect, piet::ImageFormat, ImageBuf, Selector};
+use image::{imageops::FilterType, RgbaImage};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    encode::{self, EncodeOptions},
+    exif, pixel_ops,
+};
+
+/// Export the view at every size in `SIZES`. The `ZoomImage` answers with `VIEW_TO_EXPORT`.
+pub const EXPORT_WALLPAPERS: Selector = Selector::new("image-viewer.export-wallpapers");
+/// The part of the image in view, for exporting.
+pub const VIEW_TO_EXPORT: Selector<View> = Selector::new("image-viewer.view-to-export");
+
+/// Common screen sizes, in pixels.
+pub const SIZES: &[(u32, u32)] = &[
+    // Desktops and laptops.
+    (3840, 2160),
+    (2560, 1440),
+    (2560, 1600),
+    (1920, 1200),
+    (1920, 1080),
+    (1366, 768),
+    // Phones and tablets, upright.
+    (1440, 3200),
+    (1170, 2532),
+    (1080, 1920),
+    (2048, 2732),
+];
+
+/// What is on screen.
+#[derive(Debug, Copy, Clone)]
+pub struct View {
+    /// How the view turns and flips the image, as an EXIF orientation.
+    pub orientation: u16,
+    /// The part of the image in view, in the coordinates of the image turned by `orientation`.
+    pub area: Rect,
+}
+
+/// What `export` wrote.
+#[derive(Debug)]
+pub struct Exported {
+    pub written: Vec<PathBuf>,
+    /// How many sizes were left out for being bigger than the view.
+    pub skipped: usize,
+}
+
+/// Write a file for each of `SIZES`, named after `original` with the size added, for example
+/// `photo-1920x1080.jpg`. Files with those names are replaced.
+pub fn export(
+    image: &ImageBuf,
+    view: View,
+    original: &Path,
+    options: &EncodeOptions,
+) -> Result<Exported, Box<dyn Error + Send + Sync>> {
+    let image = exif::orient(image.clone(), view.orientation);
+    let area = view.area.intersect(Rect::new(
+        0.,
+        0.,
+        image.width() as f64,
+        image.height() as f64,
+    ));
+    let stem = original
+        .file_stem()
+        .ok_or("the image has no file name")?
+        .to_string_lossy();
+    // Keep the format where we can write it, so a JPEG photo gives JPEG wallpapers.
+    let ext = match original.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ["jpg", "jpeg", "png", "webp"].contains(&&*ext.to_lowercase()) => ext,
+        _ => "png",
+    };
+    // Scaling premultiplied pixels keeps them premultiplied.
+    let format = match image.format() {
+        ImageFormat::RgbaPremul => ImageFormat::RgbaPremul,
+        _ => ImageFormat::RgbaSeparate,
+    };
+    let rgba = RgbaImage::from_raw(
+        image.width() as u32,
+        image.height() as u32,
+        pixel_ops::to_rgba(&image).into_owned(),
+    )
+    .expect("to_rgba gives 4 bytes a pixel");
+    let mut exported = Exported {
+        written: vec![],
+        skipped: 0,
+    };
+    for &(width, height) in SIZES {
+        let cut = cut(area, width as f64 / height as f64);
+        if cut.width() < width as f64 || cut.height() < height as f64 {
+            exported.skipped += 1;
+            continue;
+        }
+        let scaled = scale(&rgba, cut, width, height);
+        let scaled = ImageBuf::from_raw(scaled.into_raw(), format, width as usize, height as usize);
+        let dest = original.with_file_name(format!("{}-{}x{}.{}", stem, width, height, ext));
+        encode::save(&scaled, &dest, options)?;
+        exported.written.push(dest);
+    }
+    Ok(exported)
+}
+
+/// The largest rectangle `aspect` times wider than it is high that fits in the middle of `area`,
+/// rounded to whole pixels.
+fn cut(area: Rect, aspect: f64) -> Rect {
+    let (width, height) = if area.width() / area.height() > aspect {
+        (area.height() * aspect, area.height())
+    } else {
+        (area.width(), area.width() / aspect)
+    };
+    Rect::from_center_size(area.center(), (width, height)).round()
+}
+
+/// The `cut` of `image`, scaled to `width` by `height`.
+fn scale(image: &RgbaImage, cut: Rect, width: u32, height: u32) -> RgbaImage {
+    let cropped = image::imageops::crop_imm(
+        image,
+        cut.x0 as u32,
+        cut.y0 as u32,
+        cut.width() as u32,
+        cut.height() as u32,
+    );
+    image::imageops::resize(&cropped, width, height, FilterType::Lanczos3)
+}
@@ -1,45 +1,395 @@
 use druid::{
-    kurbo::{Affine, Point, TranslateScale, Vec2},
-    piet::{Color, Image, InterpolationMode, Piet, PietImage},
+    kurbo::{Affine, Point, Rect, Shape, Vec2},
+    piet::{
+        Color, FontFamily, ImageFormat, InterpolationMode, Piet, PietImage, Text, TextLayout,
+        TextLayoutBuilder,
+    },
     scroll_component::ScrollComponent,
-    widget::prelude::*,
-    Command, Data, ImageBuf, MouseButton, MouseEvent, RenderContext, Selector,
+    widget::{prelude::*, Controller, Viewport},
+    Application, Command, Cursor, Data, ImageBuf, KeyOrValue, Lens, MouseButton, MouseEvent,
+    RenderContext, Scale, Selector, WindowState,
 };
 use druid_material_icons::IconPaths;
-use std::{rc::Rc, sync::Arc};
+use std::{collections::HashMap, mem, path::PathBuf, rc::Rc, sync::Arc, time::Duration};
+
+use crate::{
+    analysis,
+    cache::ImageCache,
+    color::ColorSpace,
+    decode::AnimatedImage,
+    dicom::{DicomImage, Window},
+    exif::Exif,
+    expr::Expr,
+    fits::{FitsImage, Stretch},
+    pixel_ops,
+    profile::{InitialZoom, ProfileKind},
+    svg::{self, SvgImage},
+    toast::{Toast, SHOW_TOAST},
+    wallpaper::{View, EXPORT_WALLPAPERS, VIEW_TO_EXPORT},
+};
 
 /// The amount to scale scrolls by
 const SCROLL_TWEAK: f64 = 0.5;
-const MIN_SCALE: f64 = 0.2; // 20%
-const MAX_SCALE: f64 = 15.0; // 1_500%
-const TARGET_ANIM_LEN: f64 = 160.;
+/// The default zoom limits, which the config file can change.
+pub const MIN_SCALE: f64 = 0.2; // 20%
+pub const MAX_SCALE: f64 = 15.0; // 1_500%
+/// How long zooming and panning animate for by default, in milliseconds.
+pub const TARGET_ANIM_LEN: f64 = 160.;
+/// The size of the squares of the checkerboard behind transparent images, in display points.
+const CHECKER_SIZE: f64 = 8.;
+/// The greys of the checkerboard. Mid greys, so neither black nor white parts of the image
+/// disappear into it.
+const CHECKER_LIGHT: u8 = 0x99;
+const CHECKER_DARK: u8 = 0x66;
+/// Only turn an image to fit the window when that makes it this much bigger, so images that are
+/// nearly square stay the right way up.
+const AUTO_ROTATE_GAIN: f64 = 1.2;
+/// How close to an edge of the crop selection, in widget coords, the mouse must be to grab it.
+const CROP_GRAB_DISTANCE: f64 = 8.;
+/// How far the pixel inspector's readout is from the mouse.
+const INSPECTOR_OFFSET: f64 = 16.;
+/// The space around and between the parts of the readout.
+const INSPECTOR_PADDING: f64 = 6.;
+/// The size of the colour sample in the readout.
+const INSPECTOR_SWATCH: f64 = 28.;
+/// Don't make mip levels smaller than this on their shortest side.
+const MIN_MIP_SIZE: usize = 16;
+/// Images are uploaded in tiles this big, so we only upload what is on screen, and very large
+/// images aren't refused by the GPU.
+const TILE_SIZE: usize = 512;
+/// Once we have more tiles than this (about 256MiB), drop any that weren't drawn last paint.
+const MAX_TILES: usize = 256;
+/// How much memory to keep the mips of recently shown images in, in bytes.
+const DEFAULT_TEXTURE_BUDGET: usize = 256 << 20;
 
 /// Set the zoom to a particular scale.
 pub const SET_SCALE: Selector<f64> = Selector::new("image-viewer.set-scale");
 /// Change the zoom by a factor (<1. is shrink, >1 is grow)
 pub const ZOOM: Selector<f64> = Selector::new("image-viewer.zoom");
-/// This widget will report changes to scale or offset.
-pub const NOTIFY_TRANSFORM: Selector<TranslateScale> =
-    Selector::new("image-viewer.notify-transform");
+/// Zoom in on the most detailed part of the image, to check its focus.
+pub const ZOOM_TO_SUBJECT: Selector = Selector::new("image-viewer.zoom-to-subject");
+/// Turn the view a quarter turn clockwise.
+pub const ROTATE_CW: Selector = Selector::new("image-viewer.rotate-cw");
+/// Turn the view a quarter turn anticlockwise.
+pub const ROTATE_CCW: Selector = Selector::new("image-viewer.rotate-ccw");
+/// Mirror the view left to right.
+pub const FLIP_H: Selector = Selector::new("image-viewer.flip-h");
+/// Mirror the view top to bottom.
+pub const FLIP_V: Selector = Selector::new("image-viewer.flip-v");
+/// Crop the image to the selection made in crop mode, and leave crop mode. If a path is given,
+/// the cropped image is saved there too.
+pub const CROP_APPLY: Selector<Option<PathBuf>> = Selector::new("image-viewer.crop-apply");
+/// Crop as much of the image as fits the shape, from the middle, without going into crop mode.
+pub const CROP_TO: Selector<CropAspect> = Selector::new("image-viewer.crop-to");
+/// Sent by the widget once it has cropped the image.
+pub const CROPPED: Selector<Cropped> = Selector::new("image-viewer.cropped");
+/// This widget will report changes to scale, offset or rotation.
+pub const NOTIFY_TRANSFORM: Selector<Affine> = Selector::new("image-viewer.notify-transform");
+/// Enter or leave fullscreen. Sent when the image is double clicked.
+pub const TOGGLE_FULLSCREEN: Selector = Selector::new("image-viewer.toggle-fullscreen");
+/// Whether a text box has the keyboard, so shortcuts should leave its keys alone. Sent by
+/// `ReportFocus`.
+pub const EDITING_TEXT: Selector<bool> = Selector::new("image-viewer.editing-text");
+/// Turn the pixel inspector on or off.
+pub const TOGGLE_INSPECTOR: Selector = Selector::new("image-viewer.toggle-inspector");
+/// Copy the colour of the pixel under the inspector, as hex.
+pub const COPY_COLOR: Selector = Selector::new("image-viewer.copy-color");
+/// Sent by the widget to itself when it has changed the transform outside of `event`, so it can
+/// copy it into the data.
+const SYNC_TRANSFORM: Selector = Selector::new("image-viewer.sync-transform");
+
+/// The data a `ZoomImage` displays.
+///
+/// The transform lives here rather than in the widget so other widgets can read and set it. The
+/// `ZoomImage` will constrain (and animate to) any transform it is given, and write the
+/// constrained value back.
+#[derive(Debug, Clone, Data, Lens)]
+pub struct ViewerState {
+    /// The image, or the first frame if it is animated.
+    pub image: Arc<ImageBuf>,
+    /// Smaller copies of `image`, each half the size of the one before, made when it was loaded.
+    pub mips: Arc<Vec<ImageBuf>>,
+    /// All the frames, if the image is animated.
+    pub animation: Option<Arc<AnimatedImage>>,
+    /// The full depth samples, if the image is DICOM. `image` is rendered with the default
+    /// window, and is only used for its size.
+    pub dicom: Option<Arc<DicomImage>>,
+    /// The window to render `dicom` with. Dragging with the right button changes it.
+    pub window: Window,
+    /// The full depth samples, if the image is FITS. As with `dicom`, `image` is only used for
+    /// its size.
+    pub fits: Option<Arc<FitsImage>>,
+    /// How to render `fits`.
+    pub stretch: Stretch,
+    /// The source, if the image is an SVG. `image` is rendered at 100%, and is drawn while
+    /// we're moving.
+    pub svg: Option<Arc<SvgImage>>,
+    /// The colour space the image was in.
+    pub color_space: ColorSpace,
+    /// The image before it was converted to sRGB, if it needed converting.
+    pub unconverted: Option<Arc<ImageBuf>>,
+    /// The camera settings the image was tagged with.
+    pub exif: Option<Arc<Exif>>,
+    /// Whether to show the converted image. Turning this off helps to diagnose colour problems.
+    pub convert_colors: bool,
+    /// An expression to apply to every pixel before drawing.
+    pub expression: Option<Arc<Expr>>,
+    /// Draw for an e-ink display: dithered 16 level grey, with no animation, and only repainting
+    /// when the image moves.
+    pub eink: bool,
+    /// The profile the rules picked for this image.
+    pub auto_profile: ProfileKind,
+    /// The profile to draw with: `auto_profile`, unless the user has chosen another.
+    pub profile: ProfileKind,
+    /// Maps image coords to widget coords.
+    #[data(same_fn = "same_transform")]
+    pub transform: Affine,
+    /// Whether we are choosing a part of the image to crop to. Dragging with the left button
+    /// changes the selection instead of panning.
+    pub cropping: bool,
+    /// The shape to keep the crop selection.
+    pub crop_aspect: CropAspect,
+    /// Whether the pixels have been changed since the image was loaded, so no longer match the
+    /// file.
+    pub edited: bool,
+    /// Whether the image came from the clipboard or standard input rather than a file.
+    pub pasted: bool,
+    /// Whether to turn newly opened images a quarter turn when they fit the window better that
+    /// way, for example landscape photos on a portrait monitor. Only the view is turned.
+    pub auto_rotate: bool,
+    /// How to zoom newly opened images, if not as the profile says.
+    #[data(same_fn = "PartialEq::eq")]
+    pub initial_zoom: Option<InitialZoom>,
+    /// What to draw behind the image, if not as the profile says.
+    pub background: Option<Color>,
+    /// Keep the zoom and position of the image before, rather than fitting this one, so images
+    /// being compared line up.
+    pub lock_transform: bool,
+    /// Whether hovering over the image shows the pixel under the mouse.
+    pub inspect: bool,
+}
+
+impl ViewerState {
+    pub fn new(image: Arc<ImageBuf>) -> Self {
+        Self {
+            image,
+            mips: Arc::new(vec![]),
+            animation: None,
+            dicom: None,
+            window: Window::default(),
+            fits: None,
+            stretch: Stretch::default(),
+            svg: None,
+            color_space: ColorSpace::default(),
+            unconverted: None,
+            exif: None,
+            convert_colors: true,
+            expression: None,
+            eink: false,
+            auto_profile: ProfileKind::default(),
+            profile: ProfileKind::default(),
+            transform: Affine::scale(1.),
+            cropping: false,
+            crop_aspect: CropAspect::Free,
+            edited: false,
+            pasted: false,
+            auto_rotate: false,
+            initial_zoom: None,
+            background: None,
+            lock_transform: false,
+            inspect: false,
+        }
+    }
+
+    /// The state for showing `image`, cut out of this image, the way this image is shown.
+    fn cropped(&self, image: Arc<ImageBuf>) -> Self {
+        Self {
+            color_space: self.color_space.clone(),
+            exif: self.exif.clone(),
+            expression: self.expression.clone(),
+            eink: self.eink,
+            auto_profile: self.auto_profile,
+            profile: self.profile,
+            crop_aspect: self.crop_aspect,
+            edited: true,
+            pasted: self.pasted,
+            auto_rotate: self.auto_rotate,
+            initial_zoom: self.initial_zoom,
+            background: self.background.clone(),
+            ..Self::new(image)
+        }
+    }
+
+    /// Roughly how much memory the decoded image takes up.
+    pub fn bytes(&self) -> usize {
+        let image = self.image.raw_pixels().len();
+        let mips: usize = self.mips.iter().map(|mip| mip.raw_pixels().len()).sum();
+        let unconverted = self
+            .unconverted
+            .as_ref()
+            .map_or(0, |u| u.raw_pixels().len());
+        let frames: usize = self.animation.as_ref().map_or(0, |animation| {
+            animation
+                .frames
+                .iter()
+                .map(|frame| frame.raw_pixels().len())
+                .sum()
+        });
+        image + mips + unconverted + frames
+    }
+
+    /// Whether `self` and `other` would be drawn the same, given the same source image.
+    fn same_rendering(&self, other: &Self) -> bool {
+        self.window.same(&other.window)
+            && self.stretch.same(&other.stretch)
+            && self.convert_colors == other.convert_colors
+            && self.expression.same(&other.expression)
+            && self.eink == other.eink
+    }
+}
 
 pub struct ZoomImage {
     /// The transformation to apply to the image for drawing. Maps image coords
     /// to widget coords.
-    trans: TranslateScale,
+    ///
+    /// This is our copy of `ViewerState::transform`, and is always constrained. It only ever
+    /// scales uniformly, translates and turns by quarter turns.
+    trans: Affine,
     /// Whether we are in normal mode, or if there is a drag or animation in progress.
     mode: Mode,
 
-    /// We need a cache for the piet image buffer, because we cannot create it
-    /// until `paint` is called.
-    piet_image: Option<Rc<PietImage>>,
+    /// The image at full size, followed by successively halved copies, built as they are needed.
+    mips: Vec<Mip>,
+    /// Whether to round the image position to whole device pixels when the scale is a whole
+    /// number, so pixel art and screenshots aren't smeared.
+    snap_to_pixels: bool,
+    /// Overlay scrollbars, shown when the image is bigger than the widget.
+    scrollbars: Option<ScrollComponent>,
+    /// Where we are in the animation, if the image is animated.
+    playback: Playback,
+    /// Where a right button drag to change the DICOM window started, and the window then.
+    windowing: Option<(Point, Window)>,
+    /// The parsed SVG, and the last region we rendered of it.
+    svg: SvgCache,
+    /// Counts calls to `paint`, to tell which tiles are still in use.
+    paints: u64,
+    /// Our copy of `ViewerState::eink`, for the methods that don't see the state.
+    eink: bool,
+    /// The mips (and their uploaded tiles) of images we have shown recently, and the state they
+    /// were drawn from, keyed by the address of the image.
+    recent: ImageCache<usize, (ViewerState, Vec<Mip>)>,
     /// Track whether the widget was just created. This is used for initial resize. We can't do
     /// this in WidgetAdded, because we haven't run layout yet.
     fresh: bool,
+    /// How we last fitted the image to the widget, and the transform that gave. If the widget
+    /// changes size while the image is still there, for example because a panel opened beside
+    /// it, we fit it again to the new size.
+    fitted: Option<(InitialZoom, Affine)>,
+    /// The smallest and largest scales the user can zoom to.
+    scale_range: (f64, f64),
+    /// How long zooming and panning animate for, in milliseconds.
+    anim_len: f64,
+    /// How to sample the image, if not as the profile says.
+    interpolation: Option<InterpolationPolicy>,
+    /// What to draw behind images that have transparency.
+    backdrop: Backdrop,
+    /// The checkerboard, one pixel a square, and how many columns and rows it has. It is made
+    /// again when the widget changes size.
+    checkerboard: Option<(usize, usize, PietImage)>,
+    /// Where the mouse is over the widget, for the pixel inspector.
+    pointer: Option<Point>,
 }
 
-impl Widget<Arc<ImageBuf>> for ZoomImage {
-    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Arc<ImageBuf>, _env: &Env) {
+/// What to draw behind the transparent parts of images.
+#[derive(Debug, Clone)]
+pub enum Backdrop {
+    /// Nothing, so the background shows through.
+    None,
+    /// Grey squares, which can't be mistaken for part of the image.
+    Checkerboard,
+    Color(Color),
+}
+
+impl Default for Backdrop {
+    fn default() -> Self {
+        Backdrop::Checkerboard
+    }
+}
+
+impl Widget<ViewerState> for ZoomImage {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, state: &mut ViewerState, env: &Env) {
+        if let Event::Command(cmd) = event {
+            if let Some(save_to) = cmd.get(CROP_APPLY) {
+                self.apply_crop(ctx, state, save_to.clone());
+                return;
+            }
+            if let Some(&aspect) = cmd.get(CROP_TO) {
+                let size = state.image.size();
+                self.mode = Mode::Crop(Crop::new(size, aspect.ratio(size)));
+                self.apply_crop(ctx, state, None);
+                self.mode = Mode::Normal;
+                ctx.request_paint();
+                return;
+            }
+            if cmd.is(EXPORT_WALLPAPERS) {
+                let view = self.view(state.image.size(), ctx.size());
+                ctx.submit_command(VIEW_TO_EXPORT.with(view));
+                return;
+            }
+        }
+        let data = &state.image;
+        self.eink = state.eink;
+        // We stop asking for frames while minimized, so start again once anything happens.
+        if state.animation.is_some() && !state.eink && !self.playback.playing {
+            self.playback.playing = true;
+            ctx.request_anim_frame();
+        }
+        if let Some(dicom) = &state.dicom {
+            if self.window_level(ctx, event, dicom, &mut state.window) {
+                return;
+            }
+        }
+        let scrollbars = self
+            .scrollbars
+            .as_mut()
+            .filter(|_| state.profile.settings().scrollbars);
+        if let Some(scrollbars) = scrollbars {
+            let mut port = viewport(data.size(), ctx.size(), self.trans);
+            scrollbars.event(&mut port, ctx, event, env);
+            if ctx.is_handled() {
+                // The user is dragging a scrollbar, which pans without animating.
+                let bbox = self.trans.transform_rect_bbox(data.size().to_rect());
+                let pan = -port.view_origin.to_vec2() - bbox.origin().to_vec2();
+                self.trans = Affine::translate(pan) * self.trans;
+                self.constrain_transform(data, ctx.size());
+                if !self.is_cropping() {
+                    self.mode = Mode::Normal;
+                }
+                ctx.request_paint();
+                if !trans_approx_eq(state.transform, self.trans) {
+                    state.transform = self.trans;
+                    ctx.submit_command(self.notify_transform());
+                }
+                return;
+            }
+        }
+        if let Event::MouseMove(mouse) = event {
+            self.pointer = Some(mouse.pos);
+            if state.inspect {
+                ctx.set_cursor(&Cursor::Crosshair);
+                ctx.request_paint();
+            } else {
+                // Crop mode sets its own cursor after this.
+                ctx.clear_cursor();
+            }
+        }
+        if let Mode::Crop(crop) = &mut self.mode {
+            if crop.event(ctx, event, self.trans, data.size()) {
+                return;
+            }
+        }
         match event {
+            Event::Command(cmd) if cmd.is(SYNC_TRANSFORM) => (),
             Event::Command(cmd) => {
                 if let Some(&scale) = cmd.get(SET_SCALE) {
                     // TODO figure out how to use widget ids.
@@ -47,7 +397,7 @@ impl Widget<Arc<ImageBuf>> for ZoomImage {
                     // Zoom around the middle of the widget
                     // We non-positive numbers as a niche to mean "fit to window"
                     if scale <= 0. || !scale.is_finite() {
-                        self.zoom_to_fit(data, ctx.size());
+                        self.fit(data, ctx.size(), InitialZoom::Fit);
                     } else {
                         let zoom_point = (ctx.size() * 0.5).to_vec2().to_point();
                         self.zoom_to(data, ctx.size(), scale, zoom_point);
@@ -59,6 +409,30 @@ impl Widget<Arc<ImageBuf>> for ZoomImage {
                     ctx.submit_command(self.notify_transform());
                     //}
                 }
+                let turn = if cmd.is(ROTATE_CW) {
+                    Some(Affine::new([0., 1., -1., 0., 0., 0.]))
+                } else if cmd.is(ROTATE_CCW) {
+                    Some(Affine::new([0., -1., 1., 0., 0., 0.]))
+                } else if cmd.is(FLIP_H) {
+                    Some(Affine::scale_non_uniform(-1., 1.))
+                } else if cmd.is(FLIP_V) {
+                    Some(Affine::scale_non_uniform(1., -1.))
+                } else {
+                    None
+                };
+                if let Some(turn) = turn {
+                    // Turn or mirror around the middle of the widget, so it composes with
+                    // whatever zoom, pan, and turns there already are.
+                    let centre = (ctx.size() * 0.5).to_vec2();
+                    let trans =
+                        Affine::translate(centre) * turn * Affine::translate(-centre) * self.trans;
+                    self.move_to(data, ctx.size(), trans);
+                    ctx.request_paint();
+                    if self.is_animating() {
+                        ctx.request_anim_frame();
+                    }
+                    ctx.submit_command(self.notify_transform());
+                }
                 if let Some(scale_factor) = cmd.get(ZOOM) {
                     // Zoom around the middle of the widget
                     let zoom_point = (ctx.size() * 0.5).to_vec2().to_point();
@@ -70,6 +444,32 @@ impl Widget<Arc<ImageBuf>> for ZoomImage {
                     ctx.submit_command(self.notify_transform());
                     //}
                 }
+                if cmd.is(TOGGLE_INSPECTOR) {
+                    state.inspect = !state.inspect;
+                }
+                if cmd.is(COPY_COLOR) {
+                    match self.inspected(state) {
+                        Some(pixel) => {
+                            let hex = pixel.hex();
+                            Application::global().clipboard().put_string(&hex);
+                            ctx.submit_command(
+                                SHOW_TOAST.with(Toast::info(format!("Copied {}", hex))),
+                            );
+                        }
+                        None => ctx.submit_command(SHOW_TOAST.with(Toast::info(
+                            "Turn on Inspect and point at a pixel to copy its colour",
+                        ))),
+                    }
+                }
+                if cmd.is(ZOOM_TO_SUBJECT) {
+                    let subject = analysis::salient_region(data);
+                    self.zoom_to_area(data, ctx.size(), subject);
+                    ctx.request_paint();
+                    if self.is_animating() {
+                        ctx.request_anim_frame();
+                    }
+                    ctx.submit_command(self.notify_transform());
+                }
             }
             Event::Wheel(MouseEvent {
                 pos, wheel_delta, ..
@@ -82,6 +482,20 @@ impl Widget<Arc<ImageBuf>> for ZoomImage {
                 }
                 ctx.submit_command(self.notify_transform());
             }
+            // Trackpad pinch. We don't get a position, so zoom around the middle.
+            Event::Zoom(delta) => {
+                let zoom_point = (ctx.size() * 0.5).to_vec2().to_point();
+                // `exp` keeps the factor positive, and is ~`1 + delta` for small deltas.
+                self.zoom(data, ctx.size(), delta.exp(), zoom_point);
+                ctx.request_paint();
+                if self.is_animating() {
+                    ctx.request_anim_frame();
+                }
+                ctx.submit_command(self.notify_transform());
+            }
+            Event::MouseDown(mouse) if mouse.button == MouseButton::Left && mouse.count == 2 => {
+                ctx.submit_command(TOGGLE_FULLSCREEN);
+            }
             Event::MouseDown(MouseEvent {
                 buttons,
                 window_pos,
@@ -102,44 +516,81 @@ impl Widget<Arc<ImageBuf>> for ZoomImage {
             }
             Event::MouseMove(MouseEvent { window_pos, .. }) => {
                 self.drag_move(*window_pos, ctx);
+                if self.is_dragging() {
+                    self.show_scrollbars(ctx, env);
+                }
             }
             Event::AnimFrame(time) => {
                 // scale to ms.
                 let time = *time as f64 * 0.000_001;
+                // Nobody can see the animation while we're minimized, so skip straight to the
+                // end rather than keep the event loop busy.
+                let hidden = matches!(ctx.window().get_window_state(), WindowState::Minimized);
                 if let Mode::Anim(anim) = &mut self.mode {
                     anim.update(time);
-                    if anim.is_complete() {
+                    if hidden || anim.is_complete() {
                         self.mode = Mode::Normal;
                     } else {
                         ctx.request_anim_frame();
                     }
                 }
+                if let Some(animation) = state.animation.as_ref().filter(|_| !state.eink) {
+                    if hidden {
+                        self.playback.playing = false;
+                    } else {
+                        self.playback.advance(animation, time);
+                        ctx.request_anim_frame();
+                    }
+                }
                 ctx.request_paint();
             }
             _ => (),
         }
+        // Keep the data up to date with any changes we made.
+        if !trans_approx_eq(state.transform, self.trans) {
+            state.transform = self.trans;
+            self.show_scrollbars(ctx, env);
+        }
     }
 
     fn lifecycle(
         &mut self,
         ctx: &mut LifeCycleCtx,
         event: &LifeCycle,
-        data: &Arc<ImageBuf>,
-        _env: &Env,
+        state: &ViewerState,
+        env: &Env,
     ) {
+        let data = &state.image;
+        if let Some(scrollbars) = self.scrollbars.as_mut() {
+            scrollbars.lifecycle(ctx, event, env);
+        }
         match event {
             LifeCycle::WidgetAdded => {}
+            LifeCycle::HotChanged(false) => {
+                self.pointer = None;
+                if state.inspect {
+                    ctx.request_paint();
+                }
+            }
             LifeCycle::Size(size) => {
                 if self.fresh && !size.is_empty() {
                     self.fresh = false;
-                    // when inserting a new image we should also fit it to the full widget
-                    self.zoom_to_fit(data, *size);
+                    // when inserting a new image we should also fit it to the widget
+                    self.zoom_initial(state, *size);
+                } else if let Some((how, _)) = self
+                    .fitted
+                    .filter(|&(_, fitted)| trans_approx_eq(fitted, self.trans))
+                {
+                    self.fit(data, *size, how);
                 } else {
                     self.constrain_transform(data, *size);
                 }
                 ctx.submit_command(self.notify_transform());
+                ctx.submit_command(Command::new(SYNC_TRANSFORM, (), ctx.widget_id()));
                 // Cancel drag and complete animation.
-                self.mode = Mode::Normal;
+                if !self.is_cropping() {
+                    self.mode = Mode::Normal;
+                }
                 ctx.request_paint();
             }
             _ => (),
@@ -149,22 +600,86 @@ impl Widget<Arc<ImageBuf>> for ZoomImage {
     fn update(
         &mut self,
         ctx: &mut UpdateCtx,
-        old_data: &Arc<ImageBuf>,
-        data: &Arc<ImageBuf>,
+        old_state: &ViewerState,
+        state: &ViewerState,
         _env: &Env,
     ) {
+        let data = &state.image;
+        self.eink = state.eink;
+        if old_state.cropping != state.cropping
+            || (state.cropping && old_state.crop_aspect != state.crop_aspect)
+        {
+            self.mode = if state.cropping {
+                let ratio = state.crop_aspect.ratio(data.size());
+                Mode::Crop(Crop::new(data.size(), ratio))
+            } else {
+                Mode::Normal
+            };
+            ctx.request_paint();
+        }
+        if old_state.inspect != state.inspect {
+            ctx.request_paint();
+        }
+        if !old_state.background.same(&state.background) {
+            ctx.request_paint();
+        }
+        if !old_state.same_rendering(state) {
+            // The image we draw from has changed.
+            self.mips.clear();
+            self.playback.images.clear();
+            self.playback.playing = state.animation.is_some() && !state.eink;
+            if self.playback.playing {
+                ctx.request_anim_frame();
+            }
+            ctx.request_paint();
+        }
         // TODO it would be nice if we could make the image here.
-        if !old_data.same(data) {
-            // invalidate image
-            self.piet_image = None;
-            if !ctx.size().is_empty() {
-                self.zoom_to_fit(data, ctx.size());
+        if !old_state.image.same(data) || !old_state.animation.same(&state.animation) {
+            // invalidate image, keeping the mips in case we come back to it.
+            let mips = mem::take(&mut self.mips);
+            if !mips.is_empty() {
+                let bytes = mips.iter().map(|mip| mip.buf.raw_pixels().len()).sum();
+                self.recent.insert(
+                    image_key(&old_state.image),
+                    (old_state.clone(), mips),
+                    bytes,
+                );
+            }
+            if let Some((drawn_from, mips)) = self.recent.remove(&image_key(data)) {
+                if drawn_from.same_rendering(state) {
+                    self.mips = mips;
+                }
+            }
+            self.svg = SvgCache::default();
+            self.playback = Playback::default();
+            self.playback.playing = state.animation.is_some() && !state.eink;
+            if state.lock_transform {
+                self.constrain_transform(data, ctx.size());
+            } else if !ctx.size().is_empty() {
+                self.zoom_initial(state, ctx.size());
             }
-            ctx.submit_command(self.notify_transform());
+        } else if old_state.profile != state.profile
+            || old_state.auto_rotate != state.auto_rotate
+            || old_state.initial_zoom != state.initial_zoom
+        {
+            // Show the image as the new profile would have.
             ctx.request_paint();
-            if self.is_animating() {
-                ctx.request_anim_frame();
+            if !ctx.size().is_empty() {
+                self.zoom_initial(state, ctx.size());
             }
+        } else if !same_transform(&old_state.transform, &state.transform)
+            && !trans_approx_eq(state.transform, self.trans)
+        {
+            // Someone else has moved the image.
+            self.move_to(data, ctx.size(), state.transform);
+        } else {
+            return;
+        }
+        ctx.submit_command(self.notify_transform());
+        ctx.submit_command(Command::new(SYNC_TRANSFORM, (), ctx.widget_id()));
+        ctx.request_paint();
+        if self.is_animating() || self.playback.playing {
+            ctx.request_anim_frame();
         }
     }
 
@@ -172,25 +687,90 @@ impl Widget<Arc<ImageBuf>> for ZoomImage {
         &mut self,
         _ctx: &mut LayoutCtx,
         bc: &BoxConstraints,
-        _data: &Arc<ImageBuf>,
+        _data: &ViewerState,
         _env: &Env,
     ) -> Size {
         // We take all the space we can.
         bc.max()
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, data: &Arc<ImageBuf>, _env: &Env) {
+    fn paint(&mut self, ctx: &mut PaintCtx, state: &ViewerState, env: &Env) {
+        let data = &state.image;
         let widget_area = ctx.size().to_rect();
         ctx.clip(widget_area);
+        let profile = state.profile.settings();
+        if let Some(background) = state.background.as_ref().or(profile.background.as_ref()) {
+            ctx.fill(widget_area, background);
+        }
 
-        let trans = self.draw_transform();
-        let image = self.image(data, ctx);
+        let mut trans = self.draw_transform();
+        // Only snap when still, otherwise slow movement would look jerky.
+        if self.snap_to_pixels && self.is_still() {
+            trans = snap_to_device_pixels(trans, ctx.scale());
+        }
+        if matches!(
+            data.format(),
+            ImageFormat::RgbaSeparate | ImageFormat::RgbaPremul
+        ) {
+            let area = trans.transform_rect_bbox(data.size().to_rect());
+            self.paint_backdrop(ctx, area.intersect(widget_area));
+        }
+        // Re-rendering SVGs on every frame of a movement would be too slow, so use the 100%
+        // render until we stop. Expressions and dithering are only applied to the 100% render.
+        let (widget_size, device_scale) = (ctx.size(), ctx.scale().x());
+        let sharp = match &state.svg {
+            Some(svg) if self.is_still() && state.expression.is_none() && !state.eink => {
+                self.svg.raster(ctx, svg, trans, widget_size, device_scale)
+            }
+            _ => None,
+        };
+        if let Some((region, raster)) = sharp {
+            ctx.with_save(|ctx| {
+                ctx.transform(trans);
+                ctx.draw_image(&raster, region, InterpolationMode::Bilinear);
+            });
+        } else {
+            let interpolation = self.interpolation.unwrap_or(profile.interpolation);
+            let (level, mode) = interpolation.choose(scale_of(trans));
+            // Filtering would blur the dither pattern.
+            let mode = if state.eink {
+                InterpolationMode::NearestNeighbor
+            } else {
+                mode
+            };
+            match &state.animation {
+                // Animations are drawn at full size: mips for every frame would cost too much
+                // memory. On e-ink we only show the first frame.
+                Some(animation) if !state.eink => {
+                    let image = self
+                        .playback
+                        .image(animation, state.expression.as_deref(), ctx);
+                    ctx.with_save(|ctx| {
+                        ctx.transform(trans);
+                        ctx.draw_image(&image, data.size().to_rect(), mode);
+                    });
+                }
+                _ => {
+                    let level = self.build_mips(state, level);
+                    self.draw_tiles(ctx, level, trans, data.size(), mode);
+                }
+            }
+        }
 
-        ctx.draw_image(
-            &image,
-            trans * image.size().to_rect(),
-            InterpolationMode::Bilinear,
-        );
+        // Fading scrollbars would mean repainting while still.
+        let scrollbars = self
+            .scrollbars
+            .as_ref()
+            .filter(|_| !state.eink && profile.scrollbars);
+        if let Some(scrollbars) = scrollbars {
+            scrollbars.draw_bars(ctx, &viewport(data.size(), ctx.size(), trans), env);
+        }
+        if let Mode::Crop(crop) = &self.mode {
+            crop.paint(ctx, trans);
+        }
+        if let Some(pixel) = self.inspected(state) {
+            self.paint_inspector(ctx, pixel);
+        }
     }
 }
 
@@ -199,16 +779,299 @@ impl ZoomImage {
         Self {
             trans: Default::default(),
             mode: Mode::Normal,
-            piet_image: None,
+            mips: vec![],
+            snap_to_pixels: false,
+            scrollbars: None,
+            playback: Playback::default(),
+            windowing: None,
+            svg: SvgCache::default(),
+            paints: 0,
+            eink: false,
+            recent: ImageCache::new(DEFAULT_TEXTURE_BUDGET),
             fresh: true,
+            fitted: None,
+            scale_range: (MIN_SCALE, MAX_SCALE),
+            anim_len: TARGET_ANIM_LEN,
+            interpolation: None,
+            backdrop: Backdrop::default(),
+            checkerboard: None,
+            pointer: None,
         }
     }
 
-    fn image(&mut self, data: &Arc<ImageBuf>, rc: &mut Piet) -> Rc<PietImage> {
-        if let Some(img) = self.piet_image.as_ref() {
-            return img.clone();
+    /// Builder-style method to show scrollbars when the image doesn't fit.
+    pub fn with_scrollbars(mut self, show: bool) -> Self {
+        self.scrollbars = if show {
+            Some(ScrollComponent::new())
         } else {
-            self.piet_image.insert(Rc::new(data.to_image(rc))).clone()
+            None
+        };
+        self
+    }
+
+    /// Builder-style method to set how many bytes of recently shown images' mips and textures to
+    /// keep, so going back to them is quick.
+    pub fn with_texture_budget(mut self, bytes: usize) -> Self {
+        self.recent = ImageCache::new(bytes);
+        self
+    }
+
+    /// Builder-style method to round the image position to whole device pixels at integer
+    /// scales.
+    pub fn snap_to_pixels(mut self, snap: bool) -> Self {
+        self.snap_to_pixels = snap;
+        self
+    }
+
+    /// Builder-style method to set how far the user can zoom out and in. Images can always be
+    /// zoomed out until they fit, whatever `min` is.
+    pub fn with_scale_range(mut self, min: f64, max: f64) -> Self {
+        self.scale_range = (min, max);
+        self
+    }
+
+    /// Builder-style method to set how long zooming and panning animate for.
+    pub fn with_animation_duration(mut self, len: Duration) -> Self {
+        self.anim_len = len.as_secs_f64() * 1000.;
+        self
+    }
+
+    /// Builder-style method to sample images with `policy` whatever the profile says, or as the
+    /// profile says if it is `None`.
+    pub fn with_interpolation(mut self, policy: Option<InterpolationPolicy>) -> Self {
+        self.interpolation = policy;
+        self
+    }
+
+    /// Builder-style method to set what to draw behind images with transparency.
+    pub fn with_backdrop(mut self, backdrop: Backdrop) -> Self {
+        self.backdrop = backdrop;
+        self
+    }
+
+    /// Fill `area` with the backdrop, for the image's transparent parts to show against.
+    fn paint_backdrop(&mut self, ctx: &mut PaintCtx, area: Rect) {
+        if area.area() <= 0. {
+            return;
+        }
+        match &self.backdrop {
+            Backdrop::None => (),
+            Backdrop::Color(color) => ctx.fill(area, color),
+            Backdrop::Checkerboard => {
+                // The squares stay put while the image moves over them, as in most editors.
+                let size = ctx.size();
+                let cols = (size.width / CHECKER_SIZE).ceil() as usize;
+                let rows = (size.height / CHECKER_SIZE).ceil() as usize;
+                let stale = match &self.checkerboard {
+                    Some((c, r, _)) => (*c, *r) != (cols, rows),
+                    None => true,
+                };
+                if stale {
+                    let pixels: Vec<u8> = (0..rows)
+                        .flat_map(|y| {
+                            (0..cols).map(move |x| {
+                                if (x + y) % 2 == 0 {
+                                    CHECKER_LIGHT
+                                } else {
+                                    CHECKER_DARK
+                                }
+                            })
+                        })
+                        .collect();
+                    let buf = ImageBuf::from_raw(pixels, ImageFormat::Grayscale, cols, rows);
+                    self.checkerboard = Some((cols, rows, buf.to_image(ctx)));
+                }
+                if let Some((cols, rows, image)) = &self.checkerboard {
+                    let dest = Rect::new(
+                        0.,
+                        0.,
+                        *cols as f64 * CHECKER_SIZE,
+                        *rows as f64 * CHECKER_SIZE,
+                    );
+                    ctx.with_save(|ctx| {
+                        ctx.clip(area);
+                        // Stretched without smoothing, each pixel makes a sharp square.
+                        ctx.draw_image(image, dest, InterpolationMode::NearestNeighbor);
+                    });
+                }
+            }
+        }
+    }
+
+    /// Make sure we have mips up to `level`, returning `level`, or the smallest level we have if
+    /// the image is too small to go that far.
+    fn build_mips(&mut self, state: &ViewerState, level: usize) -> usize {
+        if self.mips.is_empty() {
+            let full = if let Some(dicom) = &state.dicom {
+                dicom.render(state.window)
+            } else if let Some(fits) = &state.fits {
+                fits.render(state.stretch)
+            } else {
+                match &state.unconverted {
+                    Some(original) if !state.convert_colors => (**original).clone(),
+                    _ => (*state.image).clone(),
+                }
+            };
+            let full = match &state.expression {
+                Some(expr) => expr.apply(&full),
+                None => full,
+            };
+            self.mips.push(Mip::new(full));
+            // The mips made at load time are only any use if we are drawing the image as loaded.
+            let as_loaded = state.dicom.is_none()
+                && state.fits.is_none()
+                && state.expression.is_none()
+                && (state.convert_colors || state.unconverted.is_none());
+            if as_loaded {
+                self.mips
+                    .extend(state.mips.iter().map(|mip| Mip::new(mip.clone())));
+            }
+        }
+        while self.mips.len() <= level {
+            let prev = &self.mips[self.mips.len() - 1].buf;
+            if prev.width().min(prev.height()) / 2 < MIN_MIP_SIZE {
+                break;
+            }
+            let next = halve_image(prev);
+            self.mips.push(Mip::new(next));
+        }
+        level.min(self.mips.len() - 1)
+    }
+
+    /// Draw the tiles of mip `level` that are on screen, uploading any we haven't already.
+    ///
+    /// Smaller mip levels are stretched to cover the same area as the full image.
+    ///
+    /// We need a cache for the piet images, because we cannot create them until `paint` is
+    /// called.
+    fn draw_tiles(
+        &mut self,
+        ctx: &mut PaintCtx,
+        level: usize,
+        trans: Affine,
+        image_size: Size,
+        mode: InterpolationMode,
+    ) {
+        self.paints += 1;
+        let (paints, eink) = (self.paints, self.eink);
+        let visible = trans
+            .inverse()
+            .transform_rect_bbox(ctx.size().to_rect())
+            .intersect(image_size.to_rect());
+        // Mid-rotation the tiles aren't rectangles on screen, so there are no edges to round.
+        let [a, b, c, d, _, _] = trans.as_coeffs();
+        let axis_aligned = (b.abs() < 1e-9 && c.abs() < 1e-9) || (a.abs() < 1e-9 && d.abs() < 1e-9);
+        let Mip { buf, tiles } = &mut self.mips[level];
+        let (width, height) = (buf.width(), buf.height());
+        if visible.area() > 0. && width > 0 && height > 0 {
+            // Mips drop odd rows and columns, so work out their scale from their size rather
+            // than the level.
+            let to_image = Vec2::new(
+                image_size.width / width as f64,
+                image_size.height / height as f64,
+            );
+            let tile_size = TILE_SIZE as f64;
+            let cols = (visible.x0 / to_image.x / tile_size) as usize
+                ..((visible.x1 / to_image.x / tile_size).ceil() as usize)
+                    .min(div_ceil(width, TILE_SIZE));
+            let rows = (visible.y0 / to_image.y / tile_size) as usize
+                ..((visible.y1 / to_image.y / tile_size).ceil() as usize)
+                    .min(div_ceil(height, TILE_SIZE));
+            let dpi = ctx.scale();
+            for row in rows {
+                for col in cols.clone() {
+                    // Tiles overlap their neighbours by a pixel, so bilinear filtering has
+                    // something to blend with at the seams.
+                    let (x0, y0) = (col * TILE_SIZE, row * TILE_SIZE);
+                    let x1 = (x0 + TILE_SIZE + 1).min(width);
+                    let y1 = (y0 + TILE_SIZE + 1).min(height);
+                    let tile = tiles.entry((col, row)).or_insert_with(|| {
+                        let image = if eink {
+                            dither(&crop(buf, x0, y0, x1, y1)).to_image(ctx)
+                        } else if width <= TILE_SIZE && height <= TILE_SIZE {
+                            buf.to_image(ctx)
+                        } else {
+                            crop(buf, x0, y0, x1, y1).to_image(ctx)
+                        };
+                        Tile {
+                            image: Rc::new(image),
+                            last_drawn: 0,
+                        }
+                    });
+                    tile.last_drawn = paints;
+                    let src = Rect::new(
+                        x0 as f64 * to_image.x,
+                        y0 as f64 * to_image.y,
+                        x1 as f64 * to_image.x,
+                        y1 as f64 * to_image.y,
+                    );
+                    let mut tile_trans = trans;
+                    if axis_aligned {
+                        // Put the edges on device pixels, so neighbouring tiles meet exactly.
+                        let dest = trans.transform_rect_bbox(src);
+                        let rounded = Rect::new(
+                            (dest.x0 * dpi.x()).round() / dpi.x(),
+                            (dest.y0 * dpi.y()).round() / dpi.y(),
+                            (dest.x1 * dpi.x()).round() / dpi.x(),
+                            (dest.y1 * dpi.y()).round() / dpi.y(),
+                        );
+                        // Half way through a flip the tile has no width.
+                        if rounded.area() > 0. {
+                            tile_trans = rect_to_rect(dest, rounded) * trans;
+                        }
+                    }
+                    ctx.with_save(|ctx| {
+                        ctx.transform(tile_trans);
+                        ctx.draw_image(&tile.image, src, mode);
+                    });
+                }
+            }
+        }
+        if self.mips.iter().map(|mip| mip.tiles.len()).sum::<usize>() > MAX_TILES {
+            for mip in &mut self.mips {
+                mip.tiles.retain(|_, tile| tile.last_drawn == paints);
+            }
+        }
+    }
+
+    /// Handle right button drags, which change the window a DICOM image is rendered with.
+    /// Horizontal movement changes the width (contrast) and vertical the centre (brightness).
+    ///
+    /// Returns whether the event was used.
+    fn window_level(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        dicom: &DicomImage,
+        window: &mut Window,
+    ) -> bool {
+        match event {
+            Event::MouseDown(mouse) if mouse.button == MouseButton::Right => {
+                self.windowing = Some((mouse.window_pos, *window));
+                ctx.set_active(true);
+                true
+            }
+            Event::MouseMove(mouse) => match self.windowing {
+                Some((start, start_window)) => {
+                    // Cover the whole range of values in about 500px of movement.
+                    let step = dicom.value_range() / 500.;
+                    let diff = mouse.window_pos - start;
+                    *window = Window {
+                        center: start_window.center + diff.y * step,
+                        width: (start_window.width + diff.x * step).max(1.),
+                    };
+                    true
+                }
+                None => false,
+            },
+            Event::MouseUp(mouse)
+                if mouse.button == MouseButton::Right && self.windowing.is_some() =>
+            {
+                self.windowing = None;
+                ctx.set_active(false);
+                true
+            }
+            _ => false,
         }
     }
 
@@ -238,7 +1101,7 @@ impl ZoomImage {
         self.zoom_to(
             data,
             widget_size,
-            self.trans.as_tuple().1 * scale_factor,
+            scale_of(self.trans) * scale_factor,
             origin,
         );
     }
@@ -276,56 +1139,200 @@ impl ZoomImage {
         );
         */
 
-        let old_trans = self.trans;
-
-        // Get the point in image space that the zoom in centred on
-        let origin_img = self.trans.inverse() * origin;
+        // Constrain the scale, using the size the (possibly turned) image takes up at 100%.
+        let size = bbox_size(data.size(), self.trans);
+        let scale = constrain_scale(size, widget_size, scale, self.scale_range);
 
-        // Scale
-        // Constrain the scale
-        let scale = constrain_scale(data.size(), widget_size, scale);
-
-        // Offset
-        let origin_scaled = origin_img.to_vec2() * scale;
-
-        // Calculate the new offset (the new mouse position on the image - the mouse position on
-        // screen. TODO this works but I don't know why. Actually do the math.
-        let diff = origin.to_vec2() - origin_scaled;
+        // Scale around `origin`, so the point of the image under it stays there.
+        let factor = scale / scale_of(self.trans);
+        let origin = origin.to_vec2();
+        let trans = Affine::translate(origin)
+            * Affine::scale(factor)
+            * Affine::translate(-origin)
+            * self.trans;
+        self.move_to(data, widget_size, trans);
+    }
 
-        self.trans = TranslateScale::new(diff, scale);
+    /// Move to `trans` (or as close as the constraints allow), animating if we aren't dragging.
+    fn move_to(&mut self, data: &Arc<ImageBuf>, widget_size: Size, trans: Affine) {
+        let old_trans = self.trans;
+        self.trans = trans;
         self.constrain_transform(data, widget_size);
         if !trans_approx_eq(self.trans, old_trans) {
             match &mut self.mode {
+                Mode::Normal | Mode::Anim(_) if self.eink => self.mode = Mode::Normal,
                 Mode::Normal => {
-                    self.mode = Mode::Anim(AnimState::new(old_trans, self.trans, TARGET_ANIM_LEN));
+                    let anim = AnimState::new(old_trans, self.trans, data.size(), self.anim_len);
+                    self.mode = Mode::Anim(anim);
                 }
                 Mode::Anim(anim) => {
                     let current = anim.current();
-                    self.mode = Mode::Anim(AnimState::new(current, self.trans, TARGET_ANIM_LEN))
+                    let anim = AnimState::new(current, self.trans, data.size(), self.anim_len);
+                    self.mode = Mode::Anim(anim);
                 }
-                // If we're dragging then don't animate
-                Mode::Drag(_) => (),
+                // If we're dragging or cropping then don't animate
+                Mode::Drag(_) | Mode::Crop(_) => (),
             }
         }
     }
 
-    fn zoom_to_fit(&mut self, data: &Arc<ImageBuf>, widget_size: Size) {
-        let img_size = data.size();
+    /// Zoom as the profile says a newly opened image should be, the right way up, or turned if
+    /// `auto_rotate` is on and it fits better that way.
+    fn zoom_initial(&mut self, state: &ViewerState, widget_size: Size) {
+        let data = &state.image;
+        let [_, _, _, _, x, y] = self.trans.as_coeffs();
+        self.trans = Affine::translate((x, y)) * Affine::scale(scale_of(self.trans));
+        let how = state
+            .initial_zoom
+            .unwrap_or(state.profile.settings().initial_zoom);
+        // Fitting the width of a turned image would mean scrolling sideways through it.
+        if state.auto_rotate && how != InitialZoom::FitWidth {
+            let img_size = data.size();
+            let upright =
+                (widget_size.width / img_size.width).min(widget_size.height / img_size.height);
+            let turned =
+                (widget_size.width / img_size.height).min(widget_size.height / img_size.width);
+            if turned > upright * AUTO_ROTATE_GAIN {
+                self.trans = self.trans * Affine::new([0., 1., -1., 0., 0., 0.]);
+            }
+        }
+        self.fit(data, widget_size, how);
+    }
+
+    /// Centre `area` of the image in the widget, keeping any turns and flips, and zoom so it
+    /// fills the widget, or to 100% if that is further in, so fine detail can be seen.
+    fn zoom_to_area(&mut self, data: &Arc<ImageBuf>, widget_size: Size, area: Rect) {
+        let orient = orientation(self.trans);
+        let turned = orient.transform_rect_bbox(area);
+        let scale = (widget_size.width / turned.width())
+            .min(widget_size.height / turned.height())
+            .max(1.);
+        let trans = Affine::scale(scale) * orient;
+        let offset = (widget_size * 0.5).to_vec2() - (trans * area.center()).to_vec2();
+        self.move_to(data, widget_size, Affine::translate(offset) * trans);
+    }
+
+    /// Fit the image to the widget as `how` says, keeping any turns and flips (except for
+    /// `FitWidth`, which shows the image the right way up).
+    fn fit(&mut self, data: &Arc<ImageBuf>, widget_size: Size, how: InitialZoom) {
+        // Minimised, or squeezed out by panels: there is nothing to fit to, and a scale of 0
+        // would break the transform. We fit again when there is room.
+        if widget_size.is_empty() {
+            return;
+        }
+        let img_size = bbox_size(data.size(), self.trans);
         let fit_x_scale = widget_size.width / img_size.width;
         let fit_y_scale = widget_size.height / img_size.height;
-        let scale = fit_x_scale.min(fit_y_scale);
-        self.zoom_to(data, widget_size, scale, Point::ZERO);
+        match how {
+            InitialZoom::Fit => {
+                let scale = fit_x_scale.min(fit_y_scale);
+                self.zoom_to(data, widget_size, scale, Point::ZERO);
+            }
+            InitialZoom::FitWhole => {
+                let fit = fit_x_scale.min(fit_y_scale);
+                let scale = if fit < 1. { fit } else { fit.floor() };
+                // Zooming around the centre keeps the (centred) image where it is.
+                let centre = (widget_size * 0.5).to_vec2().to_point();
+                self.zoom_to(data, widget_size, scale, centre);
+            }
+            InitialZoom::FitWidth => {
+                let fit_x_scale = widget_size.width / data.size().width;
+                self.move_to(data, widget_size, Affine::scale(fit_x_scale));
+            }
+            InitialZoom::Scale(scale) => {
+                let centre = (widget_size * 0.5).to_vec2().to_point();
+                self.zoom_to(data, widget_size, scale, centre);
+            }
+        }
+        self.fitted = Some((how, self.trans));
     }
 
     /// Transform the image at 100% scale positioned at (0,0) to the correct image
     /// position, taking into account any drag operation or animation in progress.
-    fn draw_transform(&self) -> TranslateScale {
-        match &self.mode {
-            Mode::Normal => self.trans,
-            Mode::Drag(Drag { diff, .. }) => {
-                let (trans, scale) = self.trans.as_tuple();
-                TranslateScale::new(trans + *diff, scale)
+    /// The pixel under the mouse, if the inspector is on and the mouse is over the image.
+    fn inspected(&self, state: &ViewerState) -> Option<Inspected> {
+        if !state.inspect {
+            return None;
+        }
+        let image = &state.image;
+        // The same inverse transform as we send with `NOTIFY_TRANSFORM`, but for the image as it
+        // is drawn, so the readout matches what is under the mouse mid-animation too.
+        let pos = self.draw_transform().inverse() * self.pointer?;
+        if pos.x < 0.
+            || pos.y < 0.
+            || pos.x >= image.width() as f64
+            || pos.y >= image.height() as f64
+        {
+            return None;
+        }
+        let (x, y) = (pos.x as usize, pos.y as usize);
+        Some(Inspected {
+            x,
+            y,
+            rgba: pixel_at(image, x, y),
+        })
+    }
+
+    /// Draw the readout for `pixel` beside the mouse, on whichever side keeps it in the widget.
+    fn paint_inspector(&self, ctx: &mut PaintCtx, pixel: Inspected) {
+        let pointer = match self.pointer {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        let [r, g, b, a] = pixel.rgba;
+        let text = format!(
+            "{}, {}\n{}  rgba({}, {}, {}, {})",
+            pixel.x,
+            pixel.y,
+            pixel.hex(),
+            r,
+            g,
+            b,
+            a
+        );
+        let layout = match ctx
+            .text()
+            .new_text_layout(text)
+            .font(FontFamily::MONOSPACE, 12.)
+            .text_color(Color::WHITE)
+            .build()
+        {
+            Ok(layout) => layout,
+            Err(e) => {
+                log::warn!("could not lay out the inspector's text: {}", e);
+                return;
             }
+        };
+        let text_size = layout.size();
+        let size = Size::new(
+            INSPECTOR_PADDING * 3. + INSPECTOR_SWATCH + text_size.width,
+            INSPECTOR_PADDING * 2. + text_size.height.max(INSPECTOR_SWATCH),
+        );
+        let widget_size = ctx.size();
+        let mut origin = pointer + Vec2::new(INSPECTOR_OFFSET, INSPECTOR_OFFSET);
+        if origin.x + size.width > widget_size.width {
+            origin.x = pointer.x - INSPECTOR_OFFSET - size.width;
+        }
+        if origin.y + size.height > widget_size.height {
+            origin.y = pointer.y - INSPECTOR_OFFSET - size.height;
+        }
+        let panel = Rect::from_origin_size(origin, size);
+        ctx.fill(panel.to_rounded_rect(4.), &Color::rgba8(0, 0, 0, 0xc0));
+        let swatch = Rect::from_origin_size(
+            origin + Vec2::new(INSPECTOR_PADDING, INSPECTOR_PADDING),
+            (INSPECTOR_SWATCH, INSPECTOR_SWATCH),
+        );
+        ctx.fill(swatch, &Color::rgba8(r, g, b, a));
+        ctx.stroke(swatch, &Color::grey8(0x80), 1.);
+        let text_origin =
+            origin + Vec2::new(INSPECTOR_PADDING * 2. + INSPECTOR_SWATCH, INSPECTOR_PADDING);
+        ctx.draw_text(&layout, text_origin);
+    }
+
+    fn draw_transform(&self) -> Affine {
+        match &self.mode {
+            Mode::Normal | Mode::Crop(_) => self.trans,
+            Mode::Drag(Drag { diff, .. }) => Affine::translate(*diff) * self.trans,
             Mode::Anim(anim_state) => anim_state.current(),
         }
     }
@@ -345,15 +1352,15 @@ impl ZoomImage {
     /// Returns true if we need to request animation frame.
     fn drag_stop(&mut self, data: &Arc<ImageBuf>, widget_size: Size) -> bool {
         if let Mode::Drag(drag) = &self.mode {
-            let (trans, scale) = self.trans.as_tuple();
-            let current_trans = TranslateScale::new(trans + drag.diff, scale);
+            let current_trans = Affine::translate(drag.diff) * self.trans;
             self.trans = current_trans;
             self.constrain_transform(data, widget_size);
-            if trans_approx_eq(self.trans, current_trans) {
+            if self.eink || trans_approx_eq(self.trans, current_trans) {
                 self.mode = Mode::Normal;
                 false
             } else {
-                self.mode = Mode::Anim(AnimState::new(current_trans, self.trans, TARGET_ANIM_LEN));
+                let anim = AnimState::new(current_trans, self.trans, data.size(), self.anim_len);
+                self.mode = Mode::Anim(anim);
                 true
             }
         } else {
@@ -367,13 +1374,27 @@ impl ZoomImage {
     fn drag_move(&mut self, window_pos: Point, ctx: &mut EventCtx) {
         if let Mode::Drag(drag) = &mut self.mode {
             drag.diff = window_pos - drag.start;
-            ctx.request_paint();
+            // E-ink is too slow to follow the mouse, so just jump when the drag ends.
+            if !self.eink {
+                ctx.request_paint();
+            }
         }
     }
 
     /// Helper function to call `constrain_transform` for this image.
     fn constrain_transform(&mut self, data: &Arc<ImageBuf>, widget_size: Size) {
-        self.trans = constrain_transform(data.size(), widget_size, self.trans);
+        self.trans = constrain_transform(data.size(), widget_size, self.trans, self.scale_range);
+    }
+
+    /// Make the scrollbars (if we have them) visible, and restart their fade-out timer. On e-ink
+    /// they stay hidden.
+    fn show_scrollbars(&mut self, ctx: &mut EventCtx, env: &Env) {
+        if self.eink {
+            return;
+        }
+        if let Some(scrollbars) = self.scrollbars.as_mut() {
+            scrollbars.reset_scrollbar_fade(|d| ctx.request_timer(d), env);
+        }
     }
 
     fn is_dragging(&self) -> bool {
@@ -384,16 +1405,372 @@ impl ZoomImage {
         matches!(self.mode, Mode::Anim(_))
     }
 
+    fn is_cropping(&self) -> bool {
+        matches!(self.mode, Mode::Crop(_))
+    }
+
+    /// Whether the image is sitting where `trans` says, rather than being dragged or animated.
+    fn is_still(&self) -> bool {
+        matches!(self.mode, Mode::Normal | Mode::Crop(_))
+    }
+
+    /// Replace the image with the crop selection, and leave crop mode.
+    fn apply_crop(
+        &mut self,
+        ctx: &mut EventCtx,
+        state: &mut ViewerState,
+        save_to: Option<PathBuf>,
+    ) {
+        let crop = match &self.mode {
+            Mode::Crop(crop) => crop,
+            _ => return,
+        };
+        // Animations and the full depth samples of DICOM and FITS images aren't kept: we crop
+        // the image as first shown.
+        let image = match crop.apply(&state.image) {
+            Some(image) => Arc::new(image),
+            None => return,
+        };
+        *state = state.cropped(image.clone());
+        ctx.submit_command(CROPPED.with(Cropped { image, save_to }));
+    }
+
+    /// The part of the image that is on screen.
+    fn view(&self, img_size: Size, widget_size: Size) -> View {
+        // The image turned as it is shown, at 100%, has its origin where the bounding box does.
+        let bbox = self.trans.transform_rect_bbox(img_size.to_rect());
+        let on_screen = widget_size.to_rect().intersect(bbox) - bbox.origin().to_vec2();
+        let scale = scale_of(self.trans);
+        View {
+            orientation: exif_orientation(self.trans),
+            area: Rect::new(
+                on_screen.x0 / scale,
+                on_screen.y0 / scale,
+                on_screen.x1 / scale,
+                on_screen.y1 / scale,
+            ),
+        }
+    }
+
     fn notify_transform(&self) -> Command {
         NOTIFY_TRANSFORM.with(self.trans.inverse())
     }
 }
 
+/// A pixel the inspector is pointing at.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Inspected {
+    x: usize,
+    y: usize,
+    /// The colour, not premultiplied.
+    rgba: [u8; 4],
+}
+
+impl Inspected {
+    /// The colour as `#rrggbb`, or `#rrggbbaa` if it is at all transparent.
+    fn hex(&self) -> String {
+        let [r, g, b, a] = self.rgba;
+        if a == 0xff {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+}
+
+/// The colour of the pixel at `x`, `y` in `image`, which must be inside it.
+fn pixel_at(image: &ImageBuf, x: usize, y: usize) -> [u8; 4] {
+    let idx = y * image.width() + x;
+    let pixels = image.raw_pixels();
+    match image.format() {
+        ImageFormat::Grayscale => {
+            let g = pixels[idx];
+            [g, g, g, 0xff]
+        }
+        ImageFormat::Rgb => {
+            let px = &pixels[idx * 3..idx * 3 + 3];
+            [px[0], px[1], px[2], 0xff]
+        }
+        ImageFormat::RgbaPremul => {
+            let px = &pixels[idx * 4..idx * 4 + 4];
+            let a = px[3];
+            let unpremultiply = |c: u8| {
+                if a == 0 {
+                    0
+                } else {
+                    ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+                }
+            };
+            [
+                unpremultiply(px[0]),
+                unpremultiply(px[1]),
+                unpremultiply(px[2]),
+                a,
+            ]
+        }
+        _ => {
+            let px = &pixels[idx * 4..idx * 4 + 4];
+            [px[0], px[1], px[2], px[3]]
+        }
+    }
+}
+
+/// Chooses how the image is sampled when drawn, depending on the scale it is drawn at.
+#[derive(Debug, Copy, Clone)]
+pub struct InterpolationPolicy {
+    /// At scales above this use nearest neighbour, so individual pixels stay crisp.
+    pub nearest_above: f64,
+    /// At scales below this draw from a prefiltered (downsampled) copy, to avoid aliasing.
+    pub prefilter_below: f64,
+    /// If set, always use this mode at full resolution, whatever the scale.
+    pub fixed: Option<InterpolationMode>,
+}
+
+impl Default for InterpolationPolicy {
+    fn default() -> Self {
+        Self {
+            nearest_above: 2.,
+            prefilter_below: 0.5,
+            fixed: None,
+        }
+    }
+}
+
+impl InterpolationPolicy {
+    /// Choose the mip level and interpolation mode for drawing at `scale`.
+    ///
+    /// Level 0 is the full image, and each level after that is half the size of the one before.
+    fn choose(&self, scale: f64) -> (usize, InterpolationMode) {
+        if let Some(mode) = self.fixed {
+            (0, mode)
+        } else if scale > self.nearest_above {
+            (0, InterpolationMode::NearestNeighbor)
+        } else if scale < self.prefilter_below {
+            // The smallest level that is still at least as big as what we draw, so we are always
+            // shrinking (by less than half) and never enlarging.
+            let level = (-scale.log2()).floor().max(0.) as usize;
+            (level, InterpolationMode::Bilinear)
+        } else {
+            (0, InterpolationMode::Bilinear)
+        }
+    }
+}
+
+/// The shape to keep the crop selection, as width to height.
+#[derive(Debug, Copy, Clone, PartialEq, Data)]
+pub enum CropAspect {
+    Free,
+    /// The same shape as the whole image.
+    Original,
+    Ratio(u32, u32),
+}
+
+impl CropAspect {
+    /// The width over the height, or `None` if any shape will do.
+    fn ratio(self, img_size: Size) -> Option<f64> {
+        match self {
+            CropAspect::Free => None,
+            CropAspect::Original => Some(img_size.width / img_size.height),
+            CropAspect::Ratio(w, h) => Some(w as f64 / h as f64),
+        }
+    }
+}
+
+/// The result of `CROP_APPLY`.
+#[derive(Debug, Clone)]
+pub struct Cropped {
+    pub image: Arc<ImageBuf>,
+    /// Where to save the cropped image, if anywhere.
+    pub save_to: Option<PathBuf>,
+}
+
+/// One level of the mip pyramid.
+struct Mip {
+    buf: ImageBuf,
+    /// The tiles that have been drawn, by column and row.
+    tiles: HashMap<(usize, usize), Tile>,
+}
+
+impl Mip {
+    fn new(buf: ImageBuf) -> Self {
+        Self {
+            buf,
+            tiles: HashMap::new(),
+        }
+    }
+}
+
+struct Tile {
+    image: Rc<PietImage>,
+    /// The value of `ZoomImage::paints` when this was last drawn.
+    last_drawn: u64,
+}
+
+/// Copy the pixels from `(x0, y0)` up to `(x1, y1)` out of `image`.
+fn crop(image: &ImageBuf, x0: usize, y0: usize, x1: usize, y1: usize) -> ImageBuf {
+    let bpp = image.format().bytes_per_pixel();
+    let stride = image.width() * bpp;
+    let mut pixels = Vec::with_capacity((x1 - x0) * (y1 - y0) * bpp);
+    for line in image
+        .raw_pixels()
+        .chunks_exact(stride)
+        .skip(y0)
+        .take(y1 - y0)
+    {
+        pixels.extend_from_slice(&line[x0 * bpp..x1 * bpp]);
+    }
+    ImageBuf::from_raw(pixels, image.format(), x1 - x0, y1 - y0)
+}
+
+/// Convert to 16 level grey for e-ink displays.
+fn dither(image: &ImageBuf) -> ImageBuf {
+    let premultiplied = image.format() == ImageFormat::RgbaPremul;
+    let pixels = pixel_ops::to_rgba(image);
+    let grey = pixel_ops::dither_grey(&pixels, image.width(), premultiplied);
+    ImageBuf::from_raw(grey, ImageFormat::Grayscale, image.width(), image.height())
+}
+
+/// Identifies an image for `ZoomImage::recent`. The cache holds on to the image, so the address
+/// can't be reused while it is there.
+fn image_key(image: &Arc<ImageBuf>) -> usize {
+    Arc::as_ptr(image) as usize
+}
+
+fn div_ceil(a: usize, b: usize) -> usize {
+    (a + b - 1) / b
+}
+
+/// Playback state for an animated image.
+#[derive(Default)]
+struct Playback {
+    /// Whether we are asking for animation frames.
+    playing: bool,
+    /// The frame being shown.
+    frame: usize,
+    /// How long the current frame has been shown for, in ms.
+    elapsed: f64,
+    /// Piet images for the frames, built as they are first shown.
+    images: Vec<Option<Rc<PietImage>>>,
+}
+
+impl Playback {
+    /// Move the animation on by `time` ms.
+    fn advance(&mut self, animation: &AnimatedImage, time: f64) {
+        // Don't spin through many loops if we haven't been called for a while.
+        let total = animation.duration().as_secs_f64() * 1000.;
+        self.elapsed = (self.elapsed + time) % total;
+        loop {
+            let delay = animation.delays[self.frame].as_secs_f64() * 1000.;
+            if self.elapsed < delay {
+                break;
+            }
+            self.elapsed -= delay;
+            self.frame = (self.frame + 1) % animation.frames.len();
+        }
+    }
+
+    /// Get the piet image for the current frame.
+    fn image(
+        &mut self,
+        animation: &AnimatedImage,
+        expression: Option<&Expr>,
+        rc: &mut Piet,
+    ) -> Rc<PietImage> {
+        if self.images.len() != animation.frames.len() {
+            self.images = vec![None; animation.frames.len()];
+        }
+        let frame = self.frame;
+        self.images[frame]
+            .get_or_insert_with(|| {
+                let buf = &animation.frames[frame];
+                Rc::new(match expression {
+                    Some(expr) => expr.apply(buf).to_image(rc),
+                    None => buf.to_image(rc),
+                })
+            })
+            .clone()
+    }
+}
+
+#[derive(Default)]
+struct SvgCache {
+    /// `None` until we first draw, or if the SVG failed to parse.
+    tree: Option<usvg::Tree>,
+    /// The region of the document last rendered, the scale it was rendered at, and the result.
+    raster: Option<(Rect, f64, Rc<PietImage>)>,
+}
+
+impl SvgCache {
+    /// Get the visible part of `svg` rendered at the scale it is drawn with `trans`, along with
+    /// the region of the document it covers.
+    fn raster(
+        &mut self,
+        rc: &mut Piet,
+        svg: &SvgImage,
+        trans: Affine,
+        widget_size: Size,
+        device_scale: f64,
+    ) -> Option<(Rect, Rc<PietImage>)> {
+        // We only render what is visible, so the raster is never much bigger than the window,
+        // however far in we zoom.
+        let visible = trans.inverse().transform_rect_bbox(widget_size.to_rect());
+        let region = visible.intersect(svg.size.to_rect());
+        if region.area() <= 0. {
+            return None;
+        }
+        let scale = scale_of(trans) * device_scale;
+        if let Some((cached_region, cached_scale, raster)) = &self.raster {
+            if *cached_region == region && *cached_scale == scale {
+                return Some((region, raster.clone()));
+            }
+        }
+        if self.tree.is_none() {
+            match svg.parse() {
+                Ok(tree) => self.tree = Some(tree),
+                Err(e) => {
+                    log::error!("could not parse SVG: {}", e);
+                    return None;
+                }
+            }
+        }
+        let image = svg::render(self.tree.as_ref()?, region, scale)?;
+        let raster = Rc::new(image.to_image(rc));
+        self.raster = Some((region, scale, raster.clone()));
+        Some((region, raster))
+    }
+}
+
+/// Make an image half the size of `image` in each direction.
+/// Make the mip levels below `image`, so `ZoomImage` doesn't have to while painting.
+pub fn mip_levels(image: &ImageBuf) -> Vec<ImageBuf> {
+    let mut levels: Vec<ImageBuf> = vec![];
+    loop {
+        let prev = levels.last().unwrap_or(image);
+        if prev.width().min(prev.height()) / 2 < MIN_MIP_SIZE {
+            return levels;
+        }
+        let next = halve_image(prev);
+        levels.push(next);
+    }
+}
+
+fn halve_image(image: &ImageBuf) -> ImageBuf {
+    let format = match image.format() {
+        // Averaging premultiplied values is correct, so keep them that way.
+        ImageFormat::RgbaPremul => ImageFormat::RgbaPremul,
+        _ => ImageFormat::RgbaSeparate,
+    };
+    let pixels = pixel_ops::to_rgba(image);
+    let (pixels, width, height) = pixel_ops::halve(&pixels, image.width(), image.height());
+    ImageBuf::from_raw(pixels, format, width, height)
+}
+
 #[derive(Debug)]
 enum Mode {
     Normal,
     Drag(Drag),
     Anim(AnimState),
+    Crop(Crop),
 }
 
 #[derive(Debug)]
@@ -408,27 +1785,304 @@ struct Drag {
     diff: Vec2,
 }
 
+/// The selection in crop mode.
+#[derive(Debug)]
+struct Crop {
+    /// The part of the image to keep, in image coords.
+    rect: Rect,
+    /// The width over the height to keep `rect` at, if it is locked.
+    ratio: Option<f64>,
+    /// The edges being dragged, with the selection and mouse position (in image coords) when the
+    /// drag started.
+    grab: Option<(Grab, Rect, Point)>,
+}
+
+/// Which edges of the crop selection a drag moves. If neither moves in either direction, the
+/// whole selection moves.
+#[derive(Debug, Copy, Clone)]
+struct Grab {
+    x: Edge,
+    y: Edge,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Edge {
+    Min,
+    Max,
+    Neither,
+}
+
+impl Crop {
+    /// Select as much of the image as fits `ratio`, from the middle.
+    fn new(img_size: Size, ratio: Option<f64>) -> Self {
+        let bounds = img_size.to_rect();
+        let rect = match ratio {
+            Some(ratio) => fit_aspect(bounds, ratio, bounds.center(), true, bounds),
+            None => bounds,
+        };
+        Crop {
+            rect,
+            ratio,
+            grab: None,
+        }
+    }
+
+    /// Handle left button drags, which move the selection or its edges, or start a new one.
+    ///
+    /// Returns whether the event was used.
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, trans: Affine, img_size: Size) -> bool {
+        let to_image = trans.inverse();
+        // Quarter turns swap which way the image's edges run on screen.
+        let [a, ..] = trans.as_coeffs();
+        let turned = a.abs() < 1e-9;
+        match event {
+            Event::MouseDown(mouse) if mouse.button == MouseButton::Left => {
+                let pos = to_image * mouse.pos;
+                let slop = CROP_GRAB_DISTANCE / scale_of(trans);
+                let grab = match self.grab_at(pos, slop) {
+                    Some(grab) => (grab, self.rect, pos),
+                    None => {
+                        let bounds = img_size.to_rect();
+                        let start = Point::new(
+                            pos.x.max(bounds.x0).min(bounds.x1),
+                            pos.y.max(bounds.y0).min(bounds.y1),
+                        );
+                        let grab = Grab {
+                            x: Edge::Max,
+                            y: Edge::Max,
+                        };
+                        (grab, Rect::from_points(start, start), start)
+                    }
+                };
+                self.grab = Some(grab);
+                ctx.set_active(true);
+                true
+            }
+            Event::MouseMove(mouse) => {
+                let pos = to_image * mouse.pos;
+                if self.grab.is_some() {
+                    self.drag_to(pos, img_size);
+                    ctx.request_paint();
+                    true
+                } else {
+                    let slop = CROP_GRAB_DISTANCE / scale_of(trans);
+                    ctx.set_cursor(&grab_cursor(self.grab_at(pos, slop), turned));
+                    false
+                }
+            }
+            Event::MouseUp(mouse) if mouse.button == MouseButton::Left && self.grab.is_some() => {
+                self.grab = None;
+                ctx.set_active(false);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// What a drag starting at `pos` would move, or `None` if it is outside the selection.
+    fn grab_at(&self, pos: Point, slop: f64) -> Option<Grab> {
+        let r = self.rect;
+        if pos.x < r.x0 - slop || pos.x > r.x1 + slop || pos.y < r.y0 - slop || pos.y > r.y1 + slop
+        {
+            return None;
+        }
+        let near = |v: f64, min: f64, max: f64| {
+            if (v - min).abs() <= slop {
+                Edge::Min
+            } else if (v - max).abs() <= slop {
+                Edge::Max
+            } else {
+                Edge::Neither
+            }
+        };
+        Some(Grab {
+            x: near(pos.x, r.x0, r.x1),
+            y: near(pos.y, r.y0, r.y1),
+        })
+    }
+
+    /// Move the grabbed edges to follow the mouse, now at `pos`, keeping inside the image.
+    fn drag_to(&mut self, pos: Point, img_size: Size) {
+        let (grab, start_rect, start) = match self.grab {
+            Some(grab) => grab,
+            None => return,
+        };
+        let bounds = img_size.to_rect();
+        let delta = pos - start;
+        if grab.x == Edge::Neither && grab.y == Edge::Neither {
+            let dx = delta
+                .x
+                .max(bounds.x0 - start_rect.x0)
+                .min(bounds.x1 - start_rect.x1);
+            let dy = delta
+                .y
+                .max(bounds.y0 - start_rect.y0)
+                .min(bounds.y1 - start_rect.y1);
+            self.rect = start_rect + Vec2::new(dx, dy);
+            return;
+        }
+        // The selection grows away from the edges that aren't grabbed, or from the middle if
+        // neither edge in a direction is.
+        let mut rect = start_rect;
+        let mut anchor = start_rect.center();
+        match grab.x {
+            Edge::Min => {
+                rect.x0 += delta.x;
+                anchor.x = rect.x1;
+            }
+            Edge::Max => {
+                rect.x1 += delta.x;
+                anchor.x = rect.x0;
+            }
+            Edge::Neither => (),
+        }
+        match grab.y {
+            Edge::Min => {
+                rect.y0 += delta.y;
+                anchor.y = rect.y1;
+            }
+            Edge::Max => {
+                rect.y1 += delta.y;
+                anchor.y = rect.y0;
+            }
+            Edge::Neither => (),
+        }
+        let rect = rect.abs().intersect(bounds);
+        self.rect = match self.ratio {
+            Some(ratio) => fit_aspect(rect, ratio, anchor, grab.x != Edge::Neither, bounds),
+            None => rect,
+        };
+    }
+
+    /// The selected pixels of `image`, or `None` if the selection is less than a pixel.
+    fn apply(&self, image: &ImageBuf) -> Option<ImageBuf> {
+        let (width, height) = (image.width() as f64, image.height() as f64);
+        let x0 = self.rect.x0.round().max(0.).min(width) as usize;
+        let y0 = self.rect.y0.round().max(0.).min(height) as usize;
+        let x1 = self.rect.x1.round().max(0.).min(width) as usize;
+        let y1 = self.rect.y1.round().max(0.).min(height) as usize;
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        Some(crop(image, x0, y0, x1, y1))
+    }
+
+    /// Shade the image outside the selection, and outline it.
+    fn paint(&self, ctx: &mut PaintCtx, trans: Affine) {
+        let selection = trans.transform_rect_bbox(self.rect);
+        let mut shade = ctx.size().to_rect().to_path(0.1);
+        shade.extend(selection.path_elements(0.1));
+        ctx.fill_even_odd(shade, &Color::rgba8(0, 0, 0, 0x90));
+        ctx.stroke(selection, &Color::WHITE, 1.);
+        for corner in &[
+            Point::new(selection.x0, selection.y0),
+            Point::new(selection.x1, selection.y0),
+            Point::new(selection.x0, selection.y1),
+            Point::new(selection.x1, selection.y1),
+        ] {
+            let handle = Rect::from_center_size(*corner, (6., 6.));
+            ctx.fill(handle, &Color::WHITE);
+        }
+    }
+}
+
+/// The cursor for dragging `grab`, or for starting a new selection if it is `None`.
+fn grab_cursor(grab: Option<Grab>, turned: bool) -> Cursor {
+    match grab {
+        Some(Grab {
+            x: Edge::Neither,
+            y: Edge::Neither,
+        }) => Cursor::OpenHand,
+        Some(Grab {
+            x,
+            y: Edge::Neither,
+        }) if x != Edge::Neither => {
+            if turned {
+                Cursor::ResizeUpDown
+            } else {
+                Cursor::ResizeLeftRight
+            }
+        }
+        Some(Grab {
+            x: Edge::Neither, ..
+        }) => {
+            if turned {
+                Cursor::ResizeLeftRight
+            } else {
+                Cursor::ResizeUpDown
+            }
+        }
+        _ => Cursor::Crosshair,
+    }
+}
+
+/// The largest rect with width over height `ratio`, no wider (or if `!width_drives`, no taller)
+/// than `rect`, that fits in `bounds`. `anchor` stays at the same place in the rect, so it can be
+/// a corner, the middle of an edge, or the centre.
+fn fit_aspect(rect: Rect, ratio: f64, anchor: Point, width_drives: bool, bounds: Rect) -> Rect {
+    let fraction = |a: f64, min: f64, len: f64| {
+        if len > 0. {
+            ((a - min) / len).max(0.).min(1.)
+        } else {
+            0.
+        }
+    };
+    let fx = fraction(anchor.x, rect.x0, rect.width());
+    let fy = fraction(anchor.y, rect.y0, rect.height());
+    // How far the rect can grow in one direction with the anchor `f` of the way along it.
+    let room = |a: f64, f: f64, min: f64, max: f64| {
+        let mut room = f64::INFINITY;
+        if f > 0. {
+            room = room.min((a - min) / f);
+        }
+        if f < 1. {
+            room = room.min((max - a) / (1. - f));
+        }
+        room
+    };
+    let width = if width_drives {
+        rect.width()
+    } else {
+        rect.height() * ratio
+    };
+    let width = width
+        .min(room(anchor.x, fx, bounds.x0, bounds.x1))
+        .min(room(anchor.y, fy, bounds.y0, bounds.y1) * ratio);
+    let height = width / ratio;
+    Rect::new(
+        anchor.x - fx * width,
+        anchor.y - fy * height,
+        anchor.x + (1. - fx) * width,
+        anchor.y + (1. - fy) * height,
+    )
+}
+
 /// For animation
 #[derive(Debug)]
 struct AnimState {
     /// The current position in the animation
     t: f64,
     /// The starting transform
-    from: TranslateScale,
+    from: Affine,
     /// The target transform
-    to: TranslateScale,
+    to: Affine,
+    /// The middle of the image, in image coords. It moves in a straight line while the image
+    /// turns around it.
+    centre: Point,
     /// The speed at which to animate (time to complete in ms)
     len: f64,
 }
 
 impl AnimState {
-    fn new(from: TranslateScale, to: TranslateScale, len: f64) -> Self {
+    fn new(from: Affine, to: Affine, img_size: Size, len: f64) -> Self {
+        let centre = (img_size * 0.5).to_vec2().to_point();
         // The animation is already complete.
         if trans_approx_eq(from, to) {
             return Self {
                 t: 1.,
                 from,
                 to,
+                centre,
                 len, // arbitrary
             };
         }
@@ -436,27 +2090,35 @@ impl AnimState {
             t: 0.,
             from,
             to,
+            centre,
             len,
         }
     }
 
     /// Get the current state of the animation
-    fn current(&self) -> TranslateScale {
-        let (
-            Vec2 {
-                x: x_from,
-                y: y_from,
-            },
-            s_from,
-        ) = self.from.as_tuple();
-        let (Vec2 { x: x_to, y: y_to }, s_to) = self.to.as_tuple();
-
+    fn current(&self) -> Affine {
         let t = easings::cubic_out(self.t);
-        let x_cur = x_from + (x_to - x_from) * t;
-        let y_cur = y_from + (y_to - y_from) * t;
+        let centre = (self.from * self.centre).lerp(self.to * self.centre, t);
+        let (s_from, s_to) = (scale_of(self.from), scale_of(self.to));
         let s_cur = s_from + (s_to - s_from) * t;
 
-        TranslateScale::new(Vec2::new(x_cur, y_cur), s_cur)
+        // How the image turns or flips over on screen between the two.
+        let change = orientation(self.to) * orientation(self.from).inverse();
+        let [a, b, c, d, _, _] = change.as_coeffs();
+        let change_cur = if change.determinant() > 0. {
+            // A rotation: turn the shortest way round.
+            Affine::rotate(b.atan2(a) * t)
+        } else {
+            // A reflection: squash the image to nothing across the mirror and out again the
+            // other side, like turning over a card.
+            Affine::new([1. - t + a * t, b * t, c * t, 1. - t + d * t, 0., 0.])
+        };
+
+        Affine::translate(centre.to_vec2())
+            * Affine::scale(s_cur)
+            * change_cur
+            * orientation(self.from)
+            * Affine::translate(-self.centre.to_vec2())
     }
 
     /// Update the animation, given the time in ms.
@@ -470,29 +2132,47 @@ impl AnimState {
     }
 }
 
-/// Takes any transform and returns the "closest" transform that is inside our constraints.
-fn constrain_transform(img_size: Size, widget_size: Size, trans: TranslateScale) -> TranslateScale {
-    let (offset, scale) = trans.as_tuple();
+/// Takes any similarity transform (rotation, flip, uniform scale and translation) and returns the
+/// "closest" transform that is inside our constraints.
+///
+/// The constraints apply to the axis-aligned bounding box of the transformed image, which for an
+/// unrotated image is just the image itself.
+fn constrain_transform(
+    img_size: Size,
+    widget_size: Size,
+    trans: Affine,
+    scale_range: (f64, f64),
+) -> Affine {
+    let [_, _, _, _, x, y] = trans.as_coeffs();
+    let orient = orientation(trans);
+    let img_rect = img_size.to_rect();
 
-    // Firstly, constrain the scaling.
-    let scale = constrain_scale(img_size, widget_size, scale);
+    // Firstly, constrain the scaling, using the size the image takes up at 100%.
+    let scale = constrain_scale(
+        bbox_size(img_size, trans),
+        widget_size,
+        scale_of(trans),
+        scale_range,
+    );
+    let trans = Affine::translate((x, y)) * Affine::scale(scale) * orient;
 
-    // Then, given the chosen scale, constrain the offset.
-    let offset = constrain_offset(img_size, widget_size, scale, offset);
-
-    TranslateScale::new(offset, scale)
+    // Then, given the chosen scale, constrain the position of the bounding box.
+    let bbox = trans.transform_rect_bbox(img_rect);
+    let origin = bbox.origin().to_vec2();
+    let new_origin = constrain_offset(bbox.size(), widget_size, 1., origin);
+    Affine::translate(new_origin - origin) * trans
 }
 
-fn constrain_scale(img_size: Size, widget_size: Size, scale: f64) -> f64 {
+fn constrain_scale(img_size: Size, widget_size: Size, scale: f64, (min, max): (f64, f64)) -> f64 {
     //  - At the lower end, the scale should be bigger than the smaller of
-    //    - a compile-time minimum scale (e.g. 20%)
+    //    - a configured minimum scale (e.g. 20%)
     //    - the biggest size that can fit the whole image in.
     //  - At the higher end, the scale should be smaller than some maximum scale.
     // If both constrains are not satisfyable, then choose the size from the minimum test.
     let min_x_scale = widget_size.width / img_size.width;
     let min_y_scale = widget_size.height / img_size.height;
-    let min_scale = min_x_scale.min(min_y_scale).min(MIN_SCALE);
-    scale.min(MAX_SCALE).max(min_scale)
+    let min_scale = min_x_scale.min(min_y_scale).min(min);
+    scale.min(max).max(min_scale)
 }
 
 fn constrain_offset(img_size: Size, widget_size: Size, scale: f64, offset: Vec2) -> Vec2 {
@@ -517,25 +2197,122 @@ fn constrain_offset(img_size: Size, widget_size: Size, scale: f64, offset: Vec2)
     Vec2::new(tx, ty)
 }
 
+/// How much `trans` scales by.
+fn scale_of(trans: Affine) -> f64 {
+    trans.determinant().abs().sqrt()
+}
+
+/// Just the rotation/flip part of `trans`.
+fn orientation(trans: Affine) -> Affine {
+    let [a, b, c, d, _, _] = trans.as_coeffs();
+    let scale = scale_of(trans);
+    Affine::new([a / scale, b / scale, c / scale, d / scale, 0., 0.])
+}
+
+/// The EXIF orientation that turns and flips an image as `trans` does, so the view can be saved
+/// the way it is shown.
+pub fn exif_orientation(trans: Affine) -> u16 {
+    let [a, b, c, d, _, _] = orientation(trans).as_coeffs();
+    let r = |v: f64| v.round() as i8;
+    match (r(a), r(b), r(c), r(d)) {
+        (-1, 0, 0, 1) => 2,
+        (-1, 0, 0, -1) => 3,
+        (1, 0, 0, -1) => 4,
+        (0, 1, 1, 0) => 5,
+        (0, 1, -1, 0) => 6,
+        (0, -1, -1, 0) => 7,
+        (0, -1, 1, 0) => 8,
+        _ => 1,
+    }
+}
+
+/// The size of the bounding box of the image, turned as `trans` turns it, at 100%.
+fn bbox_size(img_size: Size, trans: Affine) -> Size {
+    orientation(trans)
+        .transform_rect_bbox(img_size.to_rect())
+        .size()
+}
+
+/// The transform that stretches `from` onto `to`.
+fn rect_to_rect(from: Rect, to: Rect) -> Affine {
+    Affine::translate(to.origin().to_vec2())
+        * Affine::scale_non_uniform(to.width() / from.width(), to.height() / from.height())
+        * Affine::translate(-from.origin().to_vec2())
+}
+
+/// If `trans` scales by a whole number and turns by quarter turns, round its offset to whole
+/// device pixels.
+fn snap_to_device_pixels(trans: Affine, dpi: Scale) -> Affine {
+    let [a, b, c, d, x, y] = trans.as_coeffs();
+    if [a, b, c, d].iter().any(|v| (v - v.round()).abs() > 1e-6) {
+        return trans;
+    }
+    Affine::new([
+        a,
+        b,
+        c,
+        d,
+        (x * dpi.x()).round() / dpi.x(),
+        (y * dpi.y()).round() / dpi.y(),
+    ])
+}
+
+/// Describe the transformed image as a scroll viewport, for drawing scrollbars.
+fn viewport(img_size: Size, widget_size: Size, trans: Affine) -> Viewport {
+    let bbox = trans.transform_rect_bbox(img_size.to_rect());
+    Viewport {
+        content_size: bbox.size(),
+        view_origin: (-bbox.origin().to_vec2()).to_point(),
+        view_size: widget_size,
+    }
+}
+
+fn same_transform(t1: &Affine, t2: &Affine) -> bool {
+    trans_approx_eq(*t1, *t2)
+}
+
 /// Compare two transforms to see if they are approximately equal.
-fn trans_approx_eq(t1: TranslateScale, t2: TranslateScale) -> bool {
+fn trans_approx_eq(t1: Affine, t2: Affine) -> bool {
     const EPSILON: f64 = 1e-6;
-    let (t1, s1) = t1.as_tuple();
-    let (t2, s2) = t2.as_tuple();
-    (t2 - t1).hypot2() < EPSILON.powi(2) && (s1 - s2).abs() < EPSILON
+    let (t1, t2) = (t1.as_coeffs(), t2.as_coeffs());
+    t1.iter().zip(&t2).all(|(v1, v2)| (v1 - v2).abs() < EPSILON)
+}
+
+/// Tells the delegate when the text box it wraps gains or loses the keyboard, by sending
+/// `EDITING_TEXT`.
+pub struct ReportFocus;
+
+impl<T, W: Widget<T>> Controller<T, W> for ReportFocus {
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &T,
+        env: &Env,
+    ) {
+        if let LifeCycle::FocusChanged(focused) = event {
+            ctx.submit_command(EDITING_TEXT.with(*focused));
+        }
+        child.lifecycle(ctx, event, data, env)
+    }
 }
 
 /// Copied from druid-material-icons because versions.
 #[derive(Debug, Clone)]
 pub struct Icon {
     shapes: IconPaths,
-    color: Color,
+    color: KeyOrValue<Color>,
 }
 
 impl Icon {
+    /// An icon filled with `color`, which can be a key, so the icon follows the theme.
     #[inline]
-    pub fn new(shapes: IconPaths, color: Color) -> Self {
-        Self { shapes, color }
+    pub fn new(shapes: IconPaths, color: impl Into<KeyOrValue<Color>>) -> Self {
+        Self {
+            shapes,
+            color: color.into(),
+        }
     }
 }
 
@@ -552,7 +2329,8 @@ impl<T: Data> Widget<T> for Icon {
     fn layout(&mut self, _ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &T, _env: &Env) -> Size {
         bc.constrain_aspect_ratio(self.shapes.size.aspect_ratio(), self.shapes.size.width)
     }
-    fn paint(&mut self, ctx: &mut PaintCtx, _data: &T, _env: &Env) {
+    fn paint(&mut self, ctx: &mut PaintCtx, _data: &T, env: &Env) {
+        let color = self.color.resolve(env);
         let Size { width, height } = ctx.size();
         let Size {
             width: icon_width,
@@ -563,7 +2341,86 @@ impl<T: Data> Widget<T> for Icon {
             height * icon_height.recip(),
         ));
         for shape in self.shapes.paths {
-            ctx.fill(shape, &self.color);
+            ctx.fill(shape, &color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: Size = Size::new(300., 200.);
+    const SCALE_RANGE: (f64, f64) = (MIN_SCALE, MAX_SCALE);
+
+    /// Where the image ends up on screen under `trans`.
+    fn on_screen(img_size: Size, trans: Affine) -> Rect {
+        trans.transform_rect_bbox(img_size.to_rect())
+    }
+
+    fn approx_eq(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    /// An image bigger than the view, scaled, turned by `angle` and dragged well off to the top
+    /// left and then to the bottom right, still covers the view.
+    fn assert_covers(angle: f64) {
+        let img_size = Size::new(400., 250.);
+        for &offset in &[(-5000., -5000.), (5000., 5000.)] {
+            let trans = Affine::translate(offset) * Affine::scale(2.) * Affine::rotate(angle);
+            let trans = constrain_transform(img_size, VIEW, trans, SCALE_RANGE);
+            let rect = on_screen(img_size, trans);
+            assert!(
+                rect.x0 <= 1e-6 && rect.y0 <= 1e-6,
+                "{:?} at {}",
+                rect,
+                angle
+            );
+            assert!(
+                rect.x1 >= VIEW.width - 1e-6 && rect.y1 >= VIEW.height - 1e-6,
+                "{:?} at {}",
+                rect,
+                angle
+            );
+            assert!(approx_eq(scale_of(trans), 2.));
         }
     }
+
+    /// An image smaller than the view, turned by `angle` and dragged anywhere, is put in the
+    /// middle.
+    fn assert_centred(angle: f64) {
+        let img_size = Size::new(80., 40.);
+        for &offset in &[(-500., 30.), (0., 0.), (250., 190.)] {
+            let trans = Affine::translate(offset) * Affine::scale(1.5) * Affine::rotate(angle);
+            let trans = constrain_transform(img_size, VIEW, trans, SCALE_RANGE);
+            let centre = on_screen(img_size, trans).center();
+            assert!(
+                approx_eq(centre.x, VIEW.width / 2.) && approx_eq(centre.y, VIEW.height / 2.),
+                "{:?} at {}",
+                centre,
+                angle
+            );
+            assert!(approx_eq(scale_of(trans), 1.5));
+        }
+    }
+
+    #[test]
+    fn quarter_turn_covers_view() {
+        assert_covers(std::f64::consts::FRAC_PI_2);
+    }
+
+    #[test]
+    fn arbitrary_turn_covers_view() {
+        assert_covers(30f64.to_radians());
+    }
+
+    #[test]
+    fn quarter_turn_is_centred() {
+        assert_centred(std::f64::consts::FRAC_PI_2);
+    }
+
+    #[test]
+    fn arbitrary_turn_is_centred() {
+        assert_centred(30f64.to_radians());
+    }
 }